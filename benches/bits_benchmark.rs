@@ -1,7 +1,9 @@
 use std::io;
 
 use criterion::{black_box, criterion_group, criterion_main, Criterion};
-use gfwx::bits::{BitsIOReader, BitsIOWriter, BitsReader, BitsWriter};
+use gfwx::bits::{
+    BitsIOReader, BitsIOWriter, BitsReader, BitsWriter, SliceBitsReader, SliceBitsWriter,
+};
 
 struct BenchWriter {
     buffer: [u8; 4],
@@ -85,11 +87,53 @@ fn bits_zeros_benchmark(c: &mut Criterion) {
     });
 }
 
+fn slice_bits_writer_benchmark(c: &mut Criterion) {
+    c.bench_function("slice_bits_writer", |b| {
+        let mut buff = [0u8; 8];
+        b.iter(|| {
+            let mut stream = SliceBitsWriter::new(&mut buff);
+            for _ in 0..12 {
+                stream.put_bits(15, 4).unwrap();
+            }
+            stream.flush_write_word().unwrap();
+        })
+    });
+}
+
+fn slice_bits_reader_benchmark(c: &mut Criterion) {
+    c.bench_function("slice_bits_reader", |b| {
+        let source = [42u8; 8];
+        b.iter(|| {
+            let mut stream = SliceBitsReader::new(&source);
+            for _ in 0..15 {
+                black_box(stream.get_bits(4).unwrap());
+            }
+            stream.flush_read_word();
+        })
+    });
+}
+
+fn slice_bits_zeros_benchmark(c: &mut Criterion) {
+    c.bench_function("slice_bits_zeros", |b| {
+        let source = [42u8; 8];
+        b.iter(|| {
+            let mut stream = SliceBitsReader::new(&source);
+            for _ in 0..15 {
+                black_box(stream.get_zeros(4).unwrap());
+            }
+            stream.flush_read_word();
+        })
+    });
+}
+
 criterion_group!(
     benches,
     bits_writer_benchmark,
     bits_reader_benchmark,
-    bits_zeros_benchmark
+    bits_zeros_benchmark,
+    slice_bits_writer_benchmark,
+    slice_bits_reader_benchmark,
+    slice_bits_zeros_benchmark
 );
 
 criterion_main!(benches);
@@ -22,6 +22,8 @@ macro_rules! compress_benchmark {
                             encoder: $mode,
                             intent: gfwx::Intent::RGB,
                             metadata_size: 0,
+                            quantization: gfwx::Quantization::Scalar,
+                            weighting: gfwx::Weighting::Flat,
                         };
                         let header = builder.build().unwrap();
 
@@ -0,0 +1,77 @@
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+#include "gfwx.h"
+
+#define CHECK(expr)                                                                   \
+    do {                                                                              \
+        GfwxResult result_ = (expr);                                                  \
+        if (result_ != GFWX_RESULT_OK) {                                              \
+            fprintf(stderr, "%s:%d: %s failed: %s\n", __FILE__, __LINE__, #expr,      \
+                    gfwx_result_message(result_));                                    \
+            return 1;                                                                 \
+        }                                                                             \
+    } while (0)
+
+int main(void) {
+    const uint32_t width = 64, height = 48, channels = 3;
+    const size_t image_size = width * height * channels;
+
+    uint8_t *image = malloc(image_size);
+    for (size_t i = 0; i < image_size; ++i) {
+        image[i] = (uint8_t)((i * 7) % 256);
+    }
+
+    GfwxHeader header = {0};
+    header.width = width;
+    header.height = height;
+    header.layers = 1;
+    header.channels = channels;
+    header.quality = GFWX_QUALITY_MAX;
+    header.chroma_scale = 1;
+    header.block_size = GFWX_BLOCK_DEFAULT;
+    header.filter = GFWX_FILTER_LINEAR;
+    header.quantization = GFWX_QUANTIZATION_SCALAR;
+    header.weighting = GFWX_WEIGHTING_FLAT;
+    header.encoder = GFWX_ENCODER_CONTEXTUAL;
+    header.intent = GFWX_INTENT_RGB;
+    CHECK(gfwx_header_build(&header));
+
+    size_t compressed_size = 0;
+    uint8_t *compressed = malloc(image_size * 2);
+    CHECK(gfwx_compress(image, image_size, &header, GFWX_COLOR_TRANSFORM_RGB_TO_YUV, compressed,
+                        image_size * 2, &compressed_size));
+
+    GfwxHeader parsed = {0};
+    size_t header_size = 0;
+    CHECK(gfwx_header_decode(compressed, compressed_size, &parsed, &header_size));
+    if (header_size != GFWX_HEADER_SIZE || parsed.width != width || parsed.height != height) {
+        fprintf(stderr, "unexpected header\n");
+        return 1;
+    }
+
+    size_t decompressed_size = gfwx_decompress_buffer_size(&parsed, 0);
+    uint8_t *decompressed = malloc(decompressed_size);
+    size_t next_point_of_interest = 1;
+    CHECK(gfwx_decompress(compressed, compressed_size, 0, false, decompressed, decompressed_size,
+                          &parsed, &next_point_of_interest));
+    if (next_point_of_interest != 0 || decompressed_size != image_size ||
+        memcmp(image, decompressed, image_size) != 0) {
+        fprintf(stderr, "lossless round trip mismatch\n");
+        return 1;
+    }
+
+    if (gfwx_decompress(image, 16, 0, false, decompressed, decompressed_size, &parsed,
+                        &next_point_of_interest) != GFWX_RESULT_ERROR_WRONG_MAGIC) {
+        fprintf(stderr, "expected GFWX_RESULT_ERROR_WRONG_MAGIC\n");
+        return 1;
+    }
+
+    free(decompressed);
+    free(compressed);
+    free(image);
+
+    printf("capi test passed (%zu -> %zu bytes)\n", image_size, compressed_size);
+    return 0;
+}
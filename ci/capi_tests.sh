@@ -0,0 +1,19 @@
+#!/usr/bin/env bash
+# Builds the static library with the `capi` feature and runs the C test against it.
+set -e
+
+SCRIPT_DIR=$(dirname $(realpath -s "$0"))
+ROOT_DIR=${SCRIPT_DIR}/..
+BUILD_DIR=$(mktemp -d)
+
+cargo rustc --release --lib --features capi --crate-type cdylib,staticlib --manifest-path ${ROOT_DIR}/Cargo.toml
+
+cc -std=c99 -Wall -Wextra -Werror \
+    -I ${ROOT_DIR}/include \
+    ${SCRIPT_DIR}/capi_test.c \
+    ${ROOT_DIR}/target/release/libgfwx.a \
+    -lpthread -ldl -lm \
+    -o ${BUILD_DIR}/capi_test
+
+${BUILD_DIR}/capi_test
+rm -r ${BUILD_DIR}
@@ -13,6 +13,8 @@ main() {
     if [ $TARGET = $CODECOV_TARGET ]; then
         cargo test
         cargo test --release
+        cargo test --features async
+        cargo test --no-default-features
     else
         cross test --target $TARGET
         cross test --target $TARGET --release
@@ -21,6 +23,10 @@ main() {
     cross bench -- --test
 
     bash ci/func_tests.sh ci/test_images
+
+    if [ $TARGET = x86_64-unknown-linux-gnu ]; then
+        bash ci/capi_tests.sh
+    fi
 }
 
 # we don't run the "test phase" when doing deploys
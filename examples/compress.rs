@@ -19,8 +19,14 @@ fn main() -> Result<(), Box<dyn Error>> {
         "fast" => gfwx::Encoder::Fast,
         "turbo" => gfwx::Encoder::Turbo,
         "contextual" => gfwx::Encoder::Contextual,
+        "screen" => gfwx::Encoder::Screen,
         _ => panic!("clap betrayed us again"),
     };
+    let weighting = match matches.value_of("weighting").unwrap() {
+        "flat" => gfwx::Weighting::Flat,
+        "csf" => gfwx::Weighting::Csf,
+        _ => panic!("clap betrayed us yet again"),
+    };
 
     let file_path = Path::new(&input_file);
     let image = image::open(&file_path)?;
@@ -39,18 +45,68 @@ fn main() -> Result<(), Box<dyn Error>> {
         encoder,
         intent,
         metadata_size: 0,
+        quantization: gfwx::Quantization::Scalar,
+        weighting,
     };
     let header = builder.build().unwrap();
 
+    let color_transform = match (
+        matches.value_of("transform"),
+        matches.value_of("transform-file"),
+    ) {
+        (Some("auto"), _) => gfwx::ColorTransformProgram::auto(&image, &header)?,
+        (Some(program), _) => program.parse()?,
+        (None, Some(path)) => fs::read_to_string(path)?.parse()?,
+        (None, None) => gfwx::ColorTransformProgram::new(),
+    };
+
     let mut compressed = vec![0; 2 * image.len()];
 
+    let mut compressor = gfwx::Compressor::new(&header, &color_transform)?;
+    if let Some(alpha_quality) = matches.value_of("alpha-quality") {
+        let transparent = match matches.value_of("transparent").unwrap() {
+            "keep" => gfwx::TransparentColor::Keep,
+            "zero" => gfwx::TransparentColor::Zero,
+            "smooth" => gfwx::TransparentColor::Smooth,
+            _ => panic!("clap betrayed us once more"),
+        };
+        compressor = compressor.with_alpha(gfwx::AlphaOptions {
+            quality: alpha_quality.parse().unwrap(),
+            transparent,
+        })?;
+    }
+    if let Some(qualities) = matches.value_of("channel-qualities") {
+        let qualities = qualities
+            .split(',')
+            .map(|quality| quality.trim().parse())
+            .collect::<Result<Vec<u16>, _>>()?;
+        compressor = compressor.with_channel_qualities(&qualities)?;
+    }
+    if let Some(regions) = matches.values_of("roi") {
+        let mut map = gfwx::QualityMap::new(
+            width as usize,
+            height as usize,
+            4,
+            gfwx::QUALITY_SCALE_ONE,
+        );
+        for region in regions {
+            set_roi(&mut map, region)?;
+        }
+        compressor = compressor.with_quality_map(Some(map))?;
+    }
+    if matches.is_present("dead-zone") || matches.is_present("rdo-lambda") {
+        let mut dead_zone = gfwx::DeadZone::default();
+        if let Some(threshold) = matches.value_of("dead-zone") {
+            dead_zone.threshold = threshold.parse()?;
+        }
+        if let Some(lambda) = matches.value_of("rdo-lambda") {
+            dead_zone.lambda = lambda.parse()?;
+        }
+        compressor = compressor.with_dead_zone(dead_zone)?;
+    }
+
     let compress_start = Instant::now();
-    let gfwx_size = gfwx::compress_simple(
-        &image,
-        &header,
-        &gfwx::ColorTransformProgram::new(),
-        &mut compressed,
-    )?;
+    let gfwx_size = compressor.compress(&image, &mut compressed)?;
     let compress_end = Instant::now();
 
     println!(
@@ -122,6 +178,14 @@ fn get_matches() -> clap::ArgMatches<'static> {
                 .default_value("linear")
                 .possible_values(&["linear", "cubic"]),
         )
+        .arg(
+            clap::Arg::with_name("weighting")
+                .help("Set the quality weighting of the wavelet levels, csf lowers the quality of fine details the eye is less sensitive to")
+                .long("weighting")
+                .takes_value(true)
+                .default_value("flat")
+                .possible_values(&["flat", "csf"]),
+        )
         .arg(
             clap::Arg::with_name("encoder")
                 .help("Set the encoder mode")
@@ -129,7 +193,7 @@ fn get_matches() -> clap::ArgMatches<'static> {
                 .long("encoder")
                 .takes_value(true)
                 .default_value("turbo")
-                .possible_values(&["turbo", "fast", "contextual"]),
+                .possible_values(&["turbo", "fast", "contextual", "screen"]),
         )
         .arg(
             clap::Arg::with_name("intent")
@@ -139,9 +203,100 @@ fn get_matches() -> clap::ArgMatches<'static> {
                 .takes_value(true)
                 .possible_values(&["rgb", "rgba", "bgr", "bgra"]),
         )
+        .arg(
+            clap::Arg::with_name("transform")
+                .help("Set the color transform program, e.g. \"c0 -= c1 [chroma]; c2 -= c1 [chroma]; c1 += (c0 + c2) / 4\", or \"auto\" to choose the one giving the smallest output")
+                .short("t")
+                .long("transform")
+                .takes_value(true)
+                .conflicts_with("transform-file")
+                .validator(|v| {
+                    if v == "auto" {
+                        return Ok(());
+                    }
+                    v.parse::<gfwx::ColorTransformProgram>()
+                        .map(|_| ())
+                        .map_err(|e| e.to_string())
+                }),
+        )
+        .arg(
+            clap::Arg::with_name("transform-file")
+                .help("Read the color transform program from a file")
+                .long("transform-file")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("alpha-quality")
+                .help("Sets the quality of the alpha channel of rgba and bgra images, 1024 is lossless")
+                .long("alpha-quality")
+                .takes_value(true)
+                .validator(|v| {
+                    let v = v.parse::<u16>().map_err(|e| e.to_string())?;
+                    if v > 0 && v <= 1024 {
+                        Ok(())
+                    } else {
+                        Err("Alpha quality must be in range (1..=1024)".to_string())
+                    }
+                }),
+        )
+        .arg(
+            clap::Arg::with_name("transparent")
+                .help("Set what happens to the color of fully transparent pixels, used with --alpha-quality")
+                .long("transparent")
+                .takes_value(true)
+                .default_value("keep")
+                .possible_values(&["keep", "zero", "smooth"]),
+        )
+        .arg(
+            clap::Arg::with_name("channel-qualities")
+                .help("Sets the quality of every channel, e.g. \"1024,512,512\", 0 keeps the quality of the channel")
+                .long("channel-qualities")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("roi")
+                .help("Scales the quality of a region, e.g. \"0,0,64,32:4\" for x,y,width,height:factor, can be repeated")
+                .long("roi")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            clap::Arg::with_name("dead-zone")
+                .help("Uses dead-zone quantization, coefficients below threshold/256 quantization steps become zero")
+                .long("dead-zone")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("rdo-lambda")
+                .help("Uses dead-zone quantization, zeroing isolated coefficients that add less than lambda/256 squared steps of distortion per saved bit, 0 turns it off")
+                .long("rdo-lambda")
+                .takes_value(true),
+        )
         .get_matches()
 }
 
+/// Sets the scale of a region given as "x,y,width,height:factor".
+fn set_roi(map: &mut gfwx::QualityMap, region: &str) -> Result<(), Box<dyn Error>> {
+    let mut parts = region.split(':');
+    let rect = parts
+        .next()
+        .unwrap()
+        .split(',')
+        .map(|v| v.trim().parse())
+        .collect::<Result<Vec<usize>, _>>()?;
+    let factor: f32 = parts.next().ok_or("Region must end with :factor")?.parse()?;
+    if rect.len() != 4 {
+        return Err("Region must be x,y,width,height:factor".into());
+    }
+    let scale = (factor * f32::from(gfwx::QUALITY_SCALE_ONE))
+        .round()
+        .max(1.0)
+        .min(f32::from(gfwx::QUALITY_SCALE_MAX)) as u16;
+    map.set_rect(rect[0], rect[1], rect[2], rect[3], scale);
+    Ok(())
+}
+
 fn into_raw_image(
     image: image::DynamicImage,
     user_intent: Option<&str>,
@@ -16,10 +16,21 @@ fn main() -> Result<(), Box<dyn Error>> {
     let mut compressed = Vec::new();
     input.read_to_end(&mut compressed)?;
 
+    if matches.is_present("print-transform") {
+        let mut program = compressed
+            .get(header.metadata_size as usize..)
+            .ok_or("Input file is truncated in the metadata area")?;
+        let mut is_chroma = vec![false; header.layers as usize * header.channels as usize];
+        let color_transform = gfwx::ColorTransformProgram::decode(&mut program, &mut is_chroma)?;
+        println!("Color transform: {}", color_transform);
+    }
+
     let mut decompressed = vec![0; header.get_decompress_buffer_size(downsampling)];
 
     let decompress_start = Instant::now();
-    gfwx::decompress_simple(&compressed, &header, downsampling, false, &mut decompressed)?;
+    gfwx::Decompressor::new()
+        .with_premultiplied_alpha(matches.is_present("premultiply"))
+        .decompress(&compressed, &header, downsampling, false, &mut decompressed)?;
     let decompress_end = Instant::now();
 
     println!(
@@ -68,6 +79,16 @@ fn get_matches() -> clap::ArgMatches<'static> {
                     Ok(())
                 }),
         )
+        .arg(
+            clap::Arg::with_name("print-transform")
+                .help("Prints the color transform program of the stream")
+                .long("print-transform"),
+        )
+        .arg(
+            clap::Arg::with_name("premultiply")
+                .help("Multiplies the color of rgba and bgra images by alpha")
+                .long("premultiply"),
+        )
         .get_matches()
 }
 
@@ -0,0 +1,8 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let mut data = data;
+    let mut is_chroma = [false; 4];
+    let _ = gfwx::ColorTransformProgram::decode(&mut data, &mut is_chroma);
+});
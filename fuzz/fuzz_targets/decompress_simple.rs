@@ -0,0 +1,22 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+// Keeps the fuzzer from spending its time on allocating huge output buffers.
+const MAX_IMAGE_SIZE: usize = 1 << 20;
+
+fuzz_target!(|data: &[u8]| {
+    let mut data = data;
+    let header = match gfwx::Header::decode(&mut data) {
+        Ok(header) => header,
+        Err(_) => return,
+    };
+    if header.get_image_size() > MAX_IMAGE_SIZE {
+        return;
+    }
+
+    for downsampling in 0..3 {
+        let mut buffer = vec![0; header.get_decompress_buffer_size(downsampling)];
+        let _ = gfwx::decompress_simple(data, &header, downsampling, false, &mut buffer);
+        let _ = gfwx::decompress_simple(data, &header, downsampling, true, &mut buffer);
+    }
+});
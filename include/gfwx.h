@@ -0,0 +1,220 @@
+#ifndef GFWX_H
+#define GFWX_H
+
+/* Generated with cbindgen from src/capi/mod.rs, do not edit by hand. */
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+#define GFWX_FILTER_LINEAR 0
+
+#define GFWX_FILTER_CUBIC 1
+
+#define GFWX_QUANTIZATION_SCALAR 0
+
+#define GFWX_QUANTIZATION_DEAD_ZONE 1
+
+#define GFWX_WEIGHTING_FLAT 0
+
+#define GFWX_WEIGHTING_CSF 1
+
+#define GFWX_ENCODER_TURBO 0
+
+#define GFWX_ENCODER_FAST 1
+
+#define GFWX_ENCODER_CONTEXTUAL 2
+
+#define GFWX_ENCODER_SCREEN 3
+
+#define GFWX_INTENT_GENERIC 0
+
+#define GFWX_INTENT_RGB 7
+
+#define GFWX_INTENT_RGBA 8
+
+#define GFWX_INTENT_BGR 10
+
+#define GFWX_INTENT_BGRA 11
+
+#define GFWX_INTENT_YUV444 12
+
+#define GFWX_QUALITY_MAX 1024
+
+#define GFWX_BLOCK_DEFAULT 7
+
+#define GFWX_BLOCK_MAX 30
+
+/**
+ * Size of the encoded header in bytes. The metadata area starts right after it.
+ */
+#define GFWX_HEADER_SIZE 32
+
+/**
+ * Color transform presets, see the `ColorTransformProgram` constructors.
+ */
+typedef enum {
+  GFWX_COLOR_TRANSFORM_NONE = 0,
+  GFWX_COLOR_TRANSFORM_YUV444_TO_YUV444 = 1,
+  GFWX_COLOR_TRANSFORM_RGB_TO_YUV = 2,
+  GFWX_COLOR_TRANSFORM_RGB_TO_A710 = 3,
+  GFWX_COLOR_TRANSFORM_BGR_TO_A710 = 4,
+  GFWX_COLOR_TRANSFORM_RGB_TO_YCOCG_R = 5,
+  GFWX_COLOR_TRANSFORM_BGR_TO_YCOCG_R = 6,
+  GFWX_COLOR_TRANSFORM_RGBA_TO_YCOCG_R = 7,
+  GFWX_COLOR_TRANSFORM_BGRA_TO_YCOCG_R = 8,
+} GfwxColorTransform;
+
+/**
+ * Result codes returned by the `gfwx_*` functions, mapped from `Error` and `HeaderErr`.
+ */
+typedef enum {
+  GFWX_RESULT_OK = 0,
+  GFWX_RESULT_ERROR_IO = -1,
+  GFWX_RESULT_ERROR_OVERFLOW = -2,
+  GFWX_RESULT_ERROR_MALFORMED = -3,
+  GFWX_RESULT_ERROR_BUFFER_SIZE = -4,
+  GFWX_RESULT_ERROR_UNDERFLOW = -5,
+  GFWX_RESULT_ERROR_TYPE_MISMATCH = -6,
+  GFWX_RESULT_ERROR_WRONG_MAGIC = -7,
+  GFWX_RESULT_ERROR_WRONG_VALUE = -8,
+  GFWX_RESULT_ERROR_NULL_POINTER = -9,
+  GFWX_RESULT_ERROR_PANIC = -10,
+  GFWX_RESULT_ERROR_LIMIT_EXCEEDED = -11,
+  GFWX_RESULT_ERROR_CHECKSUM = -12,
+  GFWX_RESULT_ERROR_LOSSY = -13,
+} GfwxResult;
+
+/**
+ * Plain-data mirror of `Header`. `filter`, `quantization`, `encoder`, `intent` and `weighting`
+ * hold the values stored in the stream. `version`, `bit_depth` and `is_signed` may be left
+ * zeroed when building a header.
+ */
+typedef struct {
+  uint32_t version;
+  uint32_t width;
+  uint32_t height;
+  uint16_t layers;
+  uint16_t channels;
+  uint8_t bit_depth;
+  uint8_t is_signed;
+  uint16_t quality;
+  uint8_t chroma_scale;
+  uint8_t block_size;
+  uint8_t filter;
+  uint8_t quantization;
+  uint8_t encoder;
+  uint8_t intent;
+  uint32_t metadata_size;
+  uint8_t weighting;
+  /**
+   * Non-zero if the metadata area ends with the coding extension.
+   */
+  uint8_t has_extension;
+} GfwxHeader;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * Validates `header` and fills in the fields the codec derives itself (`version`,
+ * `bit_depth`, `is_signed`).
+ *
+ * # Safety
+ *
+ * `header` must be null or point to a valid `GfwxHeader`.
+ */
+GfwxResult gfwx_header_build(GfwxHeader *header);
+
+/**
+ * Writes the 32-byte stream header into `buffer` and stores the written size in `written`.
+ *
+ * # Safety
+ *
+ * `header` and `written` must be null or valid pointers, `buffer` must be null or point to
+ * `buffer_size` writable bytes.
+ */
+GfwxResult gfwx_header_encode(const GfwxHeader *header,
+                              uint8_t *buffer,
+                              size_t buffer_size,
+                              size_t *written);
+
+/**
+ * Parses the stream header from `data` and stores the number of consumed bytes in `read`.
+ *
+ * # Safety
+ *
+ * `header` and `read` must be null or valid pointers, `data` must be null or point to `size`
+ * readable bytes.
+ */
+GfwxResult gfwx_header_decode(const uint8_t *data, size_t size, GfwxHeader *header, size_t *read);
+
+/**
+ * Returns the size of the buffer `gfwx_decompress` needs for the given downsampling, or 0 if
+ * `header` is null or invalid.
+ *
+ * # Safety
+ *
+ * `header` must be null or point to a valid `GfwxHeader`.
+ */
+size_t gfwx_decompress_buffer_size(const GfwxHeader *header, size_t downsampling);
+
+/**
+ * Compresses interleaved 8-bit `image` into a complete GFWX stream (header, color transform
+ * program and payload) and stores the stream size in `compressed_size`.
+ *
+ * # Safety
+ *
+ * `header` and `compressed_size` must be null or valid pointers, `image` must be null or
+ * point to `image_size` readable bytes and `buffer` must be null or point to `buffer_size`
+ * writable bytes.
+ */
+GfwxResult gfwx_compress(const uint8_t *image,
+                         size_t image_size,
+                         const GfwxHeader *header,
+                         uint32_t color_transform,
+                         uint8_t *buffer,
+                         size_t buffer_size,
+                         size_t *compressed_size);
+
+/**
+ * Decompresses a complete GFWX stream into interleaved 8-bit `buffer`. The stream header is
+ * stored in `header` and the next point of interest (0 if the stream was complete) in
+ * `next_point_of_interest`. Use `gfwx_decompress_buffer_size` to size `buffer`.
+ *
+ * # Safety
+ *
+ * `header` and `next_point_of_interest` must be null or valid pointers, `data` must be null or
+ * point to `size` readable bytes and `buffer` must be null or point to `buffer_size` writable
+ * bytes.
+ */
+GfwxResult gfwx_decompress(const uint8_t *data,
+                           size_t size,
+                           size_t downsampling,
+                           bool test,
+                           uint8_t *buffer,
+                           size_t buffer_size,
+                           GfwxHeader *header,
+                           size_t *next_point_of_interest);
+
+/**
+ * Checks a whole stream, starting with the header, against the checksums stored in its
+ * metadata area.
+ *
+ * # Safety
+ *
+ * `data` must be null or point to `size` readable bytes.
+ */
+GfwxResult gfwx_verify(const uint8_t *data, size_t size);
+
+/**
+ * Returns a static, NUL-terminated description of `result`, one of the `GFWX_RESULT_*` codes.
+ */
+const char *gfwx_result_message(int result);
+
+#ifdef __cplusplus
+} // extern "C"
+#endif // __cplusplus
+
+#endif /* GFWX_H */
@@ -0,0 +1,159 @@
+use crate::header::Header;
+
+#[cfg(test)]
+mod test;
+
+/// What happens to the color of fully transparent pixels before lifting. Their color is
+/// invisible after compositing, so replacing it only saves bits.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TransparentColor {
+    /// Color is coded as is.
+    Keep,
+    /// Color channels are set to zero.
+    Zero,
+    /// Color is interpolated from the opaque neighbours along the rows, fully transparent rows
+    /// repeat the nearest row with opaque pixels.
+    Smooth,
+}
+
+/// Alpha handling of `Compressor::with_alpha` for RGBA and BGRA images.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AlphaOptions {
+    /// Quality of the alpha channel, `QUALITY_MAX` codes it losslessly while the color channels
+    /// keep `header.quality`. It is stored in the coding extension of the stream.
+    pub quality: u16,
+    pub transparent: TransparentColor,
+}
+
+/// Replaces the color of fully transparent pixels of the transformed channels in `aux_data`.
+/// The alpha channel must not be a destination of the color transform, so that it is zero
+/// exactly for the transparent pixels.
+pub(crate) fn fill_transparent(
+    aux_data: &mut [i16],
+    header: &Header,
+    transparent: TransparentColor,
+) {
+    let alpha = match header.get_alpha_channel() {
+        Some(alpha) if transparent != TransparentColor::Keep => alpha,
+        _ => return,
+    };
+    let width = header.width as usize;
+    let channels = usize::from(header.channels);
+    let channel_size = header.get_channel_size();
+    if channel_size == 0 {
+        return;
+    }
+
+    for layer in aux_data.chunks_exact_mut(channel_size * channels) {
+        let (before, rest) = layer.split_at_mut(alpha * channel_size);
+        let (alpha_plane, after) = rest.split_at_mut(channel_size);
+        let alpha_plane = &*alpha_plane;
+        for plane in before
+            .chunks_exact_mut(channel_size)
+            .chain(after.chunks_exact_mut(channel_size))
+        {
+            match transparent {
+                TransparentColor::Keep => (),
+                TransparentColor::Zero => {
+                    for (value, &alpha) in plane.iter_mut().zip(alpha_plane) {
+                        if alpha == 0 {
+                            *value = 0;
+                        }
+                    }
+                }
+                TransparentColor::Smooth => smooth(plane, alpha_plane, width),
+            }
+        }
+    }
+}
+
+fn smooth(plane: &mut [i16], alpha_plane: &[i16], width: usize) {
+    let mut last_opaque_row = None;
+    let mut first_opaque_row = None;
+    for y in 0..plane.len() / width {
+        let row = y * width..(y + 1) * width;
+        if smooth_row(&mut plane[row.clone()], &alpha_plane[row]) {
+            first_opaque_row.get_or_insert(y);
+            last_opaque_row = Some(y);
+        } else if let Some(source) = last_opaque_row {
+            plane.copy_within(source * width..(source + 1) * width, y * width);
+        }
+    }
+
+    // rows above the first one with opaque pixels
+    match first_opaque_row {
+        Some(first) => {
+            for y in 0..first {
+                plane.copy_within(first * width..(first + 1) * width, y * width);
+            }
+        }
+        None => {
+            for value in plane.iter_mut() {
+                *value = 0;
+            }
+        }
+    }
+}
+
+/// Interpolates the transparent runs of `row` between their opaque ends, runs at the ends of
+/// the row repeat the outermost opaque value. Returns `false` if the whole row is transparent.
+fn smooth_row(row: &mut [i16], alpha_row: &[i16]) -> bool {
+    let mut previous: Option<usize> = None;
+    for x in 0..=row.len() {
+        if x < row.len() && alpha_row[x] == 0 {
+            continue;
+        }
+        let start = previous.map_or(0, |p| p + 1);
+        match (previous, x < row.len()) {
+            (Some(p), true) => {
+                let (from, to) = (i32::from(row[p]), i32::from(row[x]));
+                let length = (x - p) as i32;
+                for (i, value) in row[start..x].iter_mut().enumerate() {
+                    let offset = i as i32 + 1;
+                    *value = (from + (to - from) * offset / length) as i16;
+                }
+            }
+            (Some(p), false) => {
+                let value = row[p];
+                row[start..].iter_mut().for_each(|v| *v = value);
+            }
+            (None, true) => {
+                let value = row[x];
+                row[..x].iter_mut().for_each(|v| *v = value);
+            }
+            (None, false) => return false,
+        }
+        previous = Some(x);
+    }
+    true
+}
+
+/// Multiplies the color channels of the detransformed `aux_data`, made of channels of
+/// `channel_size` samples, by alpha, for output clamped to unsigned 8 bits.
+pub(crate) fn premultiply(aux_data: &mut [i16], header: &Header, channel_size: usize) {
+    let alpha = match header.get_alpha_channel() {
+        Some(alpha) => alpha,
+        None => return,
+    };
+    let channels = usize::from(header.channels);
+    if channel_size == 0 {
+        return;
+    }
+    let boost = i32::from(header.get_boost());
+    let to_u8 = |value: i16| (i32::from(value) / boost).clamp(0, 255);
+
+    for layer in aux_data.chunks_exact_mut(channel_size * channels) {
+        let (before, rest) = layer.split_at_mut(alpha * channel_size);
+        let (alpha_plane, after) = rest.split_at_mut(channel_size);
+        let alpha_plane = &*alpha_plane;
+        for plane in before
+            .chunks_exact_mut(channel_size)
+            .chain(after.chunks_exact_mut(channel_size))
+        {
+            for (value, &alpha) in plane.iter_mut().zip(alpha_plane) {
+                let premultiplied = (to_u8(*value) * to_u8(alpha) + 127) / 255;
+                *value = (premultiplied * boost) as i16;
+            }
+        }
+    }
+}
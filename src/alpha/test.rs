@@ -0,0 +1,80 @@
+use super::*;
+use crate::header::{Encoder, Filter, HeaderBuilder, Intent, QUALITY_MAX};
+
+#[test]
+fn test_smooth_row() {
+    let alpha = [0, 1, 0, 0, 1, 0];
+    let mut row = [9, 10, 9, 9, 40, 9];
+    assert!(smooth_row(&mut row, &alpha));
+    assert_eq!(row, [10, 10, 20, 30, 40, 40]);
+
+    let mut row = [9, 9];
+    assert!(!smooth_row(&mut row, &[0, 0]));
+}
+
+#[test]
+fn test_fill_transparent() {
+    let header = HeaderBuilder {
+        width: 3,
+        height: 2,
+        channels: 4,
+        quality: QUALITY_MAX,
+        chroma_scale: 1,
+        filter: Filter::Linear,
+        encoder: Encoder::Turbo,
+        intent: Intent::RGBA,
+        ..HeaderBuilder::test_default()
+    }
+    .build()
+    .unwrap();
+    #[rustfmt::skip]
+    let original = vec![
+        1, 2, 3,  4, 5, 6, // c0
+        1, 2, 3,  4, 5, 6, // c1
+        1, 2, 3,  4, 5, 6, // c2
+        0, 1, 0,  0, 0, 0, // alpha
+    ];
+
+    let mut zeroed = original.clone();
+    fill_transparent(&mut zeroed, &header, TransparentColor::Zero);
+    for plane in zeroed[..18].chunks(6) {
+        assert_eq!(plane, &[0, 2, 0, 0, 0, 0]);
+    }
+    assert_eq!(&zeroed[18..], &original[18..]);
+
+    let mut smoothed = original.clone();
+    fill_transparent(&mut smoothed, &header, TransparentColor::Smooth);
+    for plane in smoothed[..18].chunks(6) {
+        assert_eq!(plane, &[2, 2, 2, 2, 2, 2]);
+    }
+
+    let mut kept = original.clone();
+    fill_transparent(&mut kept, &header, TransparentColor::Keep);
+    assert_eq!(kept, original);
+}
+
+#[test]
+fn test_premultiply() {
+    // lossy quality boosts the samples by 8
+    let header = HeaderBuilder {
+        width: 2,
+        height: 1,
+        channels: 4,
+        chroma_scale: 1,
+        filter: Filter::Linear,
+        encoder: Encoder::Turbo,
+        intent: Intent::RGBA,
+        ..HeaderBuilder::test_default()
+    }
+    .build()
+    .unwrap();
+    let mut aux_data = vec![200, 100, 200, 100, 200, 100, 255, 51]
+        .into_iter()
+        .map(|v| v * 8)
+        .collect::<Vec<i16>>();
+    premultiply(&mut aux_data, &header, 2);
+    assert_eq!(
+        aux_data.iter().map(|v| v / 8).collect::<Vec<_>>(),
+        vec![200, 20, 200, 20, 200, 20, 255, 51]
+    );
+}
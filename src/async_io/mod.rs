@@ -0,0 +1,394 @@
+use std::{io, mem, panic};
+
+use byteorder::{ByteOrder, LittleEndian};
+
+use futures_util::stream::{self, Stream};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::task;
+
+use crate::color_transform::ColorTransformProgram;
+use crate::compress::{
+    decompress_step_level, step_levels, unlift_and_dequantize_with, Scratch, StepLevel,
+};
+use crate::context::Compressor;
+use crate::errors::Error;
+use crate::extension::{Coding, Extension};
+use crate::header::{DecodeLimits, Encoder, Header, HEADER_SIZE};
+use crate::image_view::ImageViewMut;
+use crate::io::IOError;
+use crate::palette;
+
+#[cfg(test)]
+mod test;
+
+/// Runs `op` on tokio's blocking thread pool, so that it doesn't stall the executor.
+async fn unblock<R, F>(op: F) -> Result<R, Error>
+where
+    R: Send + 'static,
+    F: FnOnce() -> R + Send + 'static,
+{
+    match task::spawn_blocking(op).await {
+        Ok(result) => Ok(result),
+        Err(err) if err.is_panic() => panic::resume_unwind(err.into_panic()),
+        Err(err) => Err(io::Error::other(err).into()),
+    }
+}
+
+fn cancelled() -> Error {
+    io::Error::other("a previous call was cancelled while running on the blocking pool").into()
+}
+
+/// Compresses images on tokio's blocking thread pool and writes them to an `AsyncWrite`, every
+/// image as a complete stream starting with the header.
+///
+/// If an `encode` future is dropped before it completes, the encoder can't be used anymore.
+#[derive(Debug)]
+pub struct AsyncEncoder<W> {
+    writer: W,
+    /// Moved to the blocking pool while an image is compressed.
+    compressor: Option<Compressor>,
+    buffer: Vec<u8>,
+}
+
+impl<W> AsyncEncoder<W>
+where
+    W: AsyncWrite + Unpin,
+{
+    pub fn new(writer: W, compressor: Compressor) -> Self {
+        AsyncEncoder {
+            writer,
+            compressor: Some(compressor),
+            buffer: vec![],
+        }
+    }
+
+    /// Compresses `image` like `Compressor::compress` and writes the stream, returning its size.
+    pub async fn encode<I>(&mut self, image: I) -> Result<usize, Error>
+    where
+        I: AsRef<[u8]> + Send + 'static,
+    {
+        let mut compressor = self.compressor.take().ok_or_else(cancelled)?;
+        let mut buffer = mem::take(&mut self.buffer);
+        let (compressor, buffer, result) = unblock(move || {
+            let result = compress_growing(&mut compressor, image.as_ref(), &mut buffer);
+            (compressor, buffer, result)
+        })
+        .await?;
+        self.compressor = Some(compressor);
+        self.buffer = buffer;
+
+        let size = result?;
+        self.writer.write_all(&self.buffer[..size]).await?;
+        Ok(size)
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.writer
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.writer
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+/// Compresses `image` into `buffer`, growing it until the stream fits.
+fn compress_growing(
+    compressor: &mut Compressor,
+    image: &[u8],
+    buffer: &mut Vec<u8>,
+) -> Result<usize, Error> {
+    if buffer.is_empty() {
+        buffer.resize(2 * compressor.header().get_decompress_buffer_size(0), 0);
+    }
+    loop {
+        match compressor.compress(image, buffer) {
+            Err(Error::BlockTableOverflow { .. }) | Err(Error::BlockOverflow { .. }) => {
+                buffer.resize(buffer.len() * 2, 0);
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Image decoded from the step levels read so far.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DecodedLevel {
+    /// Step of the last level read, details finer than it are still missing.
+    pub step: usize,
+    /// Image in the layout of `decompress_simple`, which is what `decompress_simple` returns
+    /// for the stream cut after this level.
+    pub image: Vec<u8>,
+}
+
+/// Reads a stream from an `AsyncRead` one step level at a time and decodes every level on
+/// tokio's blocking thread pool as soon as it arrives, coarsest level first.
+///
+/// If a `next_level` future is dropped before it completes, the decoder can't be used anymore.
+#[derive(Debug)]
+pub struct AsyncDecoder<R> {
+    reader: R,
+    header: Header,
+    metadata: Vec<u8>,
+    /// Step levels with their block counts, down to the requested downsampling.
+    levels: Vec<(usize, usize)>,
+    next_level: usize,
+    /// Offset of the next level from the end of the color transform program.
+    offset: usize,
+    /// Encoded level being read.
+    data: Vec<u8>,
+    /// Moved to the blocking pool while a level is decoded.
+    decoder: Option<LevelDecoder>,
+}
+
+impl<R> AsyncDecoder<R>
+where
+    R: AsyncRead + Unpin,
+{
+    /// Reads the header, the metadata area and the color transform program of a stream, which
+    /// will be decoded downsampled by `downsampling` like with `decompress_simple`.
+    pub async fn new(reader: R, downsampling: usize) -> Result<Self, Error> {
+        AsyncDecoder::with_limits(reader, downsampling, &DecodeLimits::default()).await
+    }
+
+    /// Like `new`, but checks the header and the color transform program against `limits`
+    /// instead of the default ones.
+    pub async fn with_limits(
+        mut reader: R,
+        downsampling: usize,
+        limits: &DecodeLimits,
+    ) -> Result<Self, Error> {
+        let mut encoded_header = [0; HEADER_SIZE];
+        reader.read_exact(&mut encoded_header).await?;
+        let header = Header::decode_with_limits(&mut &encoded_header[..], limits)?;
+
+        let metadata_size = header.metadata_size as usize;
+        let mut metadata = vec![];
+        (&mut reader)
+            .take(metadata_size as u64)
+            .read_to_end(&mut metadata)
+            .await?;
+        if metadata.len() < metadata_size {
+            return Err(IOError::UnexpectedEof.into());
+        }
+
+        // the program is a sequence of words, which is read until it decodes
+        let mut is_chroma = vec![false; header.layers as usize * header.channels as usize];
+        let mut program = vec![];
+        let color_transform = loop {
+            let mut word = [0; 4];
+            reader.read_exact(&mut word).await?;
+            program.extend_from_slice(&word);
+
+            for is_chroma in is_chroma.iter_mut() {
+                *is_chroma = false;
+            }
+            match ColorTransformProgram::decode_with_limits(
+                &mut &program[..],
+                &mut is_chroma,
+                limits,
+            ) {
+                Err(Error::IOErr(IOError::UnexpectedEof)) => {}
+                result => break result?,
+            }
+        };
+
+        // the palette section of screen content streams precedes the levels, it is read until
+        // it splits off
+        let mut section = vec![];
+        if header.encoder == Encoder::Screen {
+            while let Err(size) = palette::split_section(&section) {
+                let missing = size - section.len();
+                (&mut reader)
+                    .take(missing as u64)
+                    .read_to_end(&mut section)
+                    .await?;
+                if section.len() < size {
+                    return Err(IOError::UnexpectedEof.into());
+                }
+            }
+        }
+
+        let extension = Extension::decode(&metadata, &header)?;
+        let coding = Coding::new(&header, &is_chroma, &extension);
+
+        let mut levels = step_levels(&header)?;
+        levels.retain(|&(step, _)| (step >> downsampling) >= 1);
+
+        Ok(AsyncDecoder {
+            reader,
+            metadata,
+            levels,
+            next_level: 0,
+            offset: section.len(),
+            data: vec![],
+            decoder: Some(LevelDecoder {
+                coefficients: vec![0; header.get_downsampled_image_size(downsampling)],
+                header: header.clone(),
+                downsampling,
+                color_transform,
+                coding,
+                section,
+                aux_data: vec![],
+                scratch: Scratch::default(),
+            }),
+            header,
+        })
+    }
+
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
+    pub fn metadata(&self) -> &[u8] {
+        &self.metadata
+    }
+
+    /// Reads and decodes the next step level, `None` after the last one or after an error.
+    pub async fn next_level(&mut self) -> Option<Result<DecodedLevel, Error>> {
+        let (step, block_count) = *self.levels.get(self.next_level)?;
+        let result = self.read_level(step, block_count).await;
+        self.next_level = if result.is_ok() {
+            self.next_level + 1
+        } else {
+            self.levels.len()
+        };
+        Some(result)
+    }
+
+    /// Stream of the remaining step levels, see `next_level`.
+    pub fn into_stream(self) -> impl Stream<Item = Result<DecodedLevel, Error>> {
+        stream::unfold(self, |mut decoder| async move {
+            let level = decoder.next_level().await?;
+            Some((level, decoder))
+        })
+    }
+
+    /// Reader positioned after the levels read so far, e.g. at the next stream once all the
+    /// levels are read.
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+
+    async fn read_level(&mut self, step: usize, block_count: usize) -> Result<DecodedLevel, Error> {
+        let offset = self.offset;
+        let check_size = |actual: usize, expected: usize| {
+            if actual < expected {
+                Err(Error::Truncated {
+                    step,
+                    expected: offset.saturating_add(expected),
+                    actual: offset + actual,
+                })
+            } else {
+                Ok(())
+            }
+        };
+
+        let block_sizes_storage_size = block_count * std::mem::size_of::<u32>();
+        let mut data = mem::take(&mut self.data);
+        data.clear();
+        (&mut self.reader)
+            .take(block_sizes_storage_size as u64)
+            .read_to_end(&mut data)
+            .await?;
+        check_size(data.len(), block_sizes_storage_size)?;
+
+        // block sizes come straight from the stream, so the level size saturates
+        let level_size = data
+            .chunks(std::mem::size_of::<u32>())
+            .map(|chunk| (LittleEndian::read_u32(chunk) as usize).saturating_mul(4))
+            .fold(block_sizes_storage_size, usize::saturating_add);
+        (&mut self.reader)
+            .take((level_size - block_sizes_storage_size) as u64)
+            .read_to_end(&mut data)
+            .await?;
+        check_size(data.len(), level_size)?;
+
+        let mut decoder = self.decoder.take().ok_or_else(cancelled)?;
+        let has_dc = self.next_level == 0;
+        let (decoder, data, result) = unblock(move || {
+            let level = StepLevel {
+                step,
+                block_count,
+                has_dc,
+                offset,
+                data: &data,
+            };
+            let result = decoder.decode(&level);
+            (decoder, data, result)
+        })
+        .await?;
+        self.decoder = Some(decoder);
+        self.offset += data.len();
+        self.data = data;
+
+        Ok(DecodedLevel {
+            step,
+            image: result?,
+        })
+    }
+}
+
+/// Decoding state moved between the reader and the blocking pool.
+#[derive(Debug)]
+struct LevelDecoder {
+    header: Header,
+    downsampling: usize,
+    color_transform: ColorTransformProgram,
+    coding: Coding,
+    /// Palette section, empty for other encoders.
+    section: Vec<u8>,
+    /// Quantized coefficients of the levels decoded so far.
+    coefficients: Vec<i16>,
+    aux_data: Vec<i16>,
+    scratch: Scratch,
+}
+
+impl LevelDecoder {
+    fn decode(&mut self, level: &StepLevel<'_>) -> Result<Vec<u8>, Error> {
+        let LevelDecoder {
+            header,
+            downsampling,
+            color_transform,
+            coding,
+            section,
+            coefficients,
+            aux_data,
+            scratch,
+        } = self;
+        let downsampling = *downsampling;
+        decompress_step_level(
+            coefficients,
+            header,
+            coding,
+            downsampling,
+            false,
+            level,
+            scratch,
+        )?;
+
+        // the coefficients are kept for the next levels, the image is built from a copy
+        aux_data.clear();
+        aux_data.extend_from_slice(coefficients);
+        let tiles = palette::split_section(section).map_or(&[][..], |(tiles, _)| tiles);
+        unlift_and_dequantize_with(aux_data, header, coding, downsampling, tiles, scratch)?;
+
+        let width = header.get_downsampled_width(downsampling);
+        let channels = header.channels as usize;
+        let mut image = vec![0; header.get_decompress_buffer_size(downsampling)];
+        let mut view = ImageViewMut::with_layers(
+            &mut image,
+            width,
+            header.get_downsampled_height(downsampling),
+            header.layers as usize,
+            channels,
+            width * channels,
+        )?;
+        color_transform.detransform_to_view(aux_data, header, downsampling, &mut view)?;
+
+        Ok(image)
+    }
+}
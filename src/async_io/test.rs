@@ -0,0 +1,264 @@
+use std::future::Future;
+
+use futures_util::StreamExt;
+
+use super::*;
+use crate::errors::HeaderErr;
+use crate::header::HeaderBuilder;
+
+fn compress(header: &Header, frame: &[u8]) -> Vec<u8> {
+    let mut buffer = vec![0; frame.len() * 4];
+    let size = crate::compress_simple(
+        frame,
+        header,
+        &ColorTransformProgram::rgb_to_yuv(),
+        &mut buffer,
+    )
+    .unwrap();
+    buffer.truncate(size);
+    buffer
+}
+
+fn block_on<F: Future>(future: F) -> F::Output {
+    tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap()
+        .block_on(future)
+}
+
+fn assert_send<T: Send>(value: T) -> T {
+    value
+}
+
+/// Sizes of the stream cut after every step level.
+fn level_ends(stream: &[u8], header: &Header) -> Vec<usize> {
+    let mut data = &stream[HEADER_SIZE + header.metadata_size as usize..];
+    let mut is_chroma = vec![false; 3];
+    ColorTransformProgram::decode(&mut data, &mut is_chroma).unwrap();
+    let mut end = stream.len() - data.len();
+
+    step_levels(header)
+        .unwrap()
+        .into_iter()
+        .map(|(_, block_count)| {
+            let block_sizes = &stream[end..end + block_count * 4];
+            end += block_count * 4
+                + block_sizes
+                    .chunks(4)
+                    .map(|chunk| LittleEndian::read_u32(chunk) as usize * 4)
+                    .sum::<usize>();
+            end
+        })
+        .collect()
+}
+
+#[test]
+fn test_levels_match_truncated_decompress() {
+    let header = HeaderBuilder {
+        width: 37,
+        height: 21,
+        metadata_size: 4,
+        ..HeaderBuilder::test_default()
+    }
+    .build()
+    .unwrap();
+    let stream = compress(&header, &header.test_frame(0));
+    let ends = level_ends(&stream, &header);
+
+    for &downsampling in &[0, 1] {
+        let levels = block_on(async {
+            let decoder = AsyncDecoder::new(&stream[..], downsampling).await.unwrap();
+            assert_eq!(decoder.header(), &header);
+            assert_eq!(decoder.metadata(), &[0; 4]);
+            assert_send(decoder.into_stream()).collect::<Vec<_>>().await
+        });
+        assert_eq!(
+            levels.len(),
+            step_levels(&header)
+                .unwrap()
+                .iter()
+                .filter(|&&(step, _)| step >> downsampling >= 1)
+                .count()
+        );
+
+        for (level, &end) in levels.into_iter().zip(&ends) {
+            let level = level.unwrap();
+            let mut expected = vec![0; header.get_decompress_buffer_size(downsampling)];
+            crate::decompress_simple(
+                &stream[HEADER_SIZE..end],
+                &header,
+                downsampling,
+                false,
+                &mut expected,
+            )
+            .unwrap();
+            assert_eq!(level.image, expected, "step {}", level.step);
+        }
+    }
+}
+
+#[test]
+fn test_screen_content() {
+    let header = HeaderBuilder {
+        width: 48,
+        height: 40,
+        encoder: Encoder::Screen,
+        ..HeaderBuilder::test_default()
+    }
+    .build()
+    .unwrap();
+    // flat areas of a few colors, coded as palette tiles, next to noise
+    let frame = header
+        .test_frame(3)
+        .into_iter()
+        .enumerate()
+        .map(|(i, noise)| {
+            if i / 3 % 48 < 32 {
+                (i / 3 % 48 / 8 * 60) as u8
+            } else {
+                noise
+            }
+        })
+        .collect::<Vec<_>>();
+    let stream = compress(&header, &frame);
+
+    let last = block_on(async {
+        let decoder = AsyncDecoder::new(&stream[..], 0).await.unwrap();
+        let levels = decoder.into_stream().collect::<Vec<_>>().await;
+        levels.into_iter().last().unwrap().unwrap()
+    });
+    let mut expected = vec![0; header.get_decompress_buffer_size(0)];
+    crate::decompress_simple(&stream[HEADER_SIZE..], &header, 0, false, &mut expected).unwrap();
+    assert_eq!(last.image, expected);
+
+    // the stream ends in the palette section
+    let mut data = &stream[HEADER_SIZE..];
+    ColorTransformProgram::decode(&mut data, &mut [false; 3]).unwrap();
+    let section_start = stream.len() - data.len();
+    assert!(!palette::split_section(data).unwrap().0.is_empty());
+    let result = block_on(AsyncDecoder::new(&stream[..section_start + 6], 0));
+    match result {
+        Err(Error::IOErr(IOError::UnexpectedEof)) => (),
+        result => panic!("unexpected result: {:?}", result.map(|_| ())),
+    }
+}
+
+#[test]
+fn test_encoder_writes_consecutive_streams() {
+    let header = HeaderBuilder {
+        width: 29,
+        height: 17,
+        metadata_size: 4,
+        ..HeaderBuilder::test_default()
+    }
+    .build()
+    .unwrap();
+    let frames = [header.test_frame(1), header.test_frame(2)];
+    let compressor = Compressor::new(&header, &ColorTransformProgram::rgb_to_yuv()).unwrap();
+
+    let output = block_on(async {
+        let mut encoder = AsyncEncoder::new(vec![], compressor);
+        for frame in frames.iter() {
+            assert_send(encoder.encode(frame.clone())).await.unwrap();
+        }
+        encoder.into_inner()
+    });
+    let expected = [compress(&header, &frames[0]), compress(&header, &frames[1])].concat();
+    assert_eq!(output, expected);
+
+    // every decoder stops at the end of its stream
+    let images = block_on(async {
+        let mut reader = &output[..];
+        let mut images = vec![];
+        for _ in 0..2 {
+            let mut decoder = AsyncDecoder::new(reader, 0).await.unwrap();
+            let mut last = None;
+            while let Some(level) = decoder.next_level().await {
+                last = Some(level.unwrap());
+            }
+            images.push(last.unwrap());
+            reader = decoder.into_inner();
+        }
+        assert!(reader.is_empty());
+        images
+    });
+    for (image, frame) in images.iter().zip(frames.iter()) {
+        let mut expected = vec![0; frame.len()];
+        crate::decompress_simple(
+            &compress(&header, frame)[HEADER_SIZE..],
+            &header,
+            0,
+            false,
+            &mut expected,
+        )
+        .unwrap();
+        assert_eq!(image.step, 1);
+        assert_eq!(image.image, expected);
+    }
+}
+
+#[test]
+fn test_truncated_level() {
+    let header = HeaderBuilder {
+        width: 37,
+        height: 21,
+        metadata_size: 4,
+        ..HeaderBuilder::test_default()
+    }
+    .build()
+    .unwrap();
+    let stream = compress(&header, &header.test_frame(3));
+    let ends = level_ends(&stream, &header);
+    let truncated = &stream[..ends[1] + 2];
+
+    let levels = block_on(async {
+        let decoder = AsyncDecoder::new(truncated, 0).await.unwrap();
+        decoder.into_stream().collect::<Vec<_>>().await
+    });
+    assert_eq!(levels.len(), 3);
+    assert!(levels[0].is_ok());
+    assert!(levels[1].is_ok());
+    match levels[2] {
+        Err(Error::Truncated { .. }) => (),
+        ref result => panic!("unexpected result: {:?}", result),
+    }
+}
+
+#[test]
+fn test_decode_limits() {
+    let header = HeaderBuilder {
+        width: 37,
+        height: 21,
+        metadata_size: 4,
+        ..HeaderBuilder::test_default()
+    }
+    .build()
+    .unwrap();
+    let stream = compress(&header, &header.test_frame(0));
+
+    let limits = DecodeLimits {
+        max_width: 36,
+        ..DecodeLimits::default()
+    };
+    match block_on(AsyncDecoder::with_limits(&stream[..], 0, &limits)) {
+        Err(Error::Header(HeaderErr::LimitExceeded { .. })) => (),
+        result => panic!("unexpected result: {:?}", result.map(|_| ())),
+    }
+
+    let limits = DecodeLimits {
+        max_transform_program_len: 1,
+        ..DecodeLimits::default()
+    };
+    match block_on(AsyncDecoder::with_limits(&stream[..], 0, &limits)) {
+        Err(Error::TransformLength { max: 1 }) => (),
+        result => panic!("unexpected result: {:?}", result.map(|_| ())),
+    }
+
+    let decoder = block_on(AsyncDecoder::with_limits(
+        &stream[..],
+        0,
+        &DecodeLimits::unlimited(),
+    ))
+    .unwrap();
+    assert_eq!(decoder.header(), &header);
+}
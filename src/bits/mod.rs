@@ -1,28 +1,26 @@
-use std::io;
-
-use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use crate::io::{IOError, Read, Write};
 
 #[cfg(test)]
 mod test;
 
 pub trait BitsWriter {
-    fn put_bits(&mut self, x: u32, bits: u32) -> io::Result<()>;
-    fn flush_write_word(&mut self) -> io::Result<()>;
+    fn put_bits(&mut self, x: u32, bits: u32) -> Result<(), IOError>;
+    fn flush_write_word(&mut self) -> Result<(), IOError>;
 }
 
 pub trait BitsReader {
-    fn get_bits(&mut self, bits: u32) -> io::Result<u32>;
-    fn get_zeros(&mut self, max_zeros: u32) -> io::Result<u32>;
+    fn get_bits(&mut self, bits: u32) -> Result<u32, IOError>;
+    fn get_zeros(&mut self, max_zeros: u32) -> Result<u32, IOError>;
     fn flush_read_word(&mut self);
 }
 
-pub struct BitsIOWriter<'a, W: WriteBytesExt> {
+pub struct BitsIOWriter<'a, W: Write> {
     write_stream: &'a mut W,
     write_cache: u32,
     index_bits: u32,
 }
 
-impl<'a, W: WriteBytesExt> BitsIOWriter<'a, W> {
+impl<'a, W: Write> BitsIOWriter<'a, W> {
     pub fn new(stream: &'a mut W) -> Self {
         BitsIOWriter {
             write_stream: stream,
@@ -32,8 +30,8 @@ impl<'a, W: WriteBytesExt> BitsIOWriter<'a, W> {
     }
 }
 
-impl<W: WriteBytesExt> BitsWriter for BitsIOWriter<'_, W> {
-    fn put_bits(&mut self, x: u32, bits: u32) -> io::Result<()> {
+impl<W: Write> BitsWriter for BitsIOWriter<'_, W> {
+    fn put_bits(&mut self, x: u32, bits: u32) -> Result<(), IOError> {
         let mut new_bits = self.index_bits + bits;
 
         if new_bits < 32 {
@@ -41,9 +39,8 @@ impl<W: WriteBytesExt> BitsWriter for BitsIOWriter<'_, W> {
         } else {
             new_bits -= 32;
 
-            self.write_stream.write_u32::<LittleEndian>(
-                (self.write_cache << (bits - new_bits)) | (x >> new_bits),
-            )?;
+            self.write_stream
+                .write_u32((self.write_cache << (bits - new_bits)) | (x >> new_bits))?;
 
             self.write_cache = x;
         }
@@ -53,7 +50,7 @@ impl<W: WriteBytesExt> BitsWriter for BitsIOWriter<'_, W> {
         Ok(())
     }
 
-    fn flush_write_word(&mut self) -> io::Result<()> {
+    fn flush_write_word(&mut self) -> Result<(), IOError> {
         let index_bits = self.index_bits;
         self.put_bits(0, (32 - index_bits) % 32)?;
 
@@ -62,14 +59,14 @@ impl<W: WriteBytesExt> BitsWriter for BitsIOWriter<'_, W> {
 }
 
 #[derive(Debug)]
-pub struct BitsIOReader<'a, R: ReadBytesExt> {
+pub struct BitsIOReader<'a, R: Read> {
     read_stream: &'a mut R,
     read_cache: u32,
     index_bits: u32,
     cache_filled: bool,
 }
 
-impl<'a, R: ReadBytesExt> BitsIOReader<'a, R> {
+impl<'a, R: Read> BitsIOReader<'a, R> {
     pub fn new(stream: &'a mut R) -> Self {
         BitsIOReader {
             read_cache: 0xff_ff_ff_ff,
@@ -79,8 +76,8 @@ impl<'a, R: ReadBytesExt> BitsIOReader<'a, R> {
         }
     }
 
-    fn next_u32(&mut self) -> io::Result<u32> {
-        match self.read_stream.read_u32::<LittleEndian>() {
+    fn next_u32(&mut self) -> Result<u32, IOError> {
+        match self.read_stream.read_u32() {
             Ok(n) => {
                 self.cache_filled = true;
                 Ok(n)
@@ -93,8 +90,8 @@ impl<'a, R: ReadBytesExt> BitsIOReader<'a, R> {
     }
 }
 
-impl<'a, R: ReadBytesExt> BitsReader for BitsIOReader<'a, R> {
-    fn get_bits(&mut self, bits: u32) -> io::Result<u32> {
+impl<'a, R: Read> BitsReader for BitsIOReader<'a, R> {
+    fn get_bits(&mut self, bits: u32) -> Result<u32, IOError> {
         let mut new_bits = self.index_bits + bits;
         if !self.cache_filled {
             self.read_cache = self.next_u32()?;
@@ -118,7 +115,7 @@ impl<'a, R: ReadBytesExt> BitsReader for BitsIOReader<'a, R> {
         Ok(x >> (32 - bits))
     }
 
-    fn get_zeros(&mut self, max_zeros: u32) -> io::Result<u32> {
+    fn get_zeros(&mut self, max_zeros: u32) -> Result<u32, IOError> {
         let mut new_bits = self.index_bits;
         if !self.cache_filled {
             self.read_cache = self.next_u32()?;
@@ -162,3 +159,167 @@ impl<'a, R: ReadBytesExt> BitsReader for BitsIOReader<'a, R> {
         self.index_bits = 0;
     }
 }
+
+/// `BitsWriter` into a slice, producing the same stream as `BitsIOWriter` over `&mut [u8]`.
+///
+/// Bits are collected in a 64-bit cache and stored a whole 32-bit word at a time.
+#[derive(Debug)]
+pub struct SliceBitsWriter<'a> {
+    buffer: &'a mut [u8],
+    position: usize,
+    /// Pending bits in the lowest `cache_bits` bits, the higher ones are garbage.
+    cache: u64,
+    cache_bits: u32,
+}
+
+impl<'a> SliceBitsWriter<'a> {
+    #[inline]
+    pub fn new(buffer: &'a mut [u8]) -> Self {
+        SliceBitsWriter {
+            buffer,
+            position: 0,
+            cache: 0,
+            cache_bits: 0,
+        }
+    }
+
+    /// Number of bytes stored into the slice, always a multiple of 4.
+    #[inline]
+    pub fn position(&self) -> usize {
+        self.position
+    }
+}
+
+impl BitsWriter for SliceBitsWriter<'_> {
+    #[inline]
+    fn put_bits(&mut self, x: u32, bits: u32) -> Result<(), IOError> {
+        debug_assert!(bits <= 32);
+
+        let cache = (self.cache << bits) | u64::from(x);
+        let cache_bits = self.cache_bits + bits;
+        if cache_bits < 32 {
+            self.cache = cache;
+            self.cache_bits = cache_bits;
+            return Ok(());
+        }
+
+        if self.buffer.len() - self.position < 4 {
+            return Err(IOError::WriteZero);
+        }
+        let word = (cache >> (cache_bits - 32)) as u32;
+        // SAFETY: `position + 4 <= buffer.len()` was checked above
+        unsafe {
+            self.buffer
+                .as_mut_ptr()
+                .add(self.position)
+                .cast::<[u8; 4]>()
+                .write(word.to_le_bytes());
+        }
+        self.position += 4;
+        self.cache = cache;
+        self.cache_bits = cache_bits - 32;
+
+        Ok(())
+    }
+
+    #[inline]
+    fn flush_write_word(&mut self) -> Result<(), IOError> {
+        self.put_bits(0, (32 - self.cache_bits) % 32)
+    }
+}
+
+/// `BitsReader` from a slice, decoding the same stream as `BitsIOReader` over `&[u8]`.
+///
+/// Whole 32-bit words are loaded into a 64-bit cache, and runs of zeros are counted with
+/// `leading_zeros` instead of bit by bit.
+#[derive(Debug)]
+pub struct SliceBitsReader<'a> {
+    data: &'a [u8],
+    /// Unread bits in the highest `cache_bits` bits, the lower ones are zero.
+    cache: u64,
+    cache_bits: u32,
+}
+
+impl<'a> SliceBitsReader<'a> {
+    #[inline]
+    pub fn new(data: &'a [u8]) -> Self {
+        SliceBitsReader {
+            data,
+            cache: 0,
+            cache_bits: 0,
+        }
+    }
+
+    /// Loads the next word below the unread bits, `cache_bits` must be at most 32.
+    #[inline]
+    fn refill(&mut self) -> Result<(), IOError> {
+        if self.data.len() < 4 {
+            return Err(IOError::UnexpectedEof);
+        }
+        // SAFETY: `data` has at least 4 bytes, checked above
+        let word = unsafe {
+            let word = self.data.as_ptr().cast::<[u8; 4]>().read();
+            self.data = self.data.get_unchecked(4..);
+            u32::from_le_bytes(word)
+        };
+        self.cache |= u64::from(word) << (32 - self.cache_bits);
+        self.cache_bits += 32;
+        Ok(())
+    }
+
+    #[inline]
+    fn consume(&mut self, bits: u32) {
+        self.cache <<= bits;
+        self.cache_bits -= bits;
+    }
+}
+
+impl BitsReader for SliceBitsReader<'_> {
+    #[inline]
+    fn get_bits(&mut self, bits: u32) -> Result<u32, IOError> {
+        debug_assert!(bits <= 32);
+        if bits == 0 {
+            return Ok(0);
+        }
+
+        if self.cache_bits < bits {
+            self.refill()?;
+        }
+        let x = (self.cache >> (64 - bits)) as u32;
+        self.consume(bits);
+
+        Ok(x)
+    }
+
+    #[inline]
+    fn get_zeros(&mut self, max_zeros: u32) -> Result<u32, IOError> {
+        debug_assert!(max_zeros > 0);
+
+        let mut zeros = 0;
+        loop {
+            if self.cache_bits == 0 {
+                self.refill()?;
+            }
+
+            // the bits below the unread ones are zero, so the run may exceed them
+            let run = self.cache.leading_zeros().min(self.cache_bits);
+            let left = max_zeros.wrapping_sub(zeros);
+            if run >= left {
+                self.consume(left);
+                return Ok(max_zeros);
+            }
+            if run < self.cache_bits {
+                // the run ends with a one, which is consumed too
+                self.consume(run + 1);
+                return Ok(zeros + run);
+            }
+            self.consume(run);
+            zeros += run;
+        }
+    }
+
+    #[inline]
+    fn flush_read_word(&mut self) {
+        self.consume(self.cache_bits % 32);
+    }
+}
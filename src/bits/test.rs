@@ -92,3 +92,124 @@ fn test_zeros() {
     assert_eq!(stream.get_zeros(2).unwrap(), 0);
     assert_eq!(stream.get_zeros(5).unwrap(), 3);
 }
+
+/// Pseudo-random `(value, bits)` pairs with `value < 2^bits`.
+fn build_codes(count: usize) -> Vec<(u32, u32)> {
+    let mut state = 0x1234_5678u32;
+    (0..count)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            let bits = state % 32 + 1;
+            // mostly short values, so that the stream has long runs of zeros
+            let value = (state >> (state % 32)) & (!0u32 >> (32 - bits));
+            (value, bits)
+        })
+        .collect()
+}
+
+#[test]
+fn test_slice_bits_write_matches_io() {
+    for &count in &[0, 1, 7, 100, 1000] {
+        let codes = build_codes(count);
+        let mut expected = vec![];
+        {
+            let mut stream = BitsIOWriter::new(&mut expected);
+            for &(value, bits) in &codes {
+                stream.put_bits(value, bits).unwrap();
+            }
+            stream.flush_write_word().unwrap();
+        }
+
+        let mut output = vec![0; expected.len() + 8];
+        let mut stream = SliceBitsWriter::new(&mut output);
+        for &(value, bits) in &codes {
+            stream.put_bits(value, bits).unwrap();
+        }
+        stream.flush_write_word().unwrap();
+        let size = stream.position();
+        assert_eq!(&output[..size], &expected[..]);
+    }
+}
+
+#[test]
+fn test_slice_bits_write_overflow_detection() {
+    let mut output = [0u8; 7];
+
+    let mut stream = SliceBitsWriter::new(&mut output);
+    stream.put_bits(185, 27).unwrap();
+    stream.put_bits(61, 3).unwrap();
+    stream.flush_write_word().unwrap();
+    stream.put_bits(3, 6).unwrap();
+    assert!(stream.flush_write_word().is_err());
+}
+
+#[test]
+fn test_slice_bits_read_matches_io() {
+    let mut input = vec![];
+    {
+        let mut stream = BitsIOWriter::new(&mut input);
+        for &(value, bits) in &build_codes(1000) {
+            stream.put_bits(value, bits).unwrap();
+        }
+        stream.flush_write_word().unwrap();
+    }
+
+    // the same calls on both readers, until both run out of data
+    let mut slice: &[u8] = &input;
+    let mut expected_stream = BitsIOReader::new(&mut slice);
+    let mut stream = SliceBitsReader::new(&input);
+    for (i, (value, bits)) in build_codes(5000).into_iter().enumerate() {
+        let (expected, output) = match value % 4 {
+            0 => (
+                expected_stream.get_zeros(bits % 13 + 1),
+                stream.get_zeros(bits % 13 + 1),
+            ),
+            1 if i % 16 == 0 => {
+                expected_stream.flush_read_word();
+                stream.flush_read_word();
+                continue;
+            }
+            _ => (expected_stream.get_bits(bits), stream.get_bits(bits)),
+        };
+        match (expected, output) {
+            (Ok(expected), Ok(output)) => assert_eq!(output, expected, "call {}", i),
+            (Err(_), Err(_)) => return,
+            (expected, output) => panic!("call {}: {:?} != {:?}", i, output, expected),
+        }
+    }
+    panic!("input not exhausted");
+}
+
+#[test]
+fn test_slice_bits_read_underflow_detection() {
+    let input = [1, 2, 3, 4, 5, 6u8];
+
+    let mut stream = SliceBitsReader::new(&input);
+    stream.get_bits(27).unwrap();
+    stream.get_bits(3).unwrap();
+    stream.flush_read_word();
+    assert_eq!(stream.get_bits(0).unwrap(), 0);
+    assert!(stream.get_bits(4).is_err());
+}
+
+#[test]
+fn test_slice_zeros() {
+    let input = [0b11110001_u8, 0b10000001, 0b00101100, 0b10001000];
+    let mut stream = SliceBitsReader::new(&input);
+    assert_eq!(stream.get_zeros(11).unwrap(), 0);
+    assert_eq!(stream.get_zeros(1).unwrap(), 1);
+    assert_eq!(stream.get_zeros(5).unwrap(), 2);
+    assert_eq!(stream.get_zeros(6).unwrap(), 5);
+    assert_eq!(stream.get_zeros(2).unwrap(), 1);
+    assert_eq!(stream.get_zeros(2).unwrap(), 0);
+    assert_eq!(stream.get_zeros(3).unwrap(), 2);
+    assert_eq!(stream.get_zeros(10).unwrap(), 6);
+    assert_eq!(stream.get_zeros(2).unwrap(), 0);
+    assert_eq!(stream.get_zeros(2).unwrap(), 0);
+    assert_eq!(stream.get_zeros(2).unwrap(), 0);
+    assert_eq!(stream.get_zeros(2).unwrap(), 0);
+    assert_eq!(stream.get_zeros(5).unwrap(), 3);
+    assert!(stream.get_zeros(5).is_err());
+}
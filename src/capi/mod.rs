@@ -0,0 +1,468 @@
+//! C ABI for the codec, enabled with the `capi` feature.
+//!
+//! The matching C header is `include/gfwx.h`; regenerate it after changing this module with
+//! `cbindgen --config cbindgen.toml --output include/gfwx.h`.
+
+use std::{
+    os::raw::{c_char, c_int},
+    panic, slice,
+};
+
+use num_traits::FromPrimitive;
+
+use crate::color_transform::ColorTransformProgram;
+use crate::errors::{Error, HeaderErr};
+use crate::header::{Encoder, Filter, Header, HeaderBuilder, Intent, Quantization, Weighting};
+
+#[cfg(test)]
+mod test;
+
+pub const GFWX_FILTER_LINEAR: u8 = 0;
+pub const GFWX_FILTER_CUBIC: u8 = 1;
+
+pub const GFWX_QUANTIZATION_SCALAR: u8 = 0;
+pub const GFWX_QUANTIZATION_DEAD_ZONE: u8 = 1;
+
+pub const GFWX_WEIGHTING_FLAT: u8 = 0;
+pub const GFWX_WEIGHTING_CSF: u8 = 1;
+
+pub const GFWX_ENCODER_TURBO: u8 = 0;
+pub const GFWX_ENCODER_FAST: u8 = 1;
+pub const GFWX_ENCODER_CONTEXTUAL: u8 = 2;
+pub const GFWX_ENCODER_SCREEN: u8 = 3;
+
+pub const GFWX_INTENT_GENERIC: u8 = 0;
+pub const GFWX_INTENT_RGB: u8 = 7;
+pub const GFWX_INTENT_RGBA: u8 = 8;
+pub const GFWX_INTENT_BGR: u8 = 10;
+pub const GFWX_INTENT_BGRA: u8 = 11;
+pub const GFWX_INTENT_YUV444: u8 = 12;
+
+/// Result codes returned by the `gfwx_*` functions, mapped from `Error` and `HeaderErr`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GfwxResult {
+    Ok = 0,
+    ErrorIo = -1,
+    ErrorOverflow = -2,
+    ErrorMalformed = -3,
+    ErrorBufferSize = -4,
+    ErrorUnderflow = -5,
+    ErrorTypeMismatch = -6,
+    ErrorWrongMagic = -7,
+    ErrorWrongValue = -8,
+    ErrorNullPointer = -9,
+    ErrorPanic = -10,
+    ErrorLimitExceeded = -11,
+    ErrorChecksum = -12,
+    ErrorLossy = -13,
+}
+
+/// Color transform presets, see the `ColorTransformProgram` constructors.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GfwxColorTransform {
+    None = 0,
+    Yuv444ToYuv444 = 1,
+    RgbToYuv = 2,
+    RgbToA710 = 3,
+    BgrToA710 = 4,
+    RgbToYcocgR = 5,
+    BgrToYcocgR = 6,
+    RgbaToYcocgR = 7,
+    BgraToYcocgR = 8,
+}
+
+/// Plain-data mirror of `Header`. `filter`, `quantization`, `encoder`, `intent` and `weighting`
+/// hold the values stored in the stream. `version`, `bit_depth` and `is_signed` may be left
+/// zeroed when building a header.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct GfwxHeader {
+    pub version: u32,
+    pub width: u32,
+    pub height: u32,
+    pub layers: u16,
+    pub channels: u16,
+    pub bit_depth: u8,
+    pub is_signed: u8,
+    pub quality: u16,
+    pub chroma_scale: u8,
+    pub block_size: u8,
+    pub filter: u8,
+    pub quantization: u8,
+    pub encoder: u8,
+    pub intent: u8,
+    pub metadata_size: u32,
+    pub weighting: u8,
+    /// Non-zero if the metadata area ends with the coding extension.
+    pub has_extension: u8,
+}
+
+impl From<&Error> for GfwxResult {
+    fn from(err: &Error) -> Self {
+        match err {
+            Error::IOErr(_) => GfwxResult::ErrorIo,
+            Error::Header(e) => GfwxResult::from(e),
+            Error::BufferSize { .. }
+            | Error::ViewShape { .. }
+            | Error::ViewStride { .. }
+            | Error::PlaneCount { .. } => GfwxResult::ErrorBufferSize,
+            Error::BlockTableOverflow { .. } | Error::BlockOverflow { .. } => {
+                GfwxResult::ErrorOverflow
+            }
+            Error::Truncated { .. } | Error::BlockUnderflow { .. } => GfwxResult::ErrorUnderflow,
+            Error::BlockSize(_)
+            | Error::TransformChannel { .. }
+            | Error::TransformDenominator { .. }
+            | Error::Extension { .. }
+            | Error::Palette { .. } => GfwxResult::ErrorMalformed,
+            Error::TransformLength { .. } => GfwxResult::ErrorLimitExceeded,
+            Error::TransformSyntax { .. }
+            | Error::NoAlphaChannel
+            | Error::AlphaTransformed { .. }
+            | Error::QualityTable { .. }
+            | Error::QualityMapShape { .. }
+            | Error::NoThreads
+            | Error::NoCandidates => GfwxResult::ErrorWrongValue,
+            Error::ChecksumMissing | Error::ChecksumMismatch(_) => GfwxResult::ErrorChecksum,
+            Error::Lossy(_) | Error::LosslessMismatch { .. } => GfwxResult::ErrorLossy,
+        }
+    }
+}
+
+impl From<&HeaderErr> for GfwxResult {
+    fn from(err: &HeaderErr) -> Self {
+        match err {
+            HeaderErr::IOErr(_) => GfwxResult::ErrorIo,
+            HeaderErr::WrongMagic => GfwxResult::ErrorWrongMagic,
+            HeaderErr::WrongValue(_) => GfwxResult::ErrorWrongValue,
+            HeaderErr::LimitExceeded { .. } => GfwxResult::ErrorLimitExceeded,
+        }
+    }
+}
+
+impl From<&Header> for GfwxHeader {
+    fn from(header: &Header) -> Self {
+        GfwxHeader {
+            version: header.version,
+            width: header.width,
+            height: header.height,
+            layers: header.layers,
+            channels: header.channels,
+            bit_depth: header.bit_depth,
+            is_signed: header.is_signed as u8,
+            quality: header.quality,
+            chroma_scale: header.chroma_scale,
+            block_size: header.block_size,
+            filter: header.filter as u8,
+            quantization: header.quantization as u8,
+            encoder: header.encoder as u8,
+            intent: header.intent as u8,
+            metadata_size: header.metadata_size,
+            weighting: header.weighting as u8,
+            has_extension: header.has_extension as u8,
+        }
+    }
+}
+
+impl GfwxHeader {
+    fn to_header(self) -> Result<Header, GfwxResult> {
+        let builder = HeaderBuilder {
+            width: self.width,
+            height: self.height,
+            layers: self.layers,
+            channels: self.channels,
+            quality: self.quality,
+            chroma_scale: self.chroma_scale,
+            block_size: self.block_size,
+            filter: Filter::from_u8(self.filter).ok_or(GfwxResult::ErrorWrongValue)?,
+            encoder: Encoder::from_u8(self.encoder).ok_or(GfwxResult::ErrorWrongValue)?,
+            intent: Intent::from_u8(self.intent).ok_or(GfwxResult::ErrorWrongValue)?,
+            metadata_size: self.metadata_size,
+            quantization: Quantization::from_u8(self.quantization)
+                .ok_or(GfwxResult::ErrorWrongValue)?,
+            weighting: Weighting::from_u8(self.weighting).ok_or(GfwxResult::ErrorWrongValue)?,
+        };
+        let mut header = builder.build().map_err(|e| GfwxResult::from(&e))?;
+
+        if (self.bit_depth != 0 && self.bit_depth != header.bit_depth) || self.is_signed != 0 {
+            return Err(GfwxResult::ErrorTypeMismatch);
+        }
+        if self.version != 0 {
+            header.version = self.version;
+        }
+        header.has_extension = self.has_extension != 0;
+
+        Ok(header)
+    }
+}
+
+impl GfwxColorTransform {
+    // C callers may pass any integer, so the raw value is only turned into the enum here.
+    fn from_u32(value: u32) -> Option<Self> {
+        match value {
+            0 => Some(GfwxColorTransform::None),
+            1 => Some(GfwxColorTransform::Yuv444ToYuv444),
+            2 => Some(GfwxColorTransform::RgbToYuv),
+            3 => Some(GfwxColorTransform::RgbToA710),
+            4 => Some(GfwxColorTransform::BgrToA710),
+            5 => Some(GfwxColorTransform::RgbToYcocgR),
+            6 => Some(GfwxColorTransform::BgrToYcocgR),
+            7 => Some(GfwxColorTransform::RgbaToYcocgR),
+            8 => Some(GfwxColorTransform::BgraToYcocgR),
+            _ => None,
+        }
+    }
+
+    fn to_program(self) -> ColorTransformProgram {
+        match self {
+            GfwxColorTransform::None => ColorTransformProgram::new(),
+            GfwxColorTransform::Yuv444ToYuv444 => ColorTransformProgram::yuv444_to_yuv444(),
+            GfwxColorTransform::RgbToYuv => ColorTransformProgram::rgb_to_yuv(),
+            GfwxColorTransform::RgbToA710 => ColorTransformProgram::rgb_to_a710(),
+            GfwxColorTransform::BgrToA710 => ColorTransformProgram::bgr_to_a710(),
+            GfwxColorTransform::RgbToYcocgR => ColorTransformProgram::rgb_to_ycocg_r(),
+            GfwxColorTransform::BgrToYcocgR => ColorTransformProgram::bgr_to_ycocg_r(),
+            GfwxColorTransform::RgbaToYcocgR => ColorTransformProgram::rgba_to_ycocg_r(),
+            GfwxColorTransform::BgraToYcocgR => ColorTransformProgram::bgra_to_ycocg_r(),
+        }
+    }
+}
+
+// Panics must not unwind into C, so every entry point runs its body through this guard.
+fn guard<F>(body: F) -> GfwxResult
+where
+    F: FnOnce() -> Result<(), GfwxResult> + panic::UnwindSafe,
+{
+    match panic::catch_unwind(body) {
+        Ok(Ok(())) => GfwxResult::Ok,
+        Ok(Err(e)) => e,
+        Err(_) => GfwxResult::ErrorPanic,
+    }
+}
+
+unsafe fn as_slice<'a>(data: *const u8, len: usize) -> Result<&'a [u8], GfwxResult> {
+    if data.is_null() {
+        if len == 0 {
+            Ok(&[])
+        } else {
+            Err(GfwxResult::ErrorNullPointer)
+        }
+    } else {
+        Ok(slice::from_raw_parts(data, len))
+    }
+}
+
+unsafe fn as_slice_mut<'a>(data: *mut u8, len: usize) -> Result<&'a mut [u8], GfwxResult> {
+    if data.is_null() {
+        if len == 0 {
+            Ok(&mut [])
+        } else {
+            Err(GfwxResult::ErrorNullPointer)
+        }
+    } else {
+        Ok(slice::from_raw_parts_mut(data, len))
+    }
+}
+
+unsafe fn as_mut<'a, T>(value: *mut T) -> Result<&'a mut T, GfwxResult> {
+    value.as_mut().ok_or(GfwxResult::ErrorNullPointer)
+}
+
+/// Validates `header` and fills in the fields the codec derives itself (`version`,
+/// `bit_depth`, `is_signed`).
+///
+/// # Safety
+///
+/// `header` must be null or point to a valid `GfwxHeader`.
+#[no_mangle]
+pub unsafe extern "C" fn gfwx_header_build(header: *mut GfwxHeader) -> GfwxResult {
+    guard(|| {
+        let header = as_mut(header)?;
+        *header = GfwxHeader::from(&header.to_header()?);
+        Ok(())
+    })
+}
+
+/// Writes the 32-byte stream header into `buffer` and stores the written size in `written`.
+///
+/// # Safety
+///
+/// `header` and `written` must be null or valid pointers, `buffer` must be null or point to
+/// `buffer_size` writable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn gfwx_header_encode(
+    header: *const GfwxHeader,
+    buffer: *mut u8,
+    buffer_size: usize,
+    written: *mut usize,
+) -> GfwxResult {
+    guard(|| {
+        let header = header
+            .as_ref()
+            .ok_or(GfwxResult::ErrorNullPointer)?
+            .to_header()?;
+        let buffer = as_slice_mut(buffer, buffer_size)?;
+        let written = as_mut(written)?;
+
+        let mut remainder = &mut buffer[..];
+        header
+            .encode(&mut remainder)
+            .map_err(|_| GfwxResult::ErrorOverflow)?;
+        *written = buffer_size - remainder.len();
+        Ok(())
+    })
+}
+
+/// Parses the stream header from `data` and stores the number of consumed bytes in `read`.
+///
+/// # Safety
+///
+/// `header` and `read` must be null or valid pointers, `data` must be null or point to `size`
+/// readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn gfwx_header_decode(
+    data: *const u8,
+    size: usize,
+    header: *mut GfwxHeader,
+    read: *mut usize,
+) -> GfwxResult {
+    guard(|| {
+        let mut data = as_slice(data, size)?;
+        let header = as_mut(header)?;
+        let read = as_mut(read)?;
+
+        *header = GfwxHeader::from(&Header::decode(&mut data).map_err(|e| GfwxResult::from(&e))?);
+        *read = size - data.len();
+        Ok(())
+    })
+}
+
+/// Returns the size of the buffer `gfwx_decompress` needs for the given downsampling, or 0 if
+/// `header` is null or invalid.
+///
+/// # Safety
+///
+/// `header` must be null or point to a valid `GfwxHeader`.
+#[no_mangle]
+pub unsafe extern "C" fn gfwx_decompress_buffer_size(
+    header: *const GfwxHeader,
+    downsampling: usize,
+) -> usize {
+    let header = match header.as_ref().map(|h| h.to_header()) {
+        Some(Ok(header)) => header,
+        _ => return 0,
+    };
+    header.get_decompress_buffer_size(downsampling)
+}
+
+/// Compresses interleaved 8-bit `image` into a complete GFWX stream (header, color transform
+/// program and payload) and stores the stream size in `compressed_size`.
+///
+/// # Safety
+///
+/// `header` and `compressed_size` must be null or valid pointers, `image` must be null or
+/// point to `image_size` readable bytes and `buffer` must be null or point to `buffer_size`
+/// writable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn gfwx_compress(
+    image: *const u8,
+    image_size: usize,
+    header: *const GfwxHeader,
+    color_transform: u32,
+    buffer: *mut u8,
+    buffer_size: usize,
+    compressed_size: *mut usize,
+) -> GfwxResult {
+    guard(|| {
+        let header = header
+            .as_ref()
+            .ok_or(GfwxResult::ErrorNullPointer)?
+            .to_header()?;
+        let color_transform =
+            GfwxColorTransform::from_u32(color_transform).ok_or(GfwxResult::ErrorWrongValue)?;
+        let image = as_slice(image, image_size)?;
+        let buffer = as_slice_mut(buffer, buffer_size)?;
+        let compressed_size = as_mut(compressed_size)?;
+
+        *compressed_size =
+            crate::compress_simple(image, &header, &color_transform.to_program(), buffer)
+                .map_err(|e| GfwxResult::from(&e))?;
+        Ok(())
+    })
+}
+
+/// Decompresses a complete GFWX stream into interleaved 8-bit `buffer`. The stream header is
+/// stored in `header` and the next point of interest (0 if the stream was complete) in
+/// `next_point_of_interest`. Use `gfwx_decompress_buffer_size` to size `buffer`.
+///
+/// # Safety
+///
+/// `header` and `next_point_of_interest` must be null or valid pointers, `data` must be null or
+/// point to `size` readable bytes and `buffer` must be null or point to `buffer_size` writable
+/// bytes.
+#[no_mangle]
+pub unsafe extern "C" fn gfwx_decompress(
+    data: *const u8,
+    size: usize,
+    downsampling: usize,
+    test: bool,
+    buffer: *mut u8,
+    buffer_size: usize,
+    header: *mut GfwxHeader,
+    next_point_of_interest: *mut usize,
+) -> GfwxResult {
+    guard(|| {
+        let mut data = as_slice(data, size)?;
+        let buffer = as_slice_mut(buffer, buffer_size)?;
+        let header_out = as_mut(header)?;
+        let next_point_of_interest = as_mut(next_point_of_interest)?;
+
+        let header = Header::decode(&mut data).map_err(|e| GfwxResult::from(&e))?;
+        *header_out = GfwxHeader::from(&header);
+
+        *next_point_of_interest =
+            crate::decompress_simple(data, &header, downsampling, test, buffer)
+                .map_err(|e| GfwxResult::from(&e))?;
+        Ok(())
+    })
+}
+
+/// Checks a whole stream, starting with the header, against the checksums stored in its
+/// metadata area.
+///
+/// # Safety
+///
+/// `data` must be null or point to `size` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn gfwx_verify(data: *const u8, size: usize) -> GfwxResult {
+    guard(|| {
+        let data = as_slice(data, size)?;
+        crate::verify(data).map_err(|e| GfwxResult::from(&e))?;
+        Ok(())
+    })
+}
+
+/// Returns a static, NUL-terminated description of `result`, one of the `GFWX_RESULT_*` codes.
+#[no_mangle]
+pub extern "C" fn gfwx_result_message(result: c_int) -> *const c_char {
+    let message: &'static [u8] = match result {
+        0 => b"Success\0",
+        -1 => b"I/O error\0",
+        -2 => b"Buffer is too small\0",
+        -3 => b"Malformed stream or invalid arguments\0",
+        -4 => b"Buffer size doesn't match the header\0",
+        -5 => b"Buffer underflow detected\0",
+        -6 => b"Image data doesn't match the header\0",
+        -7 => b"Header doesn't contain GFWX magic\0",
+        -8 => b"Invalid field value in header\0",
+        -9 => b"Unexpected null pointer\0",
+        -10 => b"Internal error\0",
+        -11 => b"Stream exceeds the decode limits\0",
+        -12 => b"Checksums are missing or don't match\0",
+        -13 => b"Compression is not lossless\0",
+        _ => b"Unknown result\0",
+    };
+    message.as_ptr() as *const c_char
+}
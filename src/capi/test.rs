@@ -0,0 +1,192 @@
+use std::ptr;
+
+use super::*;
+use crate::header::QUALITY_MAX;
+
+fn rgb_header(width: u32, height: u32) -> GfwxHeader {
+    GfwxHeader {
+        width,
+        height,
+        layers: 1,
+        channels: 3,
+        quality: QUALITY_MAX,
+        chroma_scale: 1,
+        block_size: crate::header::BLOCK_DEFAULT,
+        filter: GFWX_FILTER_LINEAR,
+        quantization: GFWX_QUANTIZATION_SCALAR,
+        encoder: GFWX_ENCODER_CONTEXTUAL,
+        intent: GFWX_INTENT_RGB,
+        ..GfwxHeader::default()
+    }
+}
+
+#[test]
+fn test_constants_match_enums() {
+    assert_eq!(GFWX_FILTER_LINEAR, Filter::Linear as u8);
+    assert_eq!(GFWX_FILTER_CUBIC, Filter::Cubic as u8);
+    assert_eq!(GFWX_QUANTIZATION_SCALAR, Quantization::Scalar as u8);
+    assert_eq!(GFWX_QUANTIZATION_DEAD_ZONE, Quantization::DeadZone as u8);
+    assert_eq!(GFWX_WEIGHTING_FLAT, Weighting::Flat as u8);
+    assert_eq!(GFWX_WEIGHTING_CSF, Weighting::Csf as u8);
+    assert_eq!(GFWX_ENCODER_TURBO, Encoder::Turbo as u8);
+    assert_eq!(GFWX_ENCODER_FAST, Encoder::Fast as u8);
+    assert_eq!(GFWX_ENCODER_CONTEXTUAL, Encoder::Contextual as u8);
+    assert_eq!(GFWX_ENCODER_SCREEN, Encoder::Screen as u8);
+    assert_eq!(GFWX_INTENT_GENERIC, Intent::Generic as u8);
+    assert_eq!(GFWX_INTENT_RGB, Intent::RGB as u8);
+    assert_eq!(GFWX_INTENT_RGBA, Intent::RGBA as u8);
+    assert_eq!(GFWX_INTENT_BGR, Intent::BGR as u8);
+    assert_eq!(GFWX_INTENT_BGRA, Intent::BGRA as u8);
+    assert_eq!(GFWX_INTENT_YUV444, Intent::YUV444 as u8);
+}
+
+#[test]
+fn test_header_build() {
+    let mut header = rgb_header(12, 8);
+    assert_eq!(unsafe { gfwx_header_build(&mut header) }, GfwxResult::Ok);
+    assert_eq!(header.version, 1);
+    assert_eq!(header.bit_depth, 8);
+
+    header.quality = 0;
+    assert_eq!(
+        unsafe { gfwx_header_build(&mut header) },
+        GfwxResult::ErrorWrongValue
+    );
+    assert_eq!(
+        unsafe { gfwx_header_build(ptr::null_mut()) },
+        GfwxResult::ErrorNullPointer
+    );
+}
+
+#[test]
+fn test_compress_decompress_round_trip() {
+    let header = rgb_header(12, 8);
+    let image = (0..12 * 8 * 3).map(|i| (i % 256) as u8).collect::<Vec<_>>();
+    let mut compressed = vec![0u8; image.len() * 4];
+    let mut compressed_size = 0;
+
+    let result = unsafe {
+        gfwx_compress(
+            image.as_ptr(),
+            image.len(),
+            &header,
+            GfwxColorTransform::RgbToYuv as u32,
+            compressed.as_mut_ptr(),
+            compressed.len(),
+            &mut compressed_size,
+        )
+    };
+    assert_eq!(result, GfwxResult::Ok);
+
+    let mut decoded_header = GfwxHeader::default();
+    let mut next_point_of_interest = 1;
+    let mut decompressed = vec![0u8; unsafe { gfwx_decompress_buffer_size(&header, 0) }];
+    let result = unsafe {
+        gfwx_decompress(
+            compressed.as_ptr(),
+            compressed_size,
+            0,
+            false,
+            decompressed.as_mut_ptr(),
+            decompressed.len(),
+            &mut decoded_header,
+            &mut next_point_of_interest,
+        )
+    };
+    assert_eq!(result, GfwxResult::Ok);
+    assert_eq!(next_point_of_interest, 0);
+    assert_eq!(decoded_header.width, 12);
+    assert_eq!(decompressed, image);
+}
+
+#[test]
+fn test_decompress_reports_wrong_magic() {
+    let data = [0u8; 64];
+    let mut header = GfwxHeader::default();
+    let mut next_point_of_interest = 0;
+    let result = unsafe {
+        gfwx_decompress(
+            data.as_ptr(),
+            data.len(),
+            0,
+            false,
+            ptr::null_mut(),
+            0,
+            &mut header,
+            &mut next_point_of_interest,
+        )
+    };
+    assert_eq!(result, GfwxResult::ErrorWrongMagic);
+}
+
+#[test]
+fn test_header_decode_reports_limit_exceeded() {
+    let mut header = rgb_header(1 << 20, 1 << 20);
+    assert_eq!(unsafe { gfwx_header_build(&mut header) }, GfwxResult::Ok);
+    let mut encoded = [0u8; 32];
+    let mut written = 0;
+    assert_eq!(
+        unsafe { gfwx_header_encode(&header, encoded.as_mut_ptr(), encoded.len(), &mut written) },
+        GfwxResult::Ok
+    );
+
+    let mut decoded = GfwxHeader::default();
+    let mut read = 0;
+    let result = unsafe { gfwx_header_decode(encoded.as_ptr(), written, &mut decoded, &mut read) };
+    assert_eq!(result, GfwxResult::ErrorLimitExceeded);
+}
+
+#[test]
+fn test_verify_without_checksums() {
+    let header = rgb_header(12, 8);
+    let image = vec![0u8; 12 * 8 * 3];
+    let mut compressed = vec![0u8; image.len() * 4];
+    let mut compressed_size = 0;
+    let result = unsafe {
+        gfwx_compress(
+            image.as_ptr(),
+            image.len(),
+            &header,
+            GfwxColorTransform::None as u32,
+            compressed.as_mut_ptr(),
+            compressed.len(),
+            &mut compressed_size,
+        )
+    };
+    assert_eq!(result, GfwxResult::Ok);
+
+    let result = unsafe { gfwx_verify(compressed.as_ptr(), compressed_size) };
+    assert_eq!(result, GfwxResult::ErrorChecksum);
+}
+
+#[test]
+fn test_compress_reports_unknown_color_transform() {
+    let header = rgb_header(12, 8);
+    let image = vec![0u8; 12 * 8 * 3];
+    let mut compressed = vec![0u8; image.len() * 4];
+    let mut compressed_size = 0;
+    let result = unsafe {
+        gfwx_compress(
+            image.as_ptr(),
+            image.len(),
+            &header,
+            GfwxColorTransform::BgraToYcocgR as u32 + 1,
+            compressed.as_mut_ptr(),
+            compressed.len(),
+            &mut compressed_size,
+        )
+    };
+    assert_eq!(result, GfwxResult::ErrorWrongValue);
+}
+
+#[test]
+fn test_result_message() {
+    let message = |result| unsafe { std::ffi::CStr::from_ptr(gfwx_result_message(result)) };
+    assert_eq!(message(GfwxResult::Ok as c_int).to_bytes(), b"Success");
+    assert_eq!(
+        message(GfwxResult::ErrorLossy as c_int).to_bytes(),
+        b"Compression is not lossless"
+    );
+    assert_eq!(message(1).to_bytes(), b"Unknown result");
+    assert_eq!(message(-14).to_bytes(), b"Unknown result");
+}
@@ -0,0 +1,319 @@
+use alloc::{string::String, vec, vec::Vec};
+use core::convert::TryFrom;
+
+use byteorder::{ByteOrder, LittleEndian};
+
+use crate::color_transform::ColorTransformProgram;
+use crate::compress::step_levels;
+use crate::errors::{Error, HeaderErr};
+use crate::header::{DecodeLimits, Encoder, Header, HEADER_SIZE};
+use crate::io::{IOError, Read, Write};
+use crate::palette;
+
+#[cfg(test)]
+mod test;
+
+const MAGIC: u32 = 'G' as u32 | (('F' as u32) << 8) | (('C' as u32) << 16) | (('S' as u32) << 24);
+const ALGORITHM_CRC32: u32 = 1;
+
+/// Part of the stream whose checksum doesn't match.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ChecksumLocation {
+    Header,
+    BlockTable { step: usize },
+    Block { step: usize, block_index: usize },
+    Image,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct LevelChecksums {
+    pub step: usize,
+    pub block_table: u32,
+    pub blocks: Vec<u32>,
+}
+
+/// CRC32 checksums of a stream, stored as an extension in the metadata area right after the
+/// header. Decoders that don't know about the extension skip it together with the metadata.
+///
+/// Layout (little-endian `u32` words): magic, algorithm, header checksum, image checksum,
+/// level count, then for every step level in stream order its block count, block table
+/// checksum and block checksums.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Checksums {
+    pub header: u32,
+    /// Checksum of the interleaved image decoded without downsampling.
+    pub image: u32,
+    pub levels: Vec<LevelChecksums>,
+}
+
+impl Checksums {
+    /// Size of the encoded extension in bytes for images described by `header`.
+    pub fn encoded_size(header: &Header) -> Result<usize, Error> {
+        let words = step_levels(header)?
+            .iter()
+            .fold(5, |words, &(_, block_count)| words + 2 + block_count);
+        Ok(words * 4)
+    }
+
+    /// Computes the checksums of a complete stream, starting with the header.
+    pub fn compute(mut data: &[u8]) -> Result<Self, Error> {
+        let header_bytes = data;
+        let header = Header::decode_with_limits(&mut data, &DecodeLimits::unlimited())?;
+        let (_, payload) = split_metadata(data, &header)?;
+        let levels = Level::split(
+            &header,
+            skip_palette(skip_color_transform(payload, &header)?, &header),
+        )?;
+
+        let mut buffer = vec![0; header.get_decompress_buffer_size(0)];
+        crate::decompress_simple(data, &header, 0, false, &mut buffer)?;
+
+        Ok(Checksums {
+            header: crc32fast::hash(&header_bytes[..HEADER_SIZE]),
+            image: crc32fast::hash(&buffer),
+            levels: levels
+                .iter()
+                .map(|level| LevelChecksums {
+                    step: level.step,
+                    block_table: crc32fast::hash(level.block_table),
+                    blocks: level
+                        .blocks
+                        .iter()
+                        .map(|block| crc32fast::hash(block))
+                        .collect(),
+                })
+                .collect(),
+        })
+    }
+
+    /// Reads the extension from the metadata area. Returns `None` if the metadata doesn't
+    /// start with the extension.
+    pub fn decode(mut metadata: &[u8], header: &Header) -> Result<Option<Self>, Error> {
+        if metadata.len() < 8
+            || LittleEndian::read_u32(metadata) != MAGIC
+            || LittleEndian::read_u32(&metadata[4..]) != ALGORITHM_CRC32
+        {
+            return Ok(None);
+        }
+        metadata = &metadata[8..];
+
+        let checksum_header = metadata.read_u32()?;
+        let image = metadata.read_u32()?;
+        let level_count = metadata.read_u32()? as usize;
+
+        let expected_levels = step_levels(header)?;
+        if level_count != expected_levels.len() {
+            return Err(Error::ChecksumMismatch(ChecksumLocation::Header));
+        }
+
+        let mut levels = Vec::with_capacity(level_count);
+        for (step, expected_block_count) in expected_levels {
+            let block_count = metadata.read_u32()? as usize;
+            if block_count != expected_block_count {
+                return Err(Error::ChecksumMismatch(ChecksumLocation::Header));
+            }
+            let block_table = metadata.read_u32()?;
+            let blocks = (0..block_count)
+                .map(|_| metadata.read_u32())
+                .collect::<Result<Vec<_>, _>>()?;
+            levels.push(LevelChecksums {
+                step,
+                block_table,
+                blocks,
+            });
+        }
+
+        Ok(Some(Checksums {
+            header: checksum_header,
+            image,
+            levels,
+        }))
+    }
+
+    pub fn encode(&self, buffer: &mut impl Write) -> Result<(), IOError> {
+        buffer.write_u32(MAGIC)?;
+        buffer.write_u32(ALGORITHM_CRC32)?;
+        buffer.write_u32(self.header)?;
+        buffer.write_u32(self.image)?;
+        buffer.write_u32(self.levels.len() as u32)?;
+        for level in &self.levels {
+            buffer.write_u32(level.blocks.len() as u32)?;
+            buffer.write_u32(level.block_table)?;
+            for &block in &level.blocks {
+                buffer.write_u32(block)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks the header and every block table and block present in `data`, which starts
+    /// right after the header. Missing (truncated) levels and blocks are skipped. Returns
+    /// `true` if the stream is complete.
+    fn check_stream(&self, header: &Header, data: &[u8]) -> Result<bool, Error> {
+        let mut header_bytes = Vec::with_capacity(HEADER_SIZE);
+        header.encode(&mut header_bytes)?;
+        if crc32fast::hash(&header_bytes) != self.header {
+            return Err(Error::ChecksumMismatch(ChecksumLocation::Header));
+        }
+
+        let (_, payload) = split_metadata(data, header)?;
+        let levels = Level::split(
+            header,
+            skip_palette(skip_color_transform(payload, header)?, header),
+        )?;
+
+        let mut is_complete = levels.len() == self.levels.len();
+        for (level, checksums) in levels.iter().zip(&self.levels) {
+            if crc32fast::hash(level.block_table) != checksums.block_table {
+                return Err(Error::ChecksumMismatch(ChecksumLocation::BlockTable {
+                    step: level.step,
+                }));
+            }
+            for (block_index, (block, &checksum)) in
+                level.blocks.iter().zip(&checksums.blocks).enumerate()
+            {
+                if crc32fast::hash(block) != checksum {
+                    return Err(Error::ChecksumMismatch(ChecksumLocation::Block {
+                        step: level.step,
+                        block_index,
+                    }));
+                }
+            }
+            is_complete &= level.blocks.len() == checksums.blocks.len();
+        }
+
+        Ok(is_complete)
+    }
+}
+
+/// Same as `compress_simple`, but stores `Checksums` of the result in the metadata area.
+/// `header.metadata_size` is replaced by the size of the extension.
+pub fn compress_simple_with_checksums(
+    image: &[u8],
+    header: &Header,
+    color_transform: &ColorTransformProgram,
+    buffer: &mut [u8],
+) -> Result<usize, Error> {
+    let mut header = header.clone();
+    let metadata_size = Checksums::encoded_size(&header)?;
+    header.metadata_size = u32::try_from(metadata_size)
+        .map_err(|_| HeaderErr::WrongValue(String::from("Checksums don't fit into metadata")))?;
+
+    let size = crate::compress_simple(image, &header, color_transform, buffer)?;
+    let checksums = Checksums::compute(&buffer[..size])?;
+    checksums.encode(&mut &mut buffer[HEADER_SIZE..HEADER_SIZE + metadata_size])?;
+
+    Ok(size)
+}
+
+/// Same as `decompress_simple`, but first checks the `Checksums` stored in the metadata area
+/// against the header and the part of the stream that is present. If the whole image was
+/// decoded, its checksum is checked too.
+pub fn decompress_simple_verified(
+    data: &[u8],
+    header: &Header,
+    downsampling: usize,
+    test: bool,
+    buffer: &mut [u8],
+) -> Result<usize, Error> {
+    let (metadata, _) = split_metadata(data, header)?;
+    let checksums = Checksums::decode(metadata, header)?.ok_or(Error::ChecksumMissing)?;
+    let is_complete = checksums.check_stream(header, data)?;
+
+    let next_point_of_interest =
+        crate::decompress_simple(data, header, downsampling, test, buffer)?;
+
+    if is_complete && downsampling == 0 && !test {
+        let image_size = header.get_decompress_buffer_size(0);
+        if crc32fast::hash(&buffer[..image_size]) != checksums.image {
+            return Err(Error::ChecksumMismatch(ChecksumLocation::Image));
+        }
+    }
+
+    Ok(next_point_of_interest)
+}
+
+/// Checks a whole stream, starting with the header, against the `Checksums` it carries,
+/// including the checksum of the decoded image if the stream is complete.
+pub fn verify(mut data: &[u8]) -> Result<Header, Error> {
+    let header = Header::decode(&mut data)?;
+    let mut buffer = vec![0; header.get_decompress_buffer_size(0)];
+    decompress_simple_verified(data, &header, 0, false, &mut buffer)?;
+    Ok(header)
+}
+
+struct Level<'a> {
+    step: usize,
+    block_table: &'a [u8],
+    blocks: Vec<&'a [u8]>,
+}
+
+impl<'a> Level<'a> {
+    /// Splits the payload following the color transform program into step levels. A
+    /// truncated level is returned with the blocks that are present; levels without a
+    /// complete block table are omitted.
+    fn split(header: &Header, mut payload: &'a [u8]) -> Result<Vec<Self>, Error> {
+        let mut levels = vec![];
+        for (step, block_count) in step_levels(header)? {
+            let block_table_size = block_count.saturating_mul(4);
+            if payload.len() < block_table_size {
+                break;
+            }
+            let (block_table, mut blocks_buffer) = payload.split_at(block_table_size);
+
+            let mut blocks = Vec::with_capacity(block_count);
+            for block_size in block_table.chunks(4) {
+                let block_size = (LittleEndian::read_u32(block_size) as usize).saturating_mul(4);
+                if blocks_buffer.len() < block_size {
+                    break;
+                }
+                let (block, rest) = blocks_buffer.split_at(block_size);
+                blocks.push(block);
+                blocks_buffer = rest;
+            }
+
+            let is_complete = blocks.len() == block_count;
+            levels.push(Level {
+                step,
+                block_table,
+                blocks,
+            });
+            if !is_complete {
+                break;
+            }
+            payload = blocks_buffer;
+        }
+        Ok(levels)
+    }
+}
+
+/// Splits data following the header into the metadata area and the rest of the stream.
+pub(crate) fn split_metadata<'a>(
+    data: &'a [u8],
+    header: &Header,
+) -> Result<(&'a [u8], &'a [u8]), Error> {
+    let metadata_size = header.metadata_size as usize;
+    if data.len() < metadata_size {
+        return Err(IOError::UnexpectedEof.into());
+    }
+    Ok(data.split_at(metadata_size))
+}
+
+/// Skips the color transform program at the start of `payload`.
+pub(crate) fn skip_color_transform<'a>(
+    mut payload: &'a [u8],
+    header: &Header,
+) -> Result<&'a [u8], Error> {
+    let mut is_chroma = vec![false; header.layers as usize * header.channels as usize];
+    ColorTransformProgram::decode(&mut payload, &mut is_chroma)?;
+    Ok(payload)
+}
+
+/// Skips the palette section of `Encoder::Screen` streams, which only the image checksum
+/// covers. A truncated section leaves no levels.
+fn skip_palette<'a>(payload: &'a [u8], header: &Header) -> &'a [u8] {
+    if header.encoder != Encoder::Screen {
+        return payload;
+    }
+    palette::split_section(payload).map_or(&[], |(_, levels)| levels)
+}
@@ -0,0 +1,212 @@
+use super::*;
+use crate::header::HeaderBuilder;
+
+fn compress_with_checksums(header: &Header) -> Vec<u8> {
+    let image = (0..header.get_image_size())
+        .map(|i| ((i * 7) ^ (i >> 4)) as u8)
+        .collect::<Vec<_>>();
+    let mut buffer = vec![0; image.len() * 4];
+    let size = compress_simple_with_checksums(
+        &image,
+        header,
+        &ColorTransformProgram::rgb_to_yuv(),
+        &mut buffer,
+    )
+    .unwrap();
+    buffer.truncate(size);
+    buffer
+}
+
+/// Offset of the first block table in `stream`.
+fn payload_offset(stream: &[u8]) -> usize {
+    let mut data = stream;
+    let header = Header::decode(&mut data).unwrap();
+    let (_, payload) = split_metadata(data, &header).unwrap();
+    stream.len() - skip_color_transform(payload, &header).unwrap().len()
+}
+
+#[test]
+fn test_checksums_round_trip() {
+    let header = HeaderBuilder {
+        width: 32,
+        height: 24,
+        ..HeaderBuilder::test_default()
+    }
+    .build()
+    .unwrap();
+    let stream = compress_with_checksums(&header);
+
+    let decoded_header = verify(&stream).unwrap();
+    assert_eq!(
+        decoded_header.metadata_size as usize,
+        Checksums::encoded_size(&header).unwrap()
+    );
+
+    let metadata = &stream[HEADER_SIZE..HEADER_SIZE + decoded_header.metadata_size as usize];
+    let checksums = Checksums::decode(metadata, &decoded_header)
+        .unwrap()
+        .unwrap();
+    assert_eq!(checksums, Checksums::compute(&stream).unwrap());
+
+    let mut encoded = vec![];
+    checksums.encode(&mut encoded).unwrap();
+    assert_eq!(encoded, metadata);
+}
+
+#[test]
+fn test_checksums_are_skipped_by_plain_decoder() {
+    let header = HeaderBuilder {
+        width: 32,
+        height: 24,
+        ..HeaderBuilder::test_default()
+    }
+    .build()
+    .unwrap();
+    let stream = compress_with_checksums(&header);
+
+    let mut data = stream.as_slice();
+    let decoded_header = Header::decode(&mut data).unwrap();
+    let mut plain = vec![0; header.get_decompress_buffer_size(0)];
+    crate::decompress_simple(data, &decoded_header, 0, false, &mut plain).unwrap();
+    let mut verified = vec![0; header.get_decompress_buffer_size(0)];
+    decompress_simple_verified(data, &decoded_header, 0, false, &mut verified).unwrap();
+
+    assert_eq!(plain, verified);
+}
+
+#[test]
+fn test_checksums_missing() {
+    let header = HeaderBuilder {
+        width: 32,
+        height: 24,
+        ..HeaderBuilder::test_default()
+    }
+    .build()
+    .unwrap();
+    let image = vec![0; header.get_image_size()];
+    let mut buffer = vec![0; image.len() * 4];
+    let size = crate::compress_simple(&image, &header, &ColorTransformProgram::new(), &mut buffer)
+        .unwrap();
+
+    match verify(&buffer[..size]) {
+        Err(Error::ChecksumMissing) => (),
+        r => panic!("stream without checksums must be reported: {:?}", r),
+    }
+}
+
+#[test]
+fn test_damaged_header() {
+    let header = HeaderBuilder {
+        width: 32,
+        height: 24,
+        ..HeaderBuilder::test_default()
+    }
+    .build()
+    .unwrap();
+    let mut stream = compress_with_checksums(&header);
+    // quality is stored in the 24 bits starting at byte 20
+    stream[22] ^= 0x02;
+
+    match verify(&stream) {
+        Err(Error::ChecksumMismatch(ChecksumLocation::Header)) => (),
+        r => panic!("damaged header must be reported: {:?}", r),
+    }
+}
+
+#[test]
+fn test_damaged_block_table() {
+    let header = HeaderBuilder {
+        width: 32,
+        height: 24,
+        ..HeaderBuilder::test_default()
+    }
+    .build()
+    .unwrap();
+    let mut stream = compress_with_checksums(&header);
+    let offset = payload_offset(&stream);
+    stream[offset] ^= 0x01;
+
+    let (first_step, _) = step_levels(&header).unwrap()[0];
+    match verify(&stream) {
+        Err(Error::ChecksumMismatch(ChecksumLocation::BlockTable { step })) => {
+            assert_eq!(step, first_step)
+        }
+        r => panic!("damaged block table must be reported: {:?}", r),
+    }
+}
+
+#[test]
+fn test_damaged_block() {
+    let header = HeaderBuilder {
+        width: 32,
+        height: 24,
+        ..HeaderBuilder::test_default()
+    }
+    .build()
+    .unwrap();
+    let mut stream = compress_with_checksums(&header);
+    let offset = payload_offset(&stream);
+
+    let (expected_step, expected_block_index, block_offset) = {
+        let levels = Level::split(&header, &stream[offset..]).unwrap();
+        let level = levels.last().unwrap();
+        let block_index = level
+            .blocks
+            .iter()
+            .rposition(|block| !block.is_empty())
+            .unwrap();
+        let block = level.blocks[block_index];
+        (
+            level.step,
+            block_index,
+            block.as_ptr() as usize - stream.as_ptr() as usize,
+        )
+    };
+    stream[block_offset] ^= 0x10;
+
+    match verify(&stream) {
+        Err(Error::ChecksumMismatch(ChecksumLocation::Block { step, block_index })) => {
+            assert_eq!(step, expected_step);
+            assert_eq!(block_index, expected_block_index);
+        }
+        r => panic!("damaged block must be reported: {:?}", r),
+    }
+}
+
+#[test]
+fn test_damaged_image_checksum() {
+    let header = HeaderBuilder {
+        width: 32,
+        height: 24,
+        ..HeaderBuilder::test_default()
+    }
+    .build()
+    .unwrap();
+    let mut stream = compress_with_checksums(&header);
+    // image checksum is the fourth word of the extension
+    stream[HEADER_SIZE + 12] ^= 0x01;
+
+    match verify(&stream) {
+        Err(Error::ChecksumMismatch(ChecksumLocation::Image)) => (),
+        r => panic!("image checksum mismatch must be reported: {:?}", r),
+    }
+}
+
+#[test]
+fn test_truncated_stream_is_verified_partially() {
+    let header = HeaderBuilder {
+        width: 32,
+        height: 24,
+        ..HeaderBuilder::test_default()
+    }
+    .build()
+    .unwrap();
+    let stream = compress_with_checksums(&header);
+    let mut data = &stream[..stream.len() * 3 / 4];
+    let decoded_header = Header::decode(&mut data).unwrap();
+
+    let mut buffer = vec![0; header.get_decompress_buffer_size(0)];
+    let next_point_of_interest =
+        decompress_simple_verified(data, &decoded_header, 0, false, &mut buffer).unwrap();
+    assert_ne!(next_point_of_interest, 0);
+}
@@ -0,0 +1,119 @@
+//! Choice of the color transform program giving the smallest stream for an image.
+
+use alloc::{vec, vec::Vec};
+
+use super::ColorTransformProgram;
+use crate::context::Compressor;
+use crate::errors::Error;
+use crate::header::{Header, Intent, HEADER_SIZE};
+use crate::io::IOError;
+
+/// Number of pixels of the downsampled copy the candidates are compared on, at most.
+const SAMPLE_PIXELS: usize = 1 << 16;
+
+impl ColorTransformProgram {
+    /// Programs `auto` chooses from: no transform, and the presets matching the channel order
+    /// of `intent` for images with at least 3 channels.
+    pub fn auto_candidates(intent: Intent, channels: u16) -> Vec<ColorTransformProgram> {
+        let mut candidates = vec![ColorTransformProgram::new()];
+        if channels >= 3 {
+            match intent {
+                Intent::YUV444 => candidates.push(ColorTransformProgram::yuv444_to_yuv444()),
+                Intent::BGR | Intent::BGRA => candidates.extend(vec![
+                    ColorTransformProgram::rgb_to_yuv(),
+                    ColorTransformProgram::bgr_to_a710(),
+                    ColorTransformProgram::bgr_to_ycocg_r(),
+                ]),
+                _ => candidates.extend(vec![
+                    ColorTransformProgram::rgb_to_yuv(),
+                    ColorTransformProgram::rgb_to_a710(),
+                    ColorTransformProgram::rgb_to_ycocg_r(),
+                ]),
+            }
+        }
+        candidates
+    }
+
+    /// Program out of `auto_candidates` giving the smallest stream for `image`, see `select`.
+    pub fn auto(image: &[u8], header: &Header) -> Result<Self, Error> {
+        let mut candidates = ColorTransformProgram::auto_candidates(header.intent, header.channels);
+        let index = ColorTransformProgram::select(image, header, &candidates)?;
+        Ok(candidates.swap_remove(index))
+    }
+
+    /// Index of the program out of `candidates` giving the smallest stream for the interleaved
+    /// `image` with the dimensions and the quality from `header`. The programs are compared on a
+    /// copy of the image downsampled to at most 2^16 pixels, the first one wins ties. Fails with
+    /// `Error::NoCandidates` if `candidates` is empty.
+    pub fn select(
+        image: &[u8],
+        header: &Header,
+        candidates: &[ColorTransformProgram],
+    ) -> Result<usize, Error> {
+        if candidates.is_empty() {
+            return Err(Error::NoCandidates);
+        }
+        if image.len() != header.get_image_size() {
+            return Err(Error::BufferSize {
+                expected: header.get_image_size(),
+                actual: image.len(),
+            });
+        }
+        if candidates.len() == 1 {
+            return Ok(0);
+        }
+
+        let (sample, sample_header) = downsample(image, header);
+        let mut buffer = vec![0; HEADER_SIZE + sample.len() * 4];
+        let mut best = (usize::MAX, 0);
+        for (index, candidate) in candidates.iter().enumerate() {
+            let mut compressor = Compressor::new(&sample_header, candidate)?;
+            // the header, the program and the block tables of tiny images may not fit
+            let size = loop {
+                match compressor.compress(&sample, &mut buffer) {
+                    Err(Error::BlockOverflow { .. })
+                    | Err(Error::BlockTableOverflow { .. })
+                    | Err(Error::IOErr(IOError::WriteZero)) => {
+                        buffer.resize(buffer.len() * 2, 0);
+                    }
+                    result => break result?,
+                }
+            };
+            if size < best.0 {
+                best = (size, index);
+            }
+        }
+        Ok(best.1)
+    }
+}
+
+/// Every `step`-th pixel of every `step`-th row of every layer of `image`, with the smallest
+/// power of two `step` leaving at most `SAMPLE_PIXELS` pixels, and the header describing it.
+/// Only the dimensions of the header change, so the candidates are coded like the image.
+pub(super) fn downsample(image: &[u8], header: &Header) -> (Vec<u8>, Header) {
+    let width = header.width as usize;
+    let height = header.height as usize;
+    let channels = header.channels as usize;
+
+    let mut step = 1;
+    while width.div_ceil(step) * height.div_ceil(step) > SAMPLE_PIXELS {
+        step *= 2;
+    }
+    let mut sample_header = header.clone();
+    sample_header.width = width.div_ceil(step) as u32;
+    sample_header.height = height.div_ceil(step) as u32;
+    sample_header.channel_size = width.div_ceil(step) * height.div_ceil(step);
+    sample_header.image_size = sample_header.channel_size * usize::from(header.layers) * channels;
+    sample_header.metadata_size = 0;
+    sample_header.has_extension = false;
+
+    let mut sample = Vec::with_capacity(sample_header.get_image_size());
+    for layer in image.chunks_exact(width * height * channels) {
+        for row in layer.chunks_exact(width * channels).step_by(step) {
+            for pixel in row.chunks_exact(channels).step_by(step) {
+                sample.extend_from_slice(pixel);
+            }
+        }
+    }
+    (sample, sample_header)
+}
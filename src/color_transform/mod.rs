@@ -1,12 +1,19 @@
-use std::{cmp, io, u8};
+use alloc::{vec, vec::Vec};
+use core::{cmp, u8};
 
 use num_traits::cast::NumCast;
 
 use crate::bits::{BitsIOReader, BitsIOWriter, BitsReader, BitsWriter};
 use crate::encode::{signed_code, signed_decode};
-use crate::errors::DecompressError;
-use crate::header;
-
+use crate::errors::Error;
+use crate::header::{self, DecodeLimits};
+use crate::image_view::{
+    check_planes, ChannelRows, ChannelRowsMut, ImageView, ImageViewMut, Plane, PlaneMut,
+};
+use crate::io::{IOError, Read, Write};
+
+mod auto;
+mod syntax;
 #[cfg(test)]
 mod test;
 
@@ -56,8 +63,8 @@ impl ChannelTransformBuilder {
 
     pub fn set_denominator(&mut self, denominator: isize) -> &mut Self {
         assert!(
-            self.denominator > 0,
-            "Denominator should be positive integer"
+            denominator > 0 && denominator <= i16::MAX as isize,
+            "Denominator should be positive 16-bit integer"
         );
         self.denominator = denominator;
         self
@@ -194,12 +201,82 @@ impl ColorTransformProgram {
         program
     }
 
-    pub fn decode(
-        mut buffer: &mut impl io::Read,
+    /// Stores rgb data as YCoCg-R (real order is CoCgY), which is exactly reversible.
+    /// performs the following:
+    /// R -= B (chroma); G -= (B * 2 + R) / 2 (chroma); B += (R + G) / 2 (luma)
+    ///
+    /// The luma rounds once instead of twice, so it may differ by one from the textbook
+    /// YCoCg-R, which needs floor division.
+    pub fn rgb_to_ycocg_r() -> Self {
+        Self::ycocg_r(0, 1, 2)
+    }
+
+    /// Stores bgr data as YCoCg-R (real order is YCgCo), see `rgb_to_ycocg_r`.
+    pub fn bgr_to_ycocg_r() -> Self {
+        Self::ycocg_r(2, 1, 0)
+    }
+
+    /// Same as `rgb_to_ycocg_r`, the alpha channel is stored as is.
+    pub fn rgba_to_ycocg_r() -> Self {
+        Self::rgb_to_ycocg_r()
+    }
+
+    /// Same as `bgr_to_ycocg_r`, the alpha channel is stored as is.
+    pub fn bgra_to_ycocg_r() -> Self {
+        Self::bgr_to_ycocg_r()
+    }
+
+    /// YCoCg-R lifting of the channels `r`, `g` and `b` into Co, Cg and Y respectively.
+    fn ycocg_r(r: usize, g: usize, b: usize) -> Self {
+        let mut program = Self::new();
+
+        program
+            .add_channel_transform(
+                ChannelTransformBuilder::with_dest_channel(r)
+                    .add_channel_factor(b, -1)
+                    .set_chroma()
+                    .build(),
+            )
+            .add_channel_transform(
+                ChannelTransformBuilder::with_dest_channel(g)
+                    .add_channel_factor(b, -2)
+                    .add_channel_factor(r, -1)
+                    .set_denominator(2)
+                    .set_chroma()
+                    .build(),
+            )
+            .add_channel_transform(
+                ChannelTransformBuilder::with_dest_channel(b)
+                    .add_channel_factor(r, 1)
+                    .add_channel_factor(g, 1)
+                    .set_denominator(2)
+                    .build(),
+            );
+        program
+    }
+
+    pub fn decode(buffer: &mut impl Read, is_chroma: &mut [bool]) -> Result<Self, Error> {
+        ColorTransformProgram::decode_with_limits(buffer, is_chroma, &DecodeLimits::default())
+    }
+
+    pub fn decode_with_limits(
+        buffer: &mut impl Read,
         is_chroma: &mut [bool],
-    ) -> Result<Self, DecompressError> {
-        let mut stream = BitsIOReader::new(&mut buffer);
+        limits: &DecodeLimits,
+    ) -> Result<Self, Error> {
+        let mut stream = BitsIOReader::new(buffer);
         let mut color_transform_program = ColorTransformProgram::new();
+        let mut program_len = 0;
+        let mut check_program_len = || {
+            program_len += 1;
+            if program_len > limits.max_transform_program_len {
+                Err(Error::TransformLength {
+                    max: limits.max_transform_program_len,
+                })
+            } else {
+                Ok(())
+            }
+        };
         loop {
             let dest_channel = signed_decode(&mut stream, 2)?;
 
@@ -207,9 +284,13 @@ impl ColorTransformProgram {
                 break;
             }
             let dest_channel = dest_channel as usize;
+            check_program_len()?;
 
             if dest_channel >= is_chroma.len() {
-                return Err(DecompressError::Malformed);
+                return Err(Error::TransformChannel {
+                    channel: dest_channel,
+                    channels: is_chroma.len(),
+                });
             }
 
             let mut channel_transform_builder =
@@ -221,15 +302,21 @@ impl ColorTransformProgram {
                     break;
                 }
                 if src_channel as usize >= is_chroma.len() {
-                    return Err(DecompressError::Malformed);
+                    return Err(Error::TransformChannel {
+                        channel: src_channel as usize,
+                        channels: is_chroma.len(),
+                    });
                 }
+                check_program_len()?;
 
                 let factor = signed_decode(&mut stream, 2)?;
                 channel_transform_builder.add_channel_factor(src_channel as usize, factor as isize);
             }
             let denominator = signed_decode(&mut stream, 2)?;
-            if denominator == 0 {
-                return Err(DecompressError::Malformed);
+            if denominator <= 0 || denominator > i16::MAX as i32 {
+                return Err(Error::TransformDenominator {
+                    channel: dest_channel,
+                });
             }
             channel_transform_builder.set_denominator(denominator as isize);
 
@@ -258,16 +345,34 @@ impl ColorTransformProgram {
             .any(|c| c.denominator > 1 || !c.channel_factors.is_empty())
     }
 
+    /// First channel whose transform can't be undone exactly out of `channels` ones: one outside
+    /// of them, transformed twice, reading itself or having a zero denominator. The transforms
+    /// wrap around on overflow, so every other program is an exact bijection of the values.
+    pub fn find_non_invertible_channel(&self, channels: usize) -> Option<usize> {
+        let mut is_transformed = vec![false; channels];
+        for channel_transform in &self.channel_transforms {
+            let dest_channel = channel_transform.dest_channel;
+            if dest_channel >= channels
+                || is_transformed[dest_channel]
+                || channel_transform.denominator == 0
+                || channel_transform
+                    .channel_factors
+                    .iter()
+                    .any(|f| f.src_channel >= channels || f.src_channel == dest_channel)
+            {
+                return Some(dest_channel);
+            }
+            is_transformed[dest_channel] = true;
+        }
+        None
+    }
+
     pub fn iter(&self) -> impl DoubleEndedIterator<Item = &ChannelTransform> {
         self.channel_transforms.iter()
     }
 
-    pub fn encode(
-        &self,
-        channels: usize,
-        mut buffer: &mut impl io::Write,
-    ) -> io::Result<Vec<bool>> {
-        let mut stream = BitsIOWriter::new(&mut buffer);
+    pub fn encode(&self, channels: usize, buffer: &mut impl Write) -> Result<Vec<bool>, IOError> {
+        let mut stream = BitsIOWriter::new(buffer);
         let mut is_chroma = vec![false; channels];
 
         for channel_transform in &self.channel_transforms {
@@ -292,182 +397,346 @@ impl ColorTransformProgram {
         Ok(is_chroma)
     }
 
-    fn transform_base<T, F>(
-        &self,
-        image: &[T],
-        header: &header::Header,
-        aux: &mut [i16],
-        channels_in_index: usize,
-        channel_layer: F,
-    ) where
-        T: Into<i16> + Copy,
-        F: Fn(usize, usize, usize) -> usize,
+    fn transform_base<I>(&self, image: &I, header: &header::Header, aux: &mut [i16])
+    where
+        I: ChannelRows + ?Sized,
+        I::Sample: Into<i16> + Copy,
     {
-        assert!(aux.len() >= image.len());
-
         let boost = header.get_boost() as i16;
-        let channels = header.channels as usize;
+        let width = header.width as usize;
+        let height = header.height as usize;
+        let channels = header.channels as usize * header.layers as usize;
         let channel_size = header.get_channel_size();
-        let mut is_channel_transformed = vec![false; channels * header.layers as usize];
-
-        for channel_transform in &self.channel_transforms {
-            let dest_base = channel_transform.dest_channel * channel_size;
+        assert!(aux.len() >= channels * channel_size);
+
+        let size = (width, height);
+
+        for (index, channel_transform) in self.channel_transforms.iter().enumerate() {
+            let is_channel_transformed = |channel: usize| {
+                self.channel_transforms[..index]
+                    .iter()
+                    .any(|t| t.dest_channel == channel)
+            };
+            let (before, rest) = aux.split_at_mut(channel_transform.dest_channel * channel_size);
+            let (dest, after) = rest.split_at_mut(channel_size);
+            let channel_plane = |channel: usize| {
+                let base = channel * channel_size;
+                if channel < channel_transform.dest_channel {
+                    &before[base..base + channel_size]
+                } else {
+                    let base = base - (channel_transform.dest_channel + 1) * channel_size;
+                    &after[base..base + channel_size]
+                }
+            };
 
             for channel_factor in &channel_transform.channel_factors {
-                if is_channel_transformed[channel_factor.src_channel] {
-                    for i in 0..channel_size {
-                        aux[dest_base + i] += aux[channel_factor.src_channel * channel_size + i]
-                            * channel_factor.factor as i16;
+                let src_channel = channel_factor.src_channel;
+                if is_channel_transformed(src_channel) {
+                    for (value, &src) in dest.iter_mut().zip(channel_plane(src_channel)) {
+                        *value = value.wrapping_add(src.wrapping_mul(channel_factor.factor as i16));
                     }
                 } else {
-                    let boosted_factor = channel_factor.factor as i16 * boost;
-                    let layer = channel_layer(channel_factor.src_channel, channels, channel_size);
-                    for i in 0..channel_size {
-                        aux[dest_base + i] +=
-                            image[layer + i * channels_in_index].into() * boosted_factor;
-                    }
+                    let boosted_factor = (channel_factor.factor as i16).wrapping_mul(boost);
+                    zip_channel(dest, image, src_channel, size, |value, sample| {
+                        *value = value.wrapping_add(sample.into().wrapping_mul(boosted_factor));
+                    });
                 }
             }
 
-            let layer = channel_layer(channel_transform.dest_channel, channels, channel_size);
-            for i in 0..channel_size {
-                aux[dest_base + i] /= channel_transform.denominator as i16;
-                aux[dest_base + i] += image[layer + i * channels_in_index].into() * boost;
-            }
-
-            is_channel_transformed[channel_transform.dest_channel] = true;
+            let denominator = channel_transform.denominator as i16;
+            zip_channel(
+                dest,
+                image,
+                channel_transform.dest_channel,
+                size,
+                |value, sample| {
+                    *value = value
+                        .wrapping_div(denominator)
+                        .wrapping_add(sample.into() * boost);
+                },
+            );
         }
 
-        for (channel, is_transformed) in is_channel_transformed.iter().enumerate() {
-            if !is_transformed {
-                let dest_base = channel * channel_size;
-                let layer = channel_layer(channel, channels, channel_size);
-                for i in 0..channel_size {
-                    aux[dest_base + i] = image[layer + i * channels_in_index].into() * boost;
-                }
+        for channel in 0..channels {
+            if !self
+                .channel_transforms
+                .iter()
+                .any(|t| t.dest_channel == channel)
+            {
+                let dest = &mut aux[channel * channel_size..(channel + 1) * channel_size];
+                zip_channel(dest, image, channel, size, |value, sample| {
+                    *value = sample.into() * boost;
+                });
             }
         }
     }
 
-    pub fn transform_and_to_planar<T>(
+    pub fn transform_and_to_planar<T>(&self, image: &[T], header: &header::Header, aux: &mut [i16])
+    where
+        T: Into<i16> + Copy,
+    {
+        let width = header.width as usize;
+        let channels = header.channels as usize;
+        let image = ImageView::with_layers(
+            image,
+            width,
+            header.height as usize,
+            header.layers as usize,
+            channels,
+            width * channels,
+        )
+        .expect("image is smaller than the header requires");
+        self.transform_base(&image, header, aux);
+    }
+
+    /// Same as `transform_and_to_planar`, but reads the image through a possibly strided view.
+    /// The view must have the dimensions from `header`.
+    pub fn transform_view<T>(
         &self,
-        image: &[T],
+        image: &ImageView<'_, T>,
         header: &header::Header,
-        mut aux: &mut [i16],
-    ) where
+        aux: &mut [i16],
+    ) -> Result<(), Error>
+    where
         T: Into<i16> + Copy,
     {
-        ColorTransformProgram::transform_base(
-            &self,
-            &image,
-            &header,
-            &mut aux,
+        image.shape().check(
+            header.width as usize,
+            header.height as usize,
+            header.layers as usize,
             header.channels as usize,
-            get_layer,
-        );
+        )?;
+        self.transform_base(image, header, aux);
+        Ok(())
     }
 
-    pub fn transform<T>(&self, image: &[T], header: &header::Header, mut aux: &mut [i16])
+    /// Same as `transform`, but reads every channel from its own plane. There must be a plane
+    /// with the dimensions from `header` for every channel of every layer.
+    pub fn transform_planes<T>(
+        &self,
+        planes: &[Plane<'_, T>],
+        header: &header::Header,
+        aux: &mut [i16],
+    ) -> Result<(), Error>
     where
         T: Into<i16> + Copy,
     {
-        ColorTransformProgram::transform_base(
-            &self,
-            &image,
-            &header,
-            &mut aux,
-            1,
-            |channel, _, channel_size| channel * channel_size,
-        );
+        check_planes(
+            planes.iter().map(Plane::shape),
+            header.channels as usize * header.layers as usize,
+            header.width as usize,
+            header.height as usize,
+        )?;
+        self.transform_base(planes, header, aux);
+        Ok(())
     }
 
-    fn detransform_base<'a, T>(
-        &self,
-        aux: &mut [i16],
-        header: &header::Header,
-        channel_size: usize,
-        image: &'a mut [T],
-    ) -> (&'a mut [T], i16)
+    pub fn transform<T>(&self, image: &[T], header: &header::Header, aux: &mut [i16])
     where
-        T: NumCast,
+        T: Into<i16> + Copy,
     {
-        assert!(image.len() >= aux.len());
+        let image = PackedPlanes {
+            data: image,
+            channel_size: header.get_channel_size(),
+            width: header.width as usize,
+        };
+        self.transform_base(&image, header, aux);
+    }
 
+    pub(crate) fn detransform_aux(&self, aux: &mut [i16], channel_size: usize) {
         for channel_transform in self.channel_transforms.iter().rev() {
-            let mut transform_temp = vec![0_i16; channel_size];
             let dest_base = channel_transform.dest_channel * channel_size;
 
-            for channel_factor in &channel_transform.channel_factors {
-                for i in 0..channel_size {
-                    transform_temp[i] += aux[channel_factor.src_channel * channel_size + i]
-                        * channel_factor.factor as i16;
+            for i in 0..channel_size {
+                let mut transform_temp = 0_i16;
+                for channel_factor in &channel_transform.channel_factors {
+                    transform_temp = transform_temp.wrapping_add(
+                        aux[channel_factor.src_channel * channel_size + i]
+                            .wrapping_mul(channel_factor.factor as i16),
+                    );
                 }
-            }
 
-            for i in 0..channel_size {
-                transform_temp[i] /= channel_transform.denominator as i16;
-                aux[dest_base + i] -= transform_temp[i];
+                transform_temp = transform_temp.wrapping_div(channel_transform.denominator as i16);
+                aux[dest_base + i] = aux[dest_base + i].wrapping_sub(transform_temp);
             }
         }
+    }
 
-        // split off leftover
-        let (image, _) = image.split_at_mut(aux.len());
+    fn detransform_base<I>(
+        &self,
+        aux: &mut [i16],
+        header: &header::Header,
+        (width, height): (usize, usize),
+        image: &mut I,
+    ) where
+        I: ChannelRowsMut + ?Sized,
+        I::Sample: NumCast,
+    {
+        let channel_size = width * height;
+        self.detransform_aux(aux, channel_size);
 
         let boost = header.get_boost();
-
-        (image, boost)
+        for c in 0..header.channels as usize * header.layers as usize {
+            let plane = &aux[c * channel_size..(c + 1) * channel_size];
+            zip_channel_mut(image, c, plane, (width, height), |sample, value| {
+                *sample = NumCast::from(cut_with_u8(value / boost)).unwrap();
+            });
+        }
     }
 
     pub fn detransform_and_to_interleaved<T>(
         &self,
-        mut aux: &mut [i16],
+        aux: &mut [i16],
         header: &header::Header,
         channel_size: usize,
-        mut image: &mut [T],
+        image: &mut [T],
     ) where
         T: NumCast,
     {
-        let (image, boost) = ColorTransformProgram::detransform_base(
-            &self,
-            &mut aux,
-            &header,
+        let channels = header.channels as usize;
+        // layout of the packed image is the same as one row per layer of `channel_size` pixels
+        let mut image = ImageViewMut::with_layers(
+            image,
             channel_size,
-            &mut image,
-        );
+            1,
+            header.layers as usize,
+            channels,
+            channel_size * channels,
+        )
+        .expect("image is smaller than the header requires");
+        self.detransform_base(aux, header, (channel_size, 1), &mut image);
+    }
 
-        let channels = header.channels as usize;
-        for c in 0..channels * header.layers as usize {
-            let layer = get_layer(c, channels, channel_size);
-            for i in 0..channel_size {
-                image[layer + i * channels] =
-                    T::from(cut_with_u8(aux[c * channel_size + i] / boost)).unwrap();
-            }
-        }
+    /// Same as `detransform_and_to_interleaved`, but writes the image through a possibly
+    /// strided view. The view must have the dimensions from `header` downsampled by
+    /// `downsampling`.
+    pub fn detransform_to_view<T>(
+        &self,
+        aux: &mut [i16],
+        header: &header::Header,
+        downsampling: usize,
+        image: &mut ImageViewMut<'_, T>,
+    ) -> Result<(), Error>
+    where
+        T: NumCast,
+    {
+        let width = header.get_downsampled_width(downsampling);
+        let height = header.get_downsampled_height(downsampling);
+        image.shape().check(
+            width,
+            height,
+            header.layers as usize,
+            header.channels as usize,
+        )?;
+        self.detransform_base(aux, header, (width, height), image);
+        Ok(())
+    }
+
+    /// Same as `detransform`, but writes every channel to its own plane. There must be a plane
+    /// with the dimensions from `header` downsampled by `downsampling` for every channel of
+    /// every layer.
+    pub fn detransform_to_planes<T>(
+        &self,
+        aux: &mut [i16],
+        header: &header::Header,
+        downsampling: usize,
+        planes: &mut [PlaneMut<'_, T>],
+    ) -> Result<(), Error>
+    where
+        T: NumCast,
+    {
+        let width = header.get_downsampled_width(downsampling);
+        let height = header.get_downsampled_height(downsampling);
+        check_planes(
+            planes.iter().map(PlaneMut::shape),
+            header.channels as usize * header.layers as usize,
+            width,
+            height,
+        )?;
+        self.detransform_base(aux, header, (width, height), planes);
+        Ok(())
     }
 
     pub fn detransform<T>(
         &self,
-        mut aux: &mut [i16],
+        aux: &mut [i16],
         header: &header::Header,
         channel_size: usize,
-        mut image: &mut [T],
+        image: &mut [T],
     ) where
         T: NumCast,
     {
-        let (image, boost) = ColorTransformProgram::detransform_base(
-            &self,
-            &mut aux,
-            &header,
-            channel_size,
-            &mut image,
-        );
+        assert!(image.len() >= aux.len());
+        self.detransform_aux(aux, channel_size);
 
+        let boost = header.get_boost();
         for (dest, src) in image.iter_mut().zip(aux.iter()) {
             *dest = T::from(cut_with_u8(*src / boost)).unwrap();
         }
     }
 }
 
+/// Channels stored one after another without padding, as in `aux_data`.
+struct PackedPlanes<'a, T> {
+    data: &'a [T],
+    channel_size: usize,
+    width: usize,
+}
+
+impl<T> ChannelRows for PackedPlanes<'_, T> {
+    type Sample = T;
+
+    fn row(&self, channel: usize, y: usize) -> (&[T], usize) {
+        let start = channel * self.channel_size + y * self.width;
+        (&self.data[start..start + self.width], 1)
+    }
+}
+
+/// Calls `func` for every value of the `width * height` plane `plane` with the matching sample
+/// of `channel` of `image`.
+fn zip_channel<I>(
+    plane: &mut [i16],
+    image: &I,
+    channel: usize,
+    (width, height): (usize, usize),
+    mut func: impl FnMut(&mut i16, I::Sample),
+) where
+    I: ChannelRows + ?Sized,
+    I::Sample: Copy,
+{
+    if width == 0 {
+        return;
+    }
+    for (y, plane_row) in plane.chunks_exact_mut(width).take(height).enumerate() {
+        let (row, step) = image.row(channel, y);
+        for (value, pixel) in plane_row.iter_mut().zip(row.chunks(step)) {
+            func(value, pixel[0]);
+        }
+    }
+}
+
+/// Calls `func` for every sample of `channel` of `image` with the matching value of the
+/// `width * height` plane `plane`.
+fn zip_channel_mut<I>(
+    image: &mut I,
+    channel: usize,
+    plane: &[i16],
+    (width, height): (usize, usize),
+    mut func: impl FnMut(&mut I::Sample, i16),
+) where
+    I: ChannelRowsMut + ?Sized,
+{
+    if width == 0 {
+        return;
+    }
+    for (y, plane_row) in plane.chunks_exact(width).take(height).enumerate() {
+        let (row, step) = image.row_mut(channel, y);
+        for (pixel, &value) in row.chunks_mut(step).zip(plane_row) {
+            func(&mut pixel[0], value);
+        }
+    }
+}
+
 fn convert_between_interleaved_and_planar<F, T>(
     len: usize,
     channels: usize,
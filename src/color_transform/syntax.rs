@@ -0,0 +1,245 @@
+//! Textual form of color transform programs, for example
+//! `c0 -= c1 [chroma]; c2 -= c1 [chroma]; c1 += (c0 + c2) / 4` for `rgb_to_yuv`.
+//!
+//! Every statement adds the sum of the source channels multiplied by their factors, divided by
+//! the denominator, to the destination channel: `c1 += (c0 + 3 * c2) / 8`. With `-=` the
+//! factors are negated. `[chroma]` marks the destination as a chroma channel, a statement
+//! without the sum only marks it, as in `c1 [chroma]`. Statements are separated by `;` and
+//! `#` starts a comment up to the end of the line.
+
+use core::fmt;
+use core::str::FromStr;
+
+use super::{ChannelTransform, ChannelTransformBuilder, ColorTransformProgram};
+use crate::errors::Error;
+
+impl FromStr for ColorTransformProgram {
+    type Err = Error;
+
+    fn from_str(text: &str) -> Result<Self, Error> {
+        let mut parser = Parser {
+            text: text.as_bytes(),
+            offset: 0,
+        };
+        let mut program = ColorTransformProgram::new();
+        while parser.peek().is_some() {
+            program.add_channel_transform(parser.statement()?);
+            if !parser.eat(";") && parser.peek().is_some() {
+                return Err(parser.error("`;`"));
+            }
+        }
+        Ok(program)
+    }
+}
+
+impl fmt::Display for ColorTransformProgram {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (index, channel_transform) in self.channel_transforms.iter().enumerate() {
+            if index > 0 {
+                write!(f, "; ")?;
+            }
+            write!(f, "{}", channel_transform)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for ChannelTransform {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "c{}", self.dest_channel)?;
+
+        if !self.channel_factors.is_empty() || self.denominator != 1 {
+            let negate = !self.channel_factors.is_empty()
+                && self.channel_factors.iter().all(|f| f.factor < 0);
+            write!(f, " {} ", if negate { "-=" } else { "+=" })?;
+
+            let parenthesize = self.denominator != 1 && self.channel_factors.len() > 1;
+            if parenthesize {
+                write!(f, "(")?;
+            }
+            if self.channel_factors.is_empty() {
+                write!(f, "0")?;
+            }
+            for (index, channel_factor) in self.channel_factors.iter().enumerate() {
+                let factor = if negate {
+                    -channel_factor.factor
+                } else {
+                    channel_factor.factor
+                };
+                match (index, factor < 0) {
+                    (0, true) => write!(f, "-")?,
+                    (0, false) => (),
+                    (_, true) => write!(f, " - ")?,
+                    (_, false) => write!(f, " + ")?,
+                }
+                if factor.abs() != 1 {
+                    write!(f, "{} * ", factor.abs())?;
+                }
+                write!(f, "c{}", channel_factor.src_channel)?;
+            }
+            if parenthesize {
+                write!(f, ")")?;
+            }
+            if self.denominator != 1 {
+                write!(f, " / {}", self.denominator)?;
+            }
+        }
+
+        if self.is_chroma {
+            write!(f, " [chroma]")?;
+        }
+        Ok(())
+    }
+}
+
+struct Parser<'a> {
+    text: &'a [u8],
+    offset: usize,
+}
+
+impl Parser<'_> {
+    /// `channel ["+=" | "-=" sum ["/" number]] ["[chroma]"]`
+    fn statement(&mut self) -> Result<ChannelTransform, Error> {
+        let dest_channel = self.channel()?;
+        let mut builder = ChannelTransformBuilder::with_dest_channel(dest_channel);
+
+        let sign = if self.eat("+=") {
+            1
+        } else if self.eat("-=") {
+            -1
+        } else {
+            0
+        };
+        if sign != 0 {
+            let parenthesized = self.eat("(");
+            let mut terms = 0;
+            loop {
+                let term_sign = if self.eat("-") {
+                    -1
+                } else if terms == 0 || self.eat("+") {
+                    1
+                } else {
+                    break;
+                };
+                if let Some((src_channel, factor)) = self.term()? {
+                    builder.add_channel_factor(src_channel, sign * term_sign * factor);
+                }
+                terms += 1;
+            }
+            if parenthesized {
+                self.expect(")")?;
+            }
+
+            if self.peek() == Some(b'/') {
+                if terms > 1 && !parenthesized {
+                    return Err(self.error("parentheses around the divided sum"));
+                }
+                self.offset += 1;
+                let denominator = self.number()?;
+                if denominator == 0 || denominator > i16::MAX as isize {
+                    return Err(Error::TransformDenominator {
+                        channel: dest_channel,
+                    });
+                }
+                builder.set_denominator(denominator);
+            }
+        }
+
+        if self.eat("[") {
+            self.expect("chroma")?;
+            self.expect("]")?;
+            builder.set_chroma();
+        }
+        Ok(builder.build())
+    }
+
+    /// `[number "*"] channel`, or a lone `0` without a channel.
+    fn term(&mut self) -> Result<Option<(usize, isize)>, Error> {
+        if !self.peek().is_some_and(|c| c.is_ascii_digit()) {
+            return Ok(Some((self.channel()?, 1)));
+        }
+        let factor = self.number()?;
+        if self.eat("*") {
+            Ok(Some((self.channel()?, factor)))
+        } else if factor == 0 {
+            Ok(None)
+        } else {
+            Err(self.error("`*`"))
+        }
+    }
+
+    fn channel(&mut self) -> Result<usize, Error> {
+        self.expect("c")?;
+        if !self
+            .text
+            .get(self.offset)
+            .is_some_and(|c| c.is_ascii_digit())
+        {
+            return Err(self.error("channel number"));
+        }
+        Ok(self.number()? as usize)
+    }
+
+    /// Decimal number of at most 31 bits, like the factors in the encoded program.
+    fn number(&mut self) -> Result<isize, Error> {
+        self.skip_whitespace();
+        let start = self.offset;
+        let mut value: isize = 0;
+        while let Some(digit) = self.text.get(self.offset).filter(|c| c.is_ascii_digit()) {
+            value = value * 10 + isize::from(digit - b'0');
+            if value > i32::MAX as isize {
+                self.offset = start;
+                return Err(self.error("number below 2^31"));
+            }
+            self.offset += 1;
+        }
+        if self.offset == start {
+            return Err(self.error("number"));
+        }
+        Ok(value)
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(&c) = self.text.get(self.offset) {
+            if c == b'#' {
+                while self.text.get(self.offset).is_some_and(|&c| c != b'\n') {
+                    self.offset += 1;
+                }
+            } else if c.is_ascii_whitespace() {
+                self.offset += 1;
+            } else {
+                break;
+            }
+        }
+    }
+
+    fn peek(&mut self) -> Option<u8> {
+        self.skip_whitespace();
+        self.text.get(self.offset).copied()
+    }
+
+    fn eat(&mut self, token: &str) -> bool {
+        self.skip_whitespace();
+        if self.text[self.offset..].starts_with(token.as_bytes()) {
+            self.offset += token.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, token: &'static str) -> Result<(), Error> {
+        if self.eat(token) {
+            Ok(())
+        } else {
+            Err(self.error(token))
+        }
+    }
+
+    fn error(&self, expected: &'static str) -> Error {
+        Error::TransformSyntax {
+            offset: self.offset,
+            expected,
+        }
+    }
+}
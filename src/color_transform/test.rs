@@ -110,6 +110,8 @@ fn test_color_transform_passtrough() {
         block_size: header::BLOCK_DEFAULT,
         filter: header::Filter::Cubic,
         quantization: header::Quantization::Scalar,
+        weighting: header::Weighting::Flat,
+        has_extension: false,
         encoder: header::Encoder::Turbo,
         intent: header::Intent::RGB,
         metadata_size: 0,
@@ -153,6 +155,8 @@ fn test_color_transform_yuv() {
         block_size: header::BLOCK_DEFAULT,
         filter: header::Filter::Cubic,
         quantization: header::Quantization::Scalar,
+        weighting: header::Weighting::Flat,
+        has_extension: false,
         encoder: header::Encoder::Turbo,
         intent: header::Intent::RGB,
         metadata_size: 0,
@@ -269,6 +273,30 @@ fn test_color_transform_decode() {
     assert_eq!(is_chroma, vec![true, false, true]);
 }
 
+#[test]
+fn test_color_transform_decode_limits() {
+    let buffer = vec![183, 119, 85, 151, 246, 114, 119, 85, 0, 128, 50, 233];
+
+    let mut limits = DecodeLimits {
+        max_transform_program_len: 7,
+        ..Default::default()
+    };
+    let mut is_chroma = vec![false; 3];
+    assert!(ColorTransformProgram::decode_with_limits(
+        &mut buffer.as_slice(),
+        &mut is_chroma,
+        &limits
+    )
+    .is_ok());
+
+    limits.max_transform_program_len = 6;
+    match ColorTransformProgram::decode_with_limits(&mut buffer.as_slice(), &mut is_chroma, &limits)
+    {
+        Err(Error::TransformLength { max: 6 }) => (),
+        r => panic!("transform program length is not checked: {:?}", r),
+    }
+}
+
 #[test]
 fn test_interleaved_to_planar() {
     let boost = 2;
@@ -316,3 +344,312 @@ fn test_planar_to_interleaved_with_skip() {
     planar_to_interleaved(&input, channels, boost, &mut actual, &[1]);
     assert_eq!(actual, expected);
 }
+
+#[test]
+fn test_color_transform_ycocg_r() {
+    let width = 16;
+    let height = 16;
+    let header = header::HeaderBuilder {
+        width,
+        height,
+        channels: 4,
+        quality: header::QUALITY_MAX,
+        chroma_scale: 1,
+        block_size: header::BLOCK_DEFAULT,
+        filter: header::Filter::Linear,
+        encoder: header::Encoder::Turbo,
+        intent: header::Intent::RGBA,
+        ..header::HeaderBuilder::test_default()
+    }
+    .build()
+    .unwrap();
+    let input = (0..header.get_image_size())
+        .map(|i| ((i * 97) ^ (i >> 3)) as u8)
+        .collect::<Vec<_>>();
+    let channel_size = header.get_channel_size();
+
+    let mut aux = vec![0_i16; header.get_image_size()];
+    ColorTransformProgram::rgba_to_ycocg_r().transform_and_to_planar(&input, &header, &mut aux);
+    for (i, pixel) in input.chunks(4).enumerate() {
+        let (r, g, b) = (pixel[0] as i16, pixel[1] as i16, pixel[2] as i16);
+        let co = r - b;
+        let cg = g - (b + (co >> 1));
+        let y = b + (co >> 1) + (cg >> 1);
+        assert_eq!(aux[i], co);
+        assert_eq!(aux[channel_size + i], cg);
+        assert!((aux[2 * channel_size + i] - y).abs() <= 1);
+        assert_eq!(aux[3 * channel_size + i], pixel[3] as i16);
+    }
+
+    for program in &[
+        ColorTransformProgram::rgba_to_ycocg_r(),
+        ColorTransformProgram::bgra_to_ycocg_r(),
+    ] {
+        assert!(header.is_lossless(program));
+        let mut aux = vec![0_i16; header.get_image_size()];
+        program.transform_and_to_planar(&input, &header, &mut aux);
+        let mut output = vec![0_u8; input.len()];
+        program.detransform_and_to_interleaved(&mut aux, &header, channel_size, &mut output);
+        assert_eq!(output, input);
+    }
+}
+
+#[test]
+fn test_color_transform_syntax() {
+    let presets = [
+        (ColorTransformProgram::new(), ""),
+        (
+            ColorTransformProgram::yuv444_to_yuv444(),
+            "c1 [chroma]; c2 [chroma]",
+        ),
+        (
+            ColorTransformProgram::rgb_to_yuv(),
+            "c0 -= c1 [chroma]; c2 -= c1 [chroma]; c1 += (c0 + c2) / 4",
+        ),
+        (
+            ColorTransformProgram::rgb_to_a710(),
+            "c0 -= c1 [chroma]; c2 -= (2 * c1 + c0) / 2 [chroma]; c1 += (2 * c2 + 3 * c0) / 8",
+        ),
+        (
+            ColorTransformProgram::bgr_to_ycocg_r(),
+            "c2 -= c0 [chroma]; c1 -= (2 * c0 + c2) / 2 [chroma]; c0 += (c2 + c1) / 2",
+        ),
+    ];
+    for (program, text) in presets.iter() {
+        assert_eq!(&program.to_string(), text);
+        assert_eq!(&text.parse::<ColorTransformProgram>().unwrap(), program);
+    }
+
+    let mut program = ColorTransformProgram::new();
+    program
+        .add_channel_transform(
+            ChannelTransformBuilder::with_dest_channel(3)
+                .add_channel_factor(0, 5)
+                .add_channel_factor(1, -1)
+                .add_channel_factor(2, 0)
+                .build(),
+        )
+        .add_channel_transform(
+            ChannelTransformBuilder::with_dest_channel(1)
+                .add_channel_factor(3, -7)
+                .set_denominator(3)
+                .build(),
+        )
+        .add_channel_transform(
+            ChannelTransformBuilder::with_dest_channel(0)
+                .set_denominator(2)
+                .set_chroma()
+                .build(),
+        );
+    let text = "c3 += 5 * c0 - c1 + 0 * c2; c1 -= 7 * c3 / 3; c0 += 0 / 2 [chroma]";
+    assert_eq!(program.to_string(), text);
+    assert_eq!(text.parse::<ColorTransformProgram>().unwrap(), program);
+
+    // spacing, comments and the trailing separator are free
+    let text =
+        "# luma last\nc0-=c1[chroma];\n  c2 -= c1 [ chroma ] ;\nc1 += ( c0+c2 ) / 4 ; # done\n";
+    assert_eq!(
+        text.parse::<ColorTransformProgram>().unwrap(),
+        ColorTransformProgram::rgb_to_yuv()
+    );
+    assert_eq!(
+        "c0 += -c1 - 2 * c2"
+            .parse::<ColorTransformProgram>()
+            .unwrap(),
+        "c0 -= c1 + 2 * c2"
+            .parse::<ColorTransformProgram>()
+            .unwrap()
+    );
+}
+
+#[test]
+fn test_color_transform_syntax_errors() {
+    let parse = |text: &str| match text.parse::<ColorTransformProgram>() {
+        Err(Error::TransformSyntax { offset, expected }) => (offset, expected),
+        result => panic!("{:?} parsed as {:?}", text, result),
+    };
+    assert_eq!(parse("x0 += c1"), (0, "c"));
+    assert_eq!(parse("c += c1"), (1, "channel number"));
+    assert_eq!(parse("c0 += c1 c2"), (9, "`;`"));
+    assert_eq!(parse("c0 += 2 c1"), (8, "`*`"));
+    assert_eq!(parse("c0 += (c1 + c2 / 4"), (15, ")"));
+    assert_eq!(
+        parse("c0 += c1 + c2 / 4"),
+        (14, "parentheses around the divided sum")
+    );
+    assert_eq!(parse("c0 += c1 [luma]"), (10, "chroma"));
+    assert_eq!(parse("c0 += 3000000000 * c1"), (6, "number below 2^31"));
+
+    match "c0 += c1 / 0".parse::<ColorTransformProgram>() {
+        Err(Error::TransformDenominator { channel: 0 }) => (),
+        result => panic!("unexpected result: {:?}", result),
+    }
+    match "c0 += c1 / 40000".parse::<ColorTransformProgram>() {
+        Err(Error::TransformDenominator { channel: 0 }) => (),
+        result => panic!("unexpected result: {:?}", result),
+    }
+}
+
+#[test]
+fn test_auto_candidates() {
+    use crate::header::Intent;
+
+    assert_eq!(
+        ColorTransformProgram::auto_candidates(Intent::Generic, 1).len(),
+        1
+    );
+    assert_eq!(
+        ColorTransformProgram::auto_candidates(Intent::RGBA, 4).len(),
+        4
+    );
+    assert_eq!(
+        ColorTransformProgram::auto_candidates(Intent::YUV444, 3),
+        vec![
+            ColorTransformProgram::new(),
+            ColorTransformProgram::yuv444_to_yuv444()
+        ]
+    );
+    assert!(ColorTransformProgram::auto_candidates(Intent::BGR, 3)
+        .contains(&ColorTransformProgram::bgr_to_ycocg_r()));
+}
+
+#[test]
+fn test_select_smallest() {
+    // tiny images don't fit into a buffer of 4 bytes per sample
+    for &(width, height, encoder) in &[
+        (40, 30, header::Encoder::Contextual),
+        (1, 1, header::Encoder::Contextual),
+        (1, 1, header::Encoder::Screen),
+        (2, 2, header::Encoder::Screen),
+    ] {
+        let header = header::HeaderBuilder {
+            width,
+            height,
+            quality: 256,
+            block_size: header::BLOCK_DEFAULT,
+            filter: header::Filter::Linear,
+            encoder,
+            intent: header::Intent::RGB,
+            ..header::HeaderBuilder::test_default()
+        }
+        .build()
+        .unwrap();
+        // colors along a diagonal of the RGB cube with some noise, where the transforms differ
+        let image = (0..header.get_image_size())
+            .map(|i| {
+                let pixel = i / 3;
+                let base = (pixel % 40 + pixel / 40) * 3;
+                (base + ((pixel * 7919) >> (i % 3 * 2)) % 9) as u8
+            })
+            .collect::<Vec<_>>();
+
+        let candidates = ColorTransformProgram::auto_candidates(header.intent, header.channels);
+        let sizes = candidates
+            .iter()
+            .map(|candidate| {
+                let mut buffer = vec![0; 256 + image.len() * 4];
+                crate::compress_simple(&image, &header, candidate, &mut buffer).unwrap()
+            })
+            .collect::<Vec<_>>();
+        let smallest = sizes
+            .iter()
+            .position(|size| size == sizes.iter().min().unwrap())
+            .unwrap();
+
+        assert_eq!(
+            ColorTransformProgram::select(&image, &header, &candidates).unwrap(),
+            smallest,
+            "{}x{} {:?}",
+            width,
+            height,
+            encoder
+        );
+        assert_eq!(
+            ColorTransformProgram::auto(&image, &header).unwrap(),
+            candidates[smallest]
+        );
+
+        match ColorTransformProgram::select(&image[1..], &header, &candidates) {
+            Err(Error::BufferSize { .. }) => (),
+            result => panic!("unexpected result: {:?}", result),
+        }
+        match ColorTransformProgram::select(&image, &header, &[]) {
+            Err(Error::NoCandidates) => (),
+            result => panic!("unexpected result: {:?}", result),
+        }
+    }
+}
+
+#[test]
+fn test_auto_gray() {
+    // large enough to be downsampled
+    let header = header::HeaderBuilder {
+        width: 600,
+        height: 400,
+        quality: 256,
+        block_size: header::BLOCK_DEFAULT,
+        filter: header::Filter::Linear,
+        intent: header::Intent::BGR,
+        ..header::HeaderBuilder::test_default()
+    }
+    .build()
+    .unwrap();
+    let image = (0..header.get_image_size())
+        .map(|i| ((i / 3) % 600 / 3 + (i / 1800) % 400 / 5) as u8)
+        .collect::<Vec<_>>();
+
+    // the chroma of gray is zero
+    let program = ColorTransformProgram::auto(&image, &header).unwrap();
+    assert_ne!(program, ColorTransformProgram::new());
+
+    let header = header::HeaderBuilder {
+        width: 600,
+        height: 400,
+        channels: 1,
+        quality: 256,
+        block_size: header::BLOCK_DEFAULT,
+        filter: header::Filter::Linear,
+        intent: header::Intent::Generic,
+        ..header::HeaderBuilder::test_default()
+    }
+    .build()
+    .unwrap();
+    assert_eq!(
+        ColorTransformProgram::auto(&image[..header.get_image_size()], &header).unwrap(),
+        ColorTransformProgram::new()
+    );
+}
+
+#[test]
+fn test_auto_sample_header() {
+    let header = header::Header {
+        quantization: header::Quantization::DeadZone,
+        weighting: header::Weighting::Csf,
+        ..header::HeaderBuilder {
+            width: 600,
+            height: 400,
+            quality: 256,
+            block_size: header::BLOCK_DEFAULT,
+            filter: header::Filter::Linear,
+            intent: header::Intent::RGB,
+            ..header::HeaderBuilder::test_default()
+        }
+        .build()
+        .unwrap()
+    };
+    let image = vec![0; header.get_image_size()];
+
+    // only the dimensions change, so the candidates are compared with the same quantization
+    let (sample, sample_header) = super::auto::downsample(&image, &header);
+    assert_eq!(
+        sample_header,
+        header::Header {
+            width: 300,
+            height: 200,
+            channel_size: 300 * 200,
+            image_size: 300 * 200 * 3,
+            ..header
+        }
+    );
+    assert_eq!(sample.len(), sample_header.get_image_size());
+}
@@ -1,30 +1,106 @@
-use std::{self, mem, u8};
+use alloc::{vec, vec::Vec};
+use core::{mem, ptr::NonNull, u8};
 
-use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use byteorder::{ByteOrder, LittleEndian};
 
-use crate::bits::{BitsIOReader, BitsIOWriter, BitsWriter};
-use crate::config::Config;
+use crate::bits::{BitsWriter, SliceBitsReader, SliceBitsWriter};
+use crate::config::MultithreadingFactors;
 use crate::encode::{decode, encode};
-use crate::errors::{CompressError, DecompressError};
+use crate::errors::Error;
+use crate::extension::{ChannelCoding, Coding, Extension};
 use crate::header;
+use crate::io::IOError;
 use crate::lifting;
+use crate::palette;
 use crate::processing::{
-    image::Image, process_maybe_parallel_for_each, process_maybe_parallel_map_collect,
+    image::Image, process_maybe_parallel_for_each, process_maybe_parallel_indexed_for_each,
     VariableChunksIterator,
 };
 use crate::quant;
 
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
+
 #[cfg(test)]
 mod test;
 
+/// Working buffers of `compress_aux_data` and `decompress_aux_data`, kept between calls by
+/// `Compressor` and `Decompressor` so that encoding a frame doesn't allocate.
+#[derive(Debug, Default)]
+pub(crate) struct Scratch {
+    levels: Vec<(usize, usize)>,
+    block_sizes: Vec<usize>,
+    block_offsets: Vec<usize>,
+    block_results: Vec<Result<(), Error>>,
+    /// Encoded blocks of a step level.
+    blocks: Vec<Vec<u8>>,
+    /// Allocations of the rows of every channel.
+    rows: Vec<RowsBuffer>,
+    /// Quantized coefficients the palette tiles of `Encoder::Screen` are chosen with.
+    coefficients: Vec<i16>,
+    palette: palette::Scratch,
+    /// Sample counts from which the stages run in parallel.
+    pub factors: MultithreadingFactors,
+}
+
+/// Coding of streams without the coding extension.
+fn default_coding(header: &header::Header, is_chroma: &[bool]) -> Coding {
+    Coding::new(header, is_chroma, &Extension::default())
+}
+
 pub fn compress_aux_data(
-    mut aux_data: &mut [i16],
+    aux_data: &mut [i16],
     header: &header::Header,
     is_chroma: &[bool],
-    mut buffer: &mut [u8],
-) -> Result<usize, CompressError> {
-    lift_and_quantize(&mut aux_data, &header, &is_chroma);
-    compress_image_data(&mut aux_data, &header, &mut buffer, &is_chroma)
+    buffer: &mut [u8],
+) -> Result<usize, Error> {
+    compress_aux_data_with(
+        aux_data,
+        header,
+        &default_coding(header, is_chroma),
+        buffer,
+        &mut Scratch::default(),
+    )
+}
+
+pub(crate) fn compress_aux_data_with(
+    aux_data: &mut [i16],
+    header: &header::Header,
+    coding: &Coding,
+    buffer: &mut [u8],
+    scratch: &mut Scratch,
+) -> Result<usize, Error> {
+    check_buffer_size(header.get_image_size(), aux_data.len())?;
+    let section_size = if header.encoder == header::Encoder::Screen {
+        let Scratch {
+            rows,
+            coefficients,
+            palette,
+            factors,
+            ..
+        } = scratch;
+        coefficients.clear();
+        coefficients.extend_from_slice(&aux_data[..header.get_image_size()]);
+        lift_and_quantize(coefficients, header, coding, rows, factors);
+        palette::encode_section(aux_data, coefficients, header, palette, buffer)?
+    } else {
+        0
+    };
+    lift_and_quantize(
+        aux_data,
+        header,
+        coding,
+        &mut scratch.rows,
+        &scratch.factors,
+    );
+    let size = compress_image_data(
+        aux_data,
+        header,
+        &mut buffer[section_size..],
+        coding,
+        scratch,
+    )?;
+    Ok(section_size + size)
 }
 
 pub fn decompress_aux_data(
@@ -33,96 +109,279 @@ pub fn decompress_aux_data(
     is_chroma: &[bool],
     downsampling: usize,
     test: bool,
-    mut aux_data: &mut [i16],
-) -> Result<usize, DecompressError> {
-    let payload_next_point_of_interest =
-        decompress_image_data(&mut aux_data, &header, data, downsampling, test, &is_chroma)?;
+    aux_data: &mut [i16],
+) -> Result<usize, Error> {
+    decompress_aux_data_with(
+        data,
+        header,
+        &default_coding(header, is_chroma),
+        downsampling,
+        test,
+        aux_data,
+        &mut Scratch::default(),
+    )
+}
+
+pub(crate) fn decompress_aux_data_with(
+    data: &[u8],
+    header: &header::Header,
+    coding: &Coding,
+    downsampling: usize,
+    test: bool,
+    aux_data: &mut [i16],
+    scratch: &mut Scratch,
+) -> Result<usize, Error> {
+    check_buffer_size(
+        header.get_downsampled_image_size(downsampling),
+        aux_data.len(),
+    )?;
+    let (tiles, levels) = if header.encoder == header::Encoder::Screen {
+        match palette::split_section(data) {
+            Ok(section) => section,
+            // nothing is decoded before the whole palette section is there
+            Err(section_size) => return Ok(section_size.saturating_add(1024)),
+        }
+    } else {
+        (&[][..], data)
+    };
+    let section_size = data.len() - levels.len();
+
+    let payload_next_point_of_interest = decompress_image_data_with(
+        aux_data,
+        header,
+        levels,
+        downsampling,
+        test,
+        coding,
+        scratch,
+    )?;
 
     if !test {
-        unlift_and_dequantize(&mut aux_data, &header, &is_chroma, downsampling);
+        unlift_and_dequantize_with(aux_data, header, coding, downsampling, tiles, scratch)?;
     }
 
-    Ok(payload_next_point_of_interest)
+    if payload_next_point_of_interest == 0 {
+        Ok(0)
+    } else {
+        Ok(section_size + payload_next_point_of_interest)
+    }
 }
 
-fn aux_data_to_2d_channel<'a>(
-    aux_data: &'a mut [i16],
-    header: &'a header::Header,
-    is_chroma: &'a [bool],
+/// Same as `unlift_and_dequantize`, with the working buffers of `scratch`. The palette tiles of
+/// `Encoder::Screen` streams are written over the result, `tiles` is the tile data of the
+/// palette section.
+pub(crate) fn unlift_and_dequantize_with(
+    aux_data: &mut [i16],
+    header: &header::Header,
+    coding: &Coding,
     downsampling: usize,
-) -> impl Iterator<Item = (Vec<&'a mut [i16]>, &'a bool)> {
-    let channel_size = header.get_downsampled_channel_size(downsampling);
+    tiles: &[u8],
+    scratch: &mut Scratch,
+) -> Result<(), Error> {
+    unlift_and_dequantize(
+        aux_data,
+        header,
+        coding,
+        downsampling,
+        &mut scratch.rows,
+        &scratch.factors,
+    );
+    if header.encoder == header::Encoder::Screen {
+        palette::decode_tiles(tiles, aux_data, header, downsampling, &mut scratch.palette)?;
+    }
+    Ok(())
+}
 
-    aux_data
-        .chunks_mut(channel_size)
-        .map(move |chunk| {
-            chunk
-                .chunks_mut(header.get_downsampled_width(downsampling))
-                .collect::<Vec<_>>()
-        })
-        .zip(is_chroma.iter())
+fn check_buffer_size(expected: usize, actual: usize) -> Result<(), Error> {
+    if actual < expected {
+        Err(Error::BufferSize { expected, actual })
+    } else {
+        Ok(())
+    }
 }
 
-fn lift_and_quantize(aux_data: &mut [i16], header: &header::Header, is_chroma: &[bool]) {
-    let chroma_quality = header.get_chroma_quality();
-    let boost = header.get_boost();
+/// Step levels in the order they are stored in the stream, with the number of blocks (the
+/// length of the block table) on each level.
+pub(crate) fn step_levels(header: &header::Header) -> Result<Vec<(usize, usize)>, Error> {
+    let mut levels = vec![];
+    fill_step_levels(header, &mut levels)?;
+    Ok(levels)
+}
 
-    process_maybe_parallel_for_each(
-        aux_data_to_2d_channel(aux_data, header, is_chroma, 0),
-        |(ref mut image, &is_chroma)| {
-            match header.filter {
-                header::Filter::Linear => lifting::lift_linear(image),
-                header::Filter::Cubic => lifting::lift_cubic(image),
-            };
+fn fill_step_levels(
+    header: &header::Header,
+    levels: &mut Vec<(usize, usize)>,
+) -> Result<(), Error> {
+    let width = header.width as usize;
+    let height = header.height as usize;
+    let layers = usize::from(header.layers);
+    let channels = usize::from(header.channels);
 
-            let quality = if is_chroma {
-                chroma_quality
-            } else {
-                i32::from(header.quality)
-            };
+    let mut step = 1;
+    while step * 2 < width || step * 2 < height {
+        step *= 2;
+    }
+    if (step << header.block_size) == 0 {
+        return Err(Error::BlockSize(header.block_size));
+    }
 
-            quant::quantize(
-                image,
-                quality,
-                0,
-                i32::from(header::QUALITY_MAX) * i32::from(boost),
-            );
+    levels.clear();
+    while step >= 1 {
+        let bs = step << header.block_size;
+        let block_count_x = (width + bs - 1) / bs;
+        let block_count_y = (height + bs - 1) / bs;
+        levels.push((step, block_count_x * block_count_y * layers * channels));
+        step /= 2;
+    }
+
+    Ok(())
+}
+
+/// Allocation of a vector of rows, kept between calls without holding any rows.
+#[derive(Debug)]
+struct RowsBuffer {
+    ptr: NonNull<u8>,
+    capacity: usize,
+}
+
+impl Default for RowsBuffer {
+    fn default() -> Self {
+        RowsBuffer {
+            ptr: NonNull::dangling(),
+            capacity: 0,
+        }
+    }
+}
+
+// SAFETY: the buffer is only memory, it holds no rows
+unsafe impl Send for RowsBuffer {}
+unsafe impl Sync for RowsBuffer {}
+
+impl RowsBuffer {
+    /// Takes the allocation as an empty vector of rows.
+    fn take<'a>(&mut self) -> Vec<&'a mut [i16]> {
+        let capacity = mem::take(&mut self.capacity);
+        if capacity == 0 {
+            return Vec::new();
+        }
+        // SAFETY: the allocation comes from a `Vec<&mut [i16]>` with `capacity`, see `put`,
+        // and rows of any lifetime have the same layout. The vector is empty, so it holds no
+        // rows of the image it was used for.
+        unsafe { Vec::from_raw_parts(self.ptr.as_ptr().cast(), 0, capacity) }
+    }
+
+    /// Keeps the allocation of `rows` for the next `take`.
+    fn put(&mut self, mut rows: Vec<&mut [i16]>) {
+        drop(self.take());
+        rows.clear();
+        let mut rows = mem::ManuallyDrop::new(rows);
+        self.capacity = rows.capacity();
+        self.ptr = NonNull::new(rows.as_mut_ptr().cast()).unwrap_or(NonNull::dangling());
+    }
+}
+
+impl Drop for RowsBuffer {
+    fn drop(&mut self) {
+        drop(self.take());
+    }
+}
+
+/// Splits every channel of `aux_data` into rows and runs `action` on the channels in parallel.
+fn for_each_2d_channel<A>(
+    aux_data: &mut [i16],
+    header: &header::Header,
+    coding: &Coding,
+    downsampling: usize,
+    rows_storage: &mut Vec<RowsBuffer>,
+    action: A,
+) where
+    A: Fn(&mut [&mut [i16]], &ChannelCoding) + Sync + Send,
+{
+    let channel_size = header.get_downsampled_channel_size(downsampling);
+    let width = header.get_downsampled_width(downsampling);
+
+    if rows_storage.len() < coding.channels.len() {
+        rows_storage.resize_with(coding.channels.len(), RowsBuffer::default);
+    }
+
+    process_maybe_parallel_for_each(
+        rows_storage
+            .iter_mut()
+            .zip(aux_data.chunks_mut(channel_size))
+            .zip(coding.channels.iter()),
+        |((rows_buffer, chunk), coding)| {
+            let mut rows = rows_buffer.take();
+            rows.extend(chunk.chunks_mut(width));
+            action(&mut rows, coding);
+            rows_buffer.put(rows);
         },
         true,
     );
 }
 
+fn lift_and_quantize(
+    aux_data: &mut [i16],
+    header: &header::Header,
+    coding: &Coding,
+    rows: &mut Vec<RowsBuffer>,
+    factors: &MultithreadingFactors,
+) {
+    let max_quality = i32::from(header::QUALITY_MAX) * i32::from(header.get_boost());
+    let quantizer = coding.quantizer(header);
+
+    for_each_2d_channel(aux_data, header, coding, 0, rows, |image, channel| {
+        match header.filter {
+            header::Filter::Linear => lifting::lift_linear_with(image, factors),
+            header::Filter::Cubic => lifting::lift_cubic_with(image, factors),
+        };
+
+        let scales = coding.scales(header, channel);
+        quant::quantize_with(
+            image,
+            channel.quality,
+            max_quality,
+            quantizer,
+            scales,
+            factors,
+        );
+    });
+}
+
 fn unlift_and_dequantize(
     aux_data: &mut [i16],
     header: &header::Header,
-    is_chroma: &[bool],
+    coding: &Coding,
     downsampling: usize,
+    rows: &mut Vec<RowsBuffer>,
+    factors: &MultithreadingFactors,
 ) {
-    let chroma_quality = header.get_chroma_quality();
-    let boost = header.get_boost();
-
-    process_maybe_parallel_for_each(
-        aux_data_to_2d_channel(aux_data, header, is_chroma, downsampling),
-        |(ref mut image, &is_chroma)| {
-            let quality = if is_chroma {
-                chroma_quality
-            } else {
-                i32::from(header.quality)
-            };
-
-            quant::dequantize(
+    let max_quality = i32::from(header::QUALITY_MAX) * i32::from(header.get_boost());
+    let quantizer = coding.quantizer(header);
+
+    for_each_2d_channel(
+        aux_data,
+        header,
+        coding,
+        downsampling,
+        rows,
+        |image, channel| {
+            let quality = channel.quality << downsampling;
+            let scales = coding.scales(header, channel);
+            quant::dequantize_with(
                 image,
-                quality << downsampling,
-                0,
-                i32::from(header::QUALITY_MAX) * i32::from(boost),
+                quality,
+                max_quality,
+                quantizer,
+                scales,
+                downsampling,
+                factors,
             );
 
             match header.filter {
-                header::Filter::Linear => lifting::unlift_linear(image),
-                header::Filter::Cubic => lifting::unlift_cubic(image),
+                header::Filter::Linear => lifting::unlift_linear_with(image, factors),
+                header::Filter::Cubic => lifting::unlift_cubic_with(image, factors),
             };
         },
-        true,
     );
 }
 
@@ -130,122 +389,135 @@ fn compress_image_data(
     aux_data: &mut [i16],
     header: &header::Header,
     buffer: &mut [u8],
-    is_chroma: &[bool],
-) -> Result<usize, CompressError> {
-    let chroma_quality = header.get_chroma_quality();
-
+    coding: &Coding,
+    scratch: &mut Scratch,
+) -> Result<usize, Error> {
     let width = header.width as usize;
     let height = header.height as usize;
     let layers = usize::from(header.layers);
     let channels = usize::from(header.channels);
 
-    let mut step = 1;
-    while step * 2 < width || step * 2 < height {
-        step *= 2;
-    }
-    if (step << header.block_size) == 0 {
-        return Err(CompressError::Overflow);
-    }
-
     let mut has_dc = true;
     let mut compressed_size = 0;
-    let hint_do_parallel = aux_data.len() > Config::multithreading_factors().compress;
+    let hint_do_parallel = aux_data.len() > scratch.factors.compress;
 
-    while step >= 1 {
-        let bs = step << header.block_size;
-        let block_count_x = (width + bs - 1) / bs;
-        let block_count_y = (height + bs - 1) / bs;
-        let block_count = block_count_x * block_count_y * layers * channels;
+    let Scratch {
+        levels,
+        blocks,
+        block_results,
+        ..
+    } = scratch;
+    fill_step_levels(header, levels)?;
 
+    for &(step, block_count) in levels.iter() {
+        let bs = step << header.block_size;
         let block_sizes_storage_size = block_count * mem::size_of::<u32>();
 
         // slice out already compressed data from buffer
         let (_, buffer_remainder) = buffer.split_at_mut(compressed_size);
 
         if buffer_remainder.len() < block_sizes_storage_size {
-            return Err(CompressError::Overflow);
+            return Err(Error::BlockTableOverflow {
+                step,
+                offset: compressed_size,
+                expected: block_sizes_storage_size,
+                actual: buffer_remainder.len(),
+            });
         }
 
-        let (block_sizes_buffer, blocks_buffer) =
+        let (block_sizes_buffer, mut blocks_buffer) =
             buffer_remainder.split_at_mut(block_sizes_storage_size);
 
         compressed_size += block_sizes_buffer.len();
 
-        // make iterator over temporary blocks
-        let blocks_buffer_free_space = blocks_buffer.len();
-        let temp_block_size = blocks_buffer_free_space / block_count;
-
         let aux_data_chunks = Image::from_slice(aux_data, (width, height), channels * layers)
             .into_chunks_mut(bs, step);
 
-        let block_encode_results = process_maybe_parallel_map_collect(
-            aux_data_chunks
-                .zip(blocks_buffer.chunks_mut(temp_block_size))
-                .enumerate(),
-            |(block_index, (aux_data_chunk, mut output_block))| {
-                let empty_block_size = output_block.len();
-
+        // every block is encoded into its own buffer, they are copied to the output in order
+        if blocks.len() < block_count {
+            blocks.resize_with(block_count, Vec::new);
+        }
+        block_results.clear();
+        block_results.resize_with(block_count, || Ok(()));
+
+        #[cfg(feature = "rayon")]
+        let items = aux_data_chunks
+            .into_par_iter()
+            .zip(blocks.par_iter_mut())
+            .zip(block_results.par_iter_mut());
+        #[cfg(not(feature = "rayon"))]
+        let items = aux_data_chunks
+            .zip(blocks.iter_mut())
+            .zip(block_results.iter_mut());
+
+        process_maybe_parallel_indexed_for_each(
+            items,
+            |((aux_data_chunk, block), block_result)| {
                 let channel = aux_data_chunk.channel;
                 let is_first_block_in_channel =
                     aux_data_chunk.x_range.0 == 0 && aux_data_chunk.y_range.0 == 0;
 
-                {
-                    let mut output_block_writer = BitsIOWriter::new(&mut output_block);
-                    let quality = if is_chroma[channel] {
-                        chroma_quality
-                    } else {
-                        i32::from(header.quality)
-                    };
-                    encode(
+                // a block keeps its capacity between frames, and is encoded again into twice
+                // the space when it doesn't fit
+                let (x_range, y_range, step) = (
+                    aux_data_chunk.x_range,
+                    aux_data_chunk.y_range,
+                    aux_data_chunk.step,
+                );
+                let coefficients =
+                    (x_range.1 - x_range.0) * (y_range.1 - y_range.0) / (step * step);
+                let mut size = block.capacity().max(coefficients + 4);
+                *block_result = loop {
+                    block.resize(size, 0);
+                    let mut block_writer = SliceBitsWriter::new(block);
+                    match encode(
                         &aux_data_chunk,
-                        &mut output_block_writer,
-                        header.encoder,
-                        quality,
+                        &mut block_writer,
+                        header.encoder.coefficient_scheme(),
+                        coding.channels[channel].quality,
                         has_dc && is_first_block_in_channel,
-                        is_chroma[channel],
-                    )?;
-
-                    output_block_writer.flush_write_word()?;
-                }
-
-                // After writes to output_block, it's size (as slice) is reduced to free space
-                Ok((block_index, (empty_block_size - output_block.len()) as u32))
+                        coding.channels[channel].is_chroma,
+                    )
+                    .and_then(|_| block_writer.flush_write_word())
+                    {
+                        Ok(()) => {
+                            let position = block_writer.position();
+                            block.truncate(position);
+                            break Ok(());
+                        }
+                        Err(IOError::WriteZero) => size *= 2,
+                        Err(err) => break Err(Error::from(err)),
+                    }
+                };
             },
             hint_do_parallel,
         );
 
-        let mut sorted_block_encode_results = vec![0; block_encode_results.len()];
-        for result in block_encode_results {
-            match result {
-                Ok((i, v)) => sorted_block_encode_results[i] = v,
-                Err(e) => return Err(e),
-            }
-        }
-
-        // last block tail relative to blocks_buffer slice start
-        let mut tail_pos = 0;
-
-        for (block_index, (block_size, mut block_size_buffer)) in sorted_block_encode_results
-            .into_iter()
-            .zip(block_sizes_buffer.chunks_mut(std::mem::size_of::<u32>()))
+        for (block_index, ((block, block_result), block_size_buffer)) in blocks
+            .iter()
+            .zip(block_results.drain(..))
+            .zip(block_sizes_buffer.chunks_mut(mem::size_of::<u32>()))
             .enumerate()
         {
-            block_size_buffer.write_u32::<LittleEndian>(block_size / 4)?;
-
-            let block_size = block_size as usize;
-            if block_index != 0 {
-                let block_start = block_index * temp_block_size;
-                for i in 0..block_size {
-                    blocks_buffer[tail_pos + i] = blocks_buffer[block_start + i];
-                }
+            block_result?;
+            if blocks_buffer.len() < block.len() {
+                return Err(Error::BlockOverflow {
+                    step,
+                    block_index,
+                    channel: block_index / (block_count / (channels * layers)),
+                    offset: compressed_size,
+                });
             }
 
-            compressed_size += block_size;
-            tail_pos += block_size;
+            LittleEndian::write_u32(block_size_buffer, block.len() as u32 / 4);
+            let (output_block, rest) = mem::take(&mut blocks_buffer).split_at_mut(block.len());
+            output_block.copy_from_slice(block);
+            blocks_buffer = rest;
+
+            compressed_size += block.len();
         }
 
         has_dc = false;
-        step /= 2;
     }
 
     Ok(compressed_size)
@@ -258,45 +530,49 @@ pub fn decompress_image_data(
     downsampling: usize,
     test: bool,
     is_chroma: &[bool],
-) -> Result<usize, DecompressError> {
-    let chroma_quality = header.get_chroma_quality();
-
-    let width = header.width as usize;
-    let height = header.height as usize;
-    let layers = usize::from(header.layers);
-    let channels = usize::from(header.channels);
-
-    let mut step = 1;
-    while step * 2 < width || step * 2 < height {
-        step *= 2;
-    }
-    if (step << header.block_size) == 0 {
-        return Err(DecompressError::Underflow);
-    }
+) -> Result<usize, Error> {
+    decompress_image_data_with(
+        aux_data,
+        header,
+        buffer,
+        downsampling,
+        test,
+        &default_coding(header, is_chroma),
+        &mut Scratch::default(),
+    )
+}
 
+fn decompress_image_data_with(
+    aux_data: &mut [i16],
+    header: &header::Header,
+    buffer: &[u8],
+    downsampling: usize,
+    test: bool,
+    coding: &Coding,
+    scratch: &mut Scratch,
+) -> Result<usize, Error> {
     // guess next point of interest
     let mut next_point_of_interest = buffer.len() + 1024;
     let mut is_truncated = false;
-    let mut has_dc = true;
     let mut decompressed_size = 0;
-    let hint_do_parallel = aux_data.len() > Config::multithreading_factors().compress;
 
-    let downsampled_width = header.get_downsampled_width(downsampling);
-    let downsampled_height = header.get_downsampled_height(downsampling);
-
-    while (step >> downsampling) >= 1 {
-        let bs = step << header.block_size;
-
-        let block_count_x = (width + bs - 1) / bs;
-        let block_count_y = (height + bs - 1) / bs;
-        let block_count = block_count_x * block_count_y * layers * channels;
+    fill_step_levels(header, &mut scratch.levels)?;
 
+    for level_index in 0..scratch.levels.len() {
+        let (step, block_count) = scratch.levels[level_index];
+        if (step >> downsampling) < 1 {
+            break;
+        }
         is_truncated = true;
 
-        let block_sizes_storage_size = block_count * std::mem::size_of::<u32>();
+        let block_sizes_storage_size = block_count * mem::size_of::<u32>();
 
         if decompressed_size > buffer.len() {
-            return Err(DecompressError::Underflow);
+            return Err(Error::Truncated {
+                step,
+                expected: decompressed_size,
+                actual: buffer.len(),
+            });
         }
         let (_, buffer_remainder) = buffer.split_at(decompressed_size);
 
@@ -304,88 +580,37 @@ pub fn decompress_image_data(
             break;
         }
 
-        let (block_sizes_buffer, blocks_buffer) =
-            buffer_remainder.split_at(block_sizes_storage_size);
-
-        // get block sizes
-        let mut blocks_sizes = vec![0_usize; block_count];
-
-        for (mut chunk_size_buffer, block_size) in block_sizes_buffer
-            .chunks(std::mem::size_of::<u32>())
-            .zip(blocks_sizes.iter_mut())
-        {
-            *block_size = chunk_size_buffer.read_u32::<LittleEndian>().unwrap() as usize * 4;
-        }
-
-        let blocks_size_sum: usize = blocks_sizes.iter().sum();
-
-        next_point_of_interest = decompressed_size
-            + block_sizes_storage_size
-            + blocks_size_sum
-            + if step >> downsampling > 1 {
+        let level = StepLevel {
+            step,
+            block_count,
+            has_dc: level_index == 0,
+            offset: decompressed_size,
+            data: buffer_remainder,
+        };
+        let level_size = decompress_step_level(
+            aux_data,
+            header,
+            coding,
+            downsampling,
+            test,
+            &level,
+            scratch,
+        )?;
+
+        next_point_of_interest = decompressed_size.saturating_add(level_size).saturating_add(
+            if step >> downsampling > 1 {
                 // 4 times more block on next iteration
                 block_sizes_storage_size * 4
             } else {
                 0
-            };
-
-        if blocks_buffer.len() >= blocks_size_sum {
-            is_truncated = false;
-        }
-
-        let step_downsampled = step >> downsampling;
-        let block_size_downsampled = step_downsampled << header.block_size;
-
-        let aux_data_chunks = Image::from_slice(
-            aux_data,
-            (downsampled_width, downsampled_height),
-            channels * layers,
-        )
-        .into_chunks_mut(block_size_downsampled, step_downsampled);
-
-        let block_decode_results: Vec<std::io::Result<()>> = process_maybe_parallel_map_collect(
-            aux_data_chunks
-                .zip(VariableChunksIterator::new(&blocks_buffer, &blocks_sizes))
-                .enumerate(),
-            |(block_index, (aux_data_chunk, mut input_block))| {
-                // truncated chunk does not require any actions.
-                if test || input_block.len() < blocks_sizes[block_index] {
-                    return Ok(());
-                }
-
-                let channel = aux_data_chunk.channel;
-                let is_first_block_in_channel =
-                    aux_data_chunk.x_range.0 == 0 && aux_data_chunk.y_range.0 == 0;
-
-                {
-                    let mut input_block_reader = BitsIOReader::new(&mut input_block);
-                    let quality = if is_chroma[channel] {
-                        chroma_quality
-                    } else {
-                        i32::from(header.quality)
-                    };
-                    decode(
-                        aux_data_chunk,
-                        &mut input_block_reader,
-                        header.encoder,
-                        quality,
-                        has_dc && is_first_block_in_channel,
-                        is_chroma[channel],
-                    )?;
-                }
-
-                Ok(())
             },
-            hint_do_parallel,
         );
 
-        for result in block_decode_results {
-            result.map_err(|_| DecompressError::Underflow)?
+        if buffer_remainder.len() >= level_size {
+            is_truncated = false;
         }
 
-        has_dc = false;
-        step /= 2;
-        decompressed_size += block_sizes_storage_size + blocks_size_sum;
+        decompressed_size = decompressed_size.saturating_add(level_size);
     }
 
     if is_truncated {
@@ -394,3 +619,147 @@ pub fn decompress_image_data(
         Ok(0)
     }
 }
+
+/// Step level of an encoded stream: the block table followed by the blocks, which may be cut
+/// anywhere after the table.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct StepLevel<'a> {
+    pub step: usize,
+    pub block_count: usize,
+    /// Only the first level carries the DC coefficients.
+    pub has_dc: bool,
+    /// Offset of `data` in the stream, used in errors.
+    pub offset: usize,
+    pub data: &'a [u8],
+}
+
+/// Decodes the complete blocks of `level` into `aux_data` and returns the size of the whole
+/// level according to its block table.
+pub(crate) fn decompress_step_level(
+    aux_data: &mut [i16],
+    header: &header::Header,
+    coding: &Coding,
+    downsampling: usize,
+    test: bool,
+    level: &StepLevel<'_>,
+    scratch: &mut Scratch,
+) -> Result<usize, Error> {
+    let layers = usize::from(header.layers);
+    let channels = usize::from(header.channels);
+    let hint_do_parallel = aux_data.len() > scratch.factors.compress;
+
+    let downsampled_width = header.get_downsampled_width(downsampling);
+    let downsampled_height = header.get_downsampled_height(downsampling);
+
+    let StepLevel {
+        step,
+        block_count,
+        has_dc,
+        offset,
+        data,
+    } = *level;
+    let block_sizes_storage_size = block_count * mem::size_of::<u32>();
+    if data.len() < block_sizes_storage_size {
+        return Err(Error::Truncated {
+            step,
+            expected: offset + block_sizes_storage_size,
+            actual: offset + data.len(),
+        });
+    }
+    let (block_sizes_buffer, blocks_buffer) = data.split_at(block_sizes_storage_size);
+
+    let Scratch {
+        block_sizes: blocks_sizes,
+        block_offsets: blocks_offsets,
+        block_results,
+        ..
+    } = scratch;
+
+    // get block sizes
+    // block sizes come straight from the stream, so all offset arithmetic saturates
+    blocks_sizes.clear();
+    blocks_sizes.extend(
+        block_sizes_buffer
+            .chunks(mem::size_of::<u32>())
+            .map(|chunk| (LittleEndian::read_u32(chunk) as usize).saturating_mul(4)),
+    );
+
+    let blocks_offset = offset + block_sizes_storage_size;
+    blocks_offsets.clear();
+    blocks_offsets.extend(
+        blocks_sizes
+            .iter()
+            .scan(blocks_offset, |offset, block_size| {
+                let block_offset = *offset;
+                *offset = offset.saturating_add(*block_size);
+                Some(block_offset)
+            }),
+    );
+    let blocks_size_sum = blocks_sizes
+        .iter()
+        .fold(0usize, |sum, block_size| sum.saturating_add(*block_size));
+
+    let step_downsampled = step >> downsampling;
+    let block_size_downsampled = step_downsampled << header.block_size;
+
+    let aux_data_chunks = Image::from_slice(
+        aux_data,
+        (downsampled_width, downsampled_height),
+        channels * layers,
+    )
+    .into_chunks_mut(block_size_downsampled, step_downsampled);
+
+    block_results.clear();
+    block_results.resize_with(block_count, || Ok(()));
+
+    let blocks_sizes = &*blocks_sizes;
+    let blocks_offsets = &*blocks_offsets;
+    #[cfg(feature = "rayon")]
+    let blocks = aux_data_chunks
+        .into_par_iter()
+        .zip(VariableChunksIterator::new(blocks_buffer, blocks_sizes).into_par_iter())
+        .zip(block_results.par_iter_mut())
+        .enumerate();
+    #[cfg(not(feature = "rayon"))]
+    let blocks = aux_data_chunks
+        .zip(VariableChunksIterator::new(blocks_buffer, blocks_sizes))
+        .zip(block_results.iter_mut())
+        .enumerate();
+
+    process_maybe_parallel_indexed_for_each(
+        blocks,
+        |(block_index, ((aux_data_chunk, input_block), block_result))| {
+            // truncated chunk does not require any actions.
+            if test || input_block.len() < blocks_sizes[block_index] {
+                return;
+            }
+
+            let channel = aux_data_chunk.channel;
+            let is_first_block_in_channel =
+                aux_data_chunk.x_range.0 == 0 && aux_data_chunk.y_range.0 == 0;
+
+            let mut input_block_reader = SliceBitsReader::new(input_block);
+            *block_result = decode(
+                aux_data_chunk,
+                &mut input_block_reader,
+                header.encoder.coefficient_scheme(),
+                coding.channels[channel].quality,
+                has_dc && is_first_block_in_channel,
+                coding.channels[channel].is_chroma,
+            )
+            .map_err(|_| Error::BlockUnderflow {
+                step,
+                block_index,
+                channel,
+                offset: blocks_offsets[block_index],
+            });
+        },
+        hint_do_parallel,
+    );
+
+    for result in block_results.drain(..) {
+        result?;
+    }
+
+    Ok(block_sizes_storage_size.saturating_add(blocks_size_sum))
+}
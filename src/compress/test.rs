@@ -19,6 +19,8 @@ fn test_lift_and_quantize() {
         block_size: header::BLOCK_DEFAULT,
         filter: header::Filter::Linear,
         quantization: header::Quantization::Scalar,
+        weighting: header::Weighting::Flat,
+        has_extension: false,
         encoder: header::Encoder::Contextual,
         intent: header::Intent::RGB,
         metadata_size: 0,
@@ -61,7 +63,14 @@ fn test_lift_and_quantize() {
         -26, 0, -26, 0,
     ];
 
-    lift_and_quantize(&mut aux_data, &header, &[false; 3]);
+    let coding = default_coding(&header, &[false; 3]);
+    lift_and_quantize(
+        &mut aux_data,
+        &header,
+        &coding,
+        &mut vec![],
+        &MultithreadingFactors::default(),
+    );
     assert_eq!(aux_data, expected);
 }
 
@@ -84,6 +93,8 @@ fn test_compress_lossy_contextual_linear() {
         block_size: header::BLOCK_DEFAULT,
         filter: header::Filter::Linear,
         quantization: header::Quantization::Scalar,
+        weighting: header::Weighting::Flat,
+        has_extension: false,
         encoder: header::Encoder::Contextual,
         intent: header::Intent::RGB,
         metadata_size: 0,
@@ -128,6 +139,8 @@ fn test_compress_lossy_turbo_cubic() {
         block_size: header::BLOCK_DEFAULT,
         filter: header::Filter::Cubic,
         quantization: header::Quantization::Scalar,
+        weighting: header::Weighting::Flat,
+        has_extension: false,
         encoder: header::Encoder::Turbo,
         intent: header::Intent::RGB,
         metadata_size: 0,
@@ -173,6 +186,8 @@ fn test_compress_loseless_fast_linear_block_max() {
         block_size: header::BLOCK_MAX,
         filter: header::Filter::Linear,
         quantization: header::Quantization::Scalar,
+        weighting: header::Weighting::Flat,
+        has_extension: false,
         encoder: header::Encoder::Fast,
         intent: header::Intent::RGB,
         metadata_size: 0,
@@ -254,6 +269,8 @@ fn test_decompress_lossy_contextual_linear() {
         block_size: header::BLOCK_DEFAULT,
         filter: header::Filter::Linear,
         quantization: header::Quantization::Scalar,
+        weighting: header::Weighting::Flat,
+        has_extension: false,
         encoder: header::Encoder::Contextual,
         intent: header::Intent::RGB,
         metadata_size: 0,
@@ -296,6 +313,8 @@ fn test_decompress_lossy_contextual_linear_downsampled() {
         block_size: header::BLOCK_DEFAULT,
         filter: header::Filter::Linear,
         quantization: header::Quantization::Scalar,
+        weighting: header::Weighting::Flat,
+        has_extension: false,
         encoder: header::Encoder::Contextual,
         intent: header::Intent::RGB,
         metadata_size: 0,
@@ -368,6 +387,8 @@ fn test_decompress_truncated() {
         block_size: 7,
         filter: header::Filter::Linear,
         quantization: header::Quantization::Scalar,
+        weighting: header::Weighting::Flat,
+        has_extension: false,
         encoder: header::Encoder::Contextual,
         intent: header::Intent::RGB,
         metadata_size: 0,
@@ -409,6 +430,8 @@ fn test_decompress_invalid_block_length() {
         block_size: header::BLOCK_DEFAULT,
         filter: header::Filter::Linear,
         quantization: header::Quantization::Scalar,
+        weighting: header::Weighting::Flat,
+        has_extension: false,
         encoder: header::Encoder::Turbo,
         intent: header::Intent::RGB,
         metadata_size: 0,
@@ -418,7 +441,11 @@ fn test_decompress_invalid_block_length() {
 
     let mut buffer = vec![0i16; header.get_decompress_buffer_size(0)];
     match decompress_aux_data(&compressed, &header, &[false; 3], 0, false, &mut buffer) {
-        Err(DecompressError::Underflow) => (),
+        Err(Error::Truncated {
+            step: 1,
+            actual: 72,
+            ..
+        }) => (),
         Err(e) => panic!("unexpected error: {:?}", e),
         Ok(_) => panic!("decompress must return error on invalid block lenth"),
     }
@@ -455,6 +482,8 @@ fn test_decompress_invalid_block_length2() {
         block_size: header::BLOCK_DEFAULT,
         filter: header::Filter::Linear,
         quantization: header::Quantization::Scalar,
+        weighting: header::Weighting::Flat,
+        has_extension: false,
         encoder: header::Encoder::Contextual,
         intent: header::Intent::RGB,
         metadata_size: 0,
@@ -464,7 +493,12 @@ fn test_decompress_invalid_block_length2() {
 
     let mut buffer = vec![0i16; header.get_decompress_buffer_size(0)];
     match decompress_aux_data(&compressed, &header, &[false; 3], 0, false, &mut buffer) {
-        Err(DecompressError::Underflow) => (),
+        Err(Error::BlockUnderflow {
+            step: 8,
+            block_index: 2,
+            channel: 2,
+            offset: 28,
+        }) => (),
         Err(e) => panic!("unexpected error: {:?}", e),
         Ok(_) => panic!("decompress must return error on invalid block lenth"),
     }
@@ -501,6 +535,8 @@ fn test_decompress_invalid_block_length3() {
         block_size: header::BLOCK_DEFAULT,
         filter: header::Filter::Linear,
         quantization: header::Quantization::Scalar,
+        weighting: header::Weighting::Flat,
+        has_extension: false,
         encoder: header::Encoder::Contextual,
         intent: header::Intent::RGB,
         metadata_size: 0,
@@ -510,8 +546,54 @@ fn test_decompress_invalid_block_length3() {
 
     let mut buffer = vec![0i16; header.get_decompress_buffer_size(0)];
     match decompress_aux_data(&compressed, &header, &[false; 3], 0, false, &mut buffer) {
-        Err(DecompressError::Underflow) => (),
+        Err(Error::BlockUnderflow {
+            step: 2,
+            block_index: 2,
+            channel: 2,
+            offset: 108,
+        }) => (),
         Err(e) => panic!("unexpected error: {:?}", e),
         Ok(_) => panic!("decompress must return error on invalid block lenth"),
     }
 }
+
+#[test]
+fn test_compress_into_exact_buffer() {
+    let header = header::HeaderBuilder {
+        width: 61,
+        height: 35,
+        quality: 1024,
+        chroma_scale: 1,
+        ..header::HeaderBuilder::test_default()
+    }
+    .build()
+    .unwrap();
+    // noise in a few blocks, so that block sizes vary a lot
+    let aux_data = (0..header.get_image_size())
+        .map(|i| {
+            if i % 61 < 8 {
+                ((i * 7919) % 509) as i16
+            } else {
+                0
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let mut expected = vec![0; aux_data.len() * 4];
+    let size =
+        compress_aux_data(&mut aux_data.clone(), &header, &[false; 3], &mut expected).unwrap();
+
+    // equal slots per block used to make this fail, although the result fits
+    let mut actual = vec![0; size];
+    assert_eq!(
+        compress_aux_data(&mut aux_data.clone(), &header, &[false; 3], &mut actual).unwrap(),
+        size
+    );
+    assert_eq!(actual, &expected[..size]);
+
+    let mut short = vec![0; size - 4];
+    match compress_aux_data(&mut aux_data.clone(), &header, &[false; 3], &mut short) {
+        Err(Error::BlockOverflow { step: 1, .. }) => (),
+        r => panic!("last block must not fit: {:?}", r),
+    }
+}
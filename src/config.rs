@@ -9,6 +9,9 @@ const DEFAULT_MULTITHREADING_FACTORS: MultithreadingFactors = MultithreadingFact
     compress: 128 * 128,
 };
 
+/// Minimal number of samples for which every stage is run in parallel, when the
+/// `adaptive_multithreading` feature is enabled.
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub struct MultithreadingFactors {
     pub linear_horizontal_lifting: usize,
     pub linear_vertical_lifting: usize,
@@ -18,7 +21,14 @@ pub struct MultithreadingFactors {
     pub compress: usize,
 }
 
+impl Default for MultithreadingFactors {
+    fn default() -> Self {
+        DEFAULT_MULTITHREADING_FACTORS
+    }
+}
+
 impl Config {
+    /// Default multithreading factors, which `Compressor` and `Decompressor` can override.
     pub fn multithreading_factors() -> &'static MultithreadingFactors {
         &DEFAULT_MULTITHREADING_FACTORS
     }
@@ -0,0 +1,523 @@
+#[cfg(feature = "rayon")]
+use alloc::sync::Arc;
+use alloc::{string::String, vec, vec::Vec};
+
+use num_traits::cast::NumCast;
+
+use crate::alpha::{fill_transparent, premultiply, AlphaOptions, TransparentColor};
+use crate::color_transform::ColorTransformProgram;
+use crate::compress::{compress_aux_data_with, decompress_aux_data_with, Scratch};
+use crate::config::MultithreadingFactors;
+use crate::errors::{Error, HeaderErr};
+use crate::extension::{Coding, Extension};
+use crate::header::{Header, Quantization, QUALITY_MAX};
+use crate::image_view::{check_planes, ImageView, ImageViewMut, Plane, PlaneMut};
+use crate::io::{IOError, Write};
+use crate::quant::{DeadZone, QualityMap};
+
+#[cfg(test)]
+mod test;
+
+/// Thread pool the parallel stages run on, rayon's global one by default.
+#[derive(Clone, Debug, Default)]
+struct Threads {
+    #[cfg(feature = "rayon")]
+    pool: Option<Arc<rayon::ThreadPool>>,
+}
+
+impl Threads {
+    #[cfg(feature = "rayon")]
+    fn with_max_threads(max_threads: usize) -> Result<Self, Error> {
+        if max_threads == 0 {
+            return Err(Error::NoThreads);
+        }
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(max_threads)
+            .build()
+            .map_err(std::io::Error::other)?;
+        Ok(Threads {
+            pool: Some(Arc::new(pool)),
+        })
+    }
+
+    fn install<R, F>(&self, op: F) -> R
+    where
+        R: Send,
+        F: FnOnce() -> R + Send,
+    {
+        #[cfg(feature = "rayon")]
+        {
+            if let Some(pool) = &self.pool {
+                return pool.install(op);
+            }
+        }
+        op()
+    }
+}
+
+/// Compression context for a sequence of images sharing the header and the color transform,
+/// e.g. frames of a screen stream.
+///
+/// The working buffers are allocated once and reused, so compressing a frame doesn't allocate.
+/// With the `rayon` feature, the parallel processing may still allocate internally.
+#[derive(Debug)]
+pub struct Compressor {
+    threads: Threads,
+    /// Header of the streams, with the coding extension added to the metadata area.
+    header: Header,
+    color_transform: ColorTransformProgram,
+    extension: Extension,
+    coding: Coding,
+    transparent: TransparentColor,
+    /// Encoded header, metadata area and color transform program, the same for every image.
+    prefix: Vec<u8>,
+    aux_data: Vec<i16>,
+    scratch: Scratch,
+}
+
+impl Compressor {
+    pub fn new(header: &Header, color_transform: &ColorTransformProgram) -> Result<Self, Error> {
+        let mut compressor = Compressor {
+            threads: Threads::default(),
+            header: header.clone(),
+            color_transform: color_transform.clone(),
+            extension: Extension::default(),
+            coding: Coding::default(),
+            transparent: TransparentColor::Keep,
+            prefix: vec![],
+            aux_data: vec![0; header.get_image_size()],
+            scratch: Scratch::default(),
+        };
+        compressor.set_extension(Extension::default())?;
+        Ok(compressor)
+    }
+
+    /// Codes the alpha channel of RGBA and BGRA images with its own quality and replaces the
+    /// color of fully transparent pixels, see `AlphaOptions`. Unless the color is kept, the
+    /// color transform program must leave the alpha channel as is.
+    pub fn with_alpha(mut self, options: AlphaOptions) -> Result<Self, Error> {
+        let alpha = self
+            .header
+            .get_alpha_channel()
+            .ok_or(Error::NoAlphaChannel)?;
+        if options.quality == 0 || options.quality > QUALITY_MAX {
+            return Err(HeaderErr::WrongValue(String::from(
+                "Alpha quality must be in range (0..1025)",
+            ))
+            .into());
+        }
+        if options.transparent != TransparentColor::Keep {
+            let channels = self.header.channels as usize;
+            if let Some(transform) = self
+                .color_transform
+                .iter()
+                .find(|t| t.dest_channel % channels == alpha)
+            {
+                return Err(Error::AlphaTransformed {
+                    channel: transform.dest_channel,
+                });
+            }
+        }
+
+        let mut extension = self.extension.clone();
+        extension.alpha_quality = Some(options.quality);
+        self.set_extension(extension)?;
+        self.transparent = options.transparent;
+        Ok(self)
+    }
+
+    /// Quantizes every channel of every layer with its own quality from `qualities`, instead
+    /// of `header.quality` and the chroma quality. Channels of the second and further layers
+    /// follow the channels of the first one. 0 keeps the quality the channel would have without
+    /// the table, `QUALITY_MAX` codes the channel losslessly. The table is stored in the coding
+    /// extension of the stream, an empty one removes it.
+    pub fn with_channel_qualities(mut self, qualities: &[u16]) -> Result<Self, Error> {
+        if qualities.iter().any(|&quality| quality > QUALITY_MAX) {
+            return Err(HeaderErr::WrongValue(String::from(
+                "Channel qualities must be in range (0..1025)",
+            ))
+            .into());
+        }
+
+        let mut extension = self.extension.clone();
+        extension.channel_qualities = qualities.to_vec();
+        extension.check(&self.header)?;
+        self.set_extension(extension)?;
+        Ok(self)
+    }
+
+    /// Scales the quality of the regions of the image by `map`, e.g. to keep a region of interest
+    /// sharp. Channels coded losslessly stay lossless. The map is stored in the coding extension
+    /// of the stream, `None` removes it.
+    pub fn with_quality_map(mut self, map: Option<QualityMap>) -> Result<Self, Error> {
+        let mut extension = self.extension.clone();
+        extension.quality_map = map;
+        extension.check(&self.header)?;
+        self.set_extension(extension)?;
+        Ok(self)
+    }
+
+    /// Quantizes with `Quantization::DeadZone`, zeroing coefficients as `dead_zone` decides. The
+    /// header of the streams records the quantization, the parameters only affect the encoder.
+    pub fn with_dead_zone(mut self, dead_zone: DeadZone) -> Result<Self, Error> {
+        self.header.quantization = Quantization::DeadZone;
+        self.coding.dead_zone = dead_zone;
+        let extension = self.extension.clone();
+        self.set_extension(extension)?;
+        Ok(self)
+    }
+
+    /// Replaces the coding extension, updating the header and the encoded prefix.
+    fn set_extension(&mut self, extension: Extension) -> Result<(), Error> {
+        let mut header = self.header.clone();
+        header.metadata_size -= self.extension.encoded_size() as u32;
+        let header = extension.extend_header(&header);
+
+        let mut prefix = vec![];
+        header.encode(&mut prefix)?;
+        prefix.resize(
+            prefix.len() + header.metadata_size as usize - extension.encoded_size(),
+            0,
+        );
+        extension.encode(&mut prefix)?;
+        let is_chroma = self.color_transform.encode(
+            header.channels as usize * header.layers as usize,
+            &mut prefix,
+        )?;
+        self.coding.update(&header, &is_chroma, &extension);
+
+        self.header = header;
+        self.extension = extension;
+        self.prefix = prefix;
+        Ok(())
+    }
+
+    /// Runs the parallel stages on `pool` instead of rayon's global thread pool.
+    #[cfg(feature = "rayon")]
+    pub fn with_thread_pool(mut self, pool: Arc<rayon::ThreadPool>) -> Self {
+        self.threads = Threads { pool: Some(pool) };
+        self
+    }
+
+    /// Runs the parallel stages on an own thread pool of at most `max_threads` threads. Fails
+    /// with `Error::NoThreads` if `max_threads` is 0.
+    #[cfg(feature = "rayon")]
+    pub fn with_max_threads(mut self, max_threads: usize) -> Result<Self, Error> {
+        self.threads = Threads::with_max_threads(max_threads)?;
+        Ok(self)
+    }
+
+    /// Runs the stages in parallel from the sample counts of `factors` on, with the
+    /// `adaptive_multithreading` feature, instead of `Config::multithreading_factors`.
+    pub fn with_multithreading_factors(mut self, factors: MultithreadingFactors) -> Self {
+        self.scratch.factors = factors;
+        self
+    }
+
+    /// Header of the compressed streams. The metadata area includes the coding extension, if
+    /// there is one.
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
+    /// Same as `compress_simple`.
+    pub fn compress(&mut self, image: &[u8], buffer: &mut [u8]) -> Result<usize, Error> {
+        let width = self.header.width as usize;
+        let channels = self.header.channels as usize;
+        let image = ImageView::with_layers(
+            image,
+            width,
+            self.header.height as usize,
+            self.header.layers as usize,
+            channels,
+            width * channels,
+        )?;
+        self.compress_view(&image, buffer)
+    }
+
+    /// Same as `compress_view`.
+    pub fn compress_view<T>(
+        &mut self,
+        image: &ImageView<'_, T>,
+        buffer: &mut [u8],
+    ) -> Result<usize, Error>
+    where
+        T: Into<i16> + Copy,
+    {
+        self.clear_aux_data();
+        self.color_transform
+            .transform_view(image, &self.header, &mut self.aux_data)?;
+        fill_transparent(&mut self.aux_data, &self.header, self.transparent);
+        self.compress_transformed(buffer)
+    }
+
+    /// Same as `compress_planes`.
+    pub fn compress_planes<T>(
+        &mut self,
+        planes: &[Plane<'_, T>],
+        buffer: &mut [u8],
+    ) -> Result<usize, Error>
+    where
+        T: Into<i16> + Copy,
+    {
+        self.clear_aux_data();
+        self.color_transform
+            .transform_planes(planes, &self.header, &mut self.aux_data)?;
+        fill_transparent(&mut self.aux_data, &self.header, self.transparent);
+        self.compress_transformed(buffer)
+    }
+
+    /// The color transform accumulates into the transformed channels.
+    fn clear_aux_data(&mut self) {
+        for value in self.aux_data.iter_mut() {
+            *value = 0;
+        }
+    }
+
+    fn compress_transformed(&mut self, mut buffer: &mut [u8]) -> Result<usize, Error> {
+        buffer.write_all(&self.prefix)?;
+
+        let Compressor {
+            threads,
+            header,
+            coding,
+            aux_data,
+            scratch,
+            ..
+        } = self;
+        let size = threads
+            .install(|| compress_aux_data_with(aux_data, header, coding, buffer, scratch))?;
+
+        Ok(self.prefix.len() + size)
+    }
+}
+
+/// Decompression context reusing its working buffers between images.
+///
+/// The color transform program of the previous image is kept too, and is decoded again only if
+/// the header or the program change, so decompressing a frame of a stream doesn't allocate.
+/// With the `rayon` feature, the parallel processing may still allocate internally.
+#[derive(Debug, Default)]
+pub struct Decompressor {
+    threads: Threads,
+    /// Header the cached program was decoded for.
+    header: Option<Header>,
+    /// Encoded color transform program.
+    program: Vec<u8>,
+    color_transform: ColorTransformProgram,
+    is_chroma: Vec<bool>,
+    /// Encoded coding extension `coding` was computed for, together with the program.
+    extension: Vec<u8>,
+    coding: Coding,
+    premultiply_alpha: bool,
+    aux_data: Vec<i16>,
+    scratch: Scratch,
+}
+
+impl Decompressor {
+    pub fn new() -> Self {
+        Decompressor::default()
+    }
+
+    /// Multiplies the color channels of RGBA and BGRA images by alpha on output.
+    pub fn with_premultiplied_alpha(mut self, premultiplied: bool) -> Self {
+        self.premultiply_alpha = premultiplied;
+        self
+    }
+
+    /// Runs the parallel stages on `pool` instead of rayon's global thread pool.
+    #[cfg(feature = "rayon")]
+    pub fn with_thread_pool(mut self, pool: Arc<rayon::ThreadPool>) -> Self {
+        self.threads = Threads { pool: Some(pool) };
+        self
+    }
+
+    /// Runs the parallel stages on an own thread pool of at most `max_threads` threads. Fails
+    /// with `Error::NoThreads` if `max_threads` is 0.
+    #[cfg(feature = "rayon")]
+    pub fn with_max_threads(mut self, max_threads: usize) -> Result<Self, Error> {
+        self.threads = Threads::with_max_threads(max_threads)?;
+        Ok(self)
+    }
+
+    /// Runs the stages in parallel from the sample counts of `factors` on, with the
+    /// `adaptive_multithreading` feature, instead of `Config::multithreading_factors`.
+    pub fn with_multithreading_factors(mut self, factors: MultithreadingFactors) -> Self {
+        self.scratch.factors = factors;
+        self
+    }
+
+    /// Same as `decompress_simple`.
+    pub fn decompress(
+        &mut self,
+        data: &[u8],
+        header: &Header,
+        downsampling: usize,
+        test: bool,
+        buffer: &mut [u8],
+    ) -> Result<usize, Error> {
+        let width = header.get_downsampled_width(downsampling);
+        let channels = header.channels as usize;
+        let mut image = ImageViewMut::with_layers(
+            buffer,
+            width,
+            header.get_downsampled_height(downsampling),
+            header.layers as usize,
+            channels,
+            width * channels,
+        )?;
+        self.decompress_view(data, header, downsampling, test, &mut image)
+    }
+
+    /// Same as `decompress_view`.
+    pub fn decompress_view<T>(
+        &mut self,
+        data: &[u8],
+        header: &Header,
+        downsampling: usize,
+        test: bool,
+        image: &mut ImageViewMut<'_, T>,
+    ) -> Result<usize, Error>
+    where
+        T: NumCast,
+    {
+        image.shape().check(
+            header.get_downsampled_width(downsampling),
+            header.get_downsampled_height(downsampling),
+            header.layers as usize,
+            header.channels as usize,
+        )?;
+
+        let next_point_of_interest =
+            self.decompress_untransformed(data, header, downsampling, test)?;
+        if self.premultiply(header, downsampling) {
+            ColorTransformProgram::new().detransform_to_view(
+                &mut self.aux_data,
+                header,
+                downsampling,
+                image,
+            )?;
+        } else {
+            self.color_transform.detransform_to_view(
+                &mut self.aux_data,
+                header,
+                downsampling,
+                image,
+            )?;
+        }
+
+        Ok(next_point_of_interest)
+    }
+
+    /// Same as `decompress_planes`.
+    pub fn decompress_planes<T>(
+        &mut self,
+        data: &[u8],
+        header: &Header,
+        downsampling: usize,
+        test: bool,
+        planes: &mut [PlaneMut<'_, T>],
+    ) -> Result<usize, Error>
+    where
+        T: NumCast,
+    {
+        check_planes(
+            planes.iter().map(PlaneMut::shape),
+            header.layers as usize * header.channels as usize,
+            header.get_downsampled_width(downsampling),
+            header.get_downsampled_height(downsampling),
+        )?;
+
+        let next_point_of_interest =
+            self.decompress_untransformed(data, header, downsampling, test)?;
+        if self.premultiply(header, downsampling) {
+            ColorTransformProgram::new().detransform_to_planes(
+                &mut self.aux_data,
+                header,
+                downsampling,
+                planes,
+            )?;
+        } else {
+            self.color_transform.detransform_to_planes(
+                &mut self.aux_data,
+                header,
+                downsampling,
+                planes,
+            )?;
+        }
+
+        Ok(next_point_of_interest)
+    }
+
+    /// Undoes the color transform and premultiplies alpha in `aux_data` if requested and the
+    /// image has alpha. Returns `false` if the color transform is still to be undone.
+    fn premultiply(&mut self, header: &Header, downsampling: usize) -> bool {
+        if !self.premultiply_alpha || header.get_alpha_channel().is_none() {
+            return false;
+        }
+        let channel_size = header.get_downsampled_channel_size(downsampling);
+        self.color_transform
+            .detransform_aux(&mut self.aux_data, channel_size);
+        premultiply(&mut self.aux_data, header, channel_size);
+        true
+    }
+
+    /// Decodes everything following the header into `aux_data`, up to the color transform.
+    fn decompress_untransformed(
+        &mut self,
+        mut data: &[u8],
+        header: &Header,
+        downsampling: usize,
+        test: bool,
+    ) -> Result<usize, Error> {
+        let metadata_size = header.metadata_size as usize;
+        if data.len() < metadata_size {
+            return Err(IOError::UnexpectedEof.into());
+        }
+        let metadata = &data[..metadata_size];
+        data = &data[metadata_size..];
+
+        // the program encoding is prefix-free, so the same bytes decode to the same program
+        let program_changed =
+            self.header.as_ref() != Some(header) || !data.starts_with(&self.program);
+        if program_changed {
+            self.header = None;
+            self.is_chroma.clear();
+            self.is_chroma
+                .resize(header.layers as usize * header.channels as usize, false);
+
+            let mut rest = data;
+            self.color_transform = ColorTransformProgram::decode(&mut rest, &mut self.is_chroma)?;
+            self.program.clear();
+            self.program
+                .extend_from_slice(&data[..data.len() - rest.len()]);
+            self.header = Some(header.clone());
+        }
+        data = &data[self.program.len()..];
+
+        // decoding the extension allocates, so it is only done when the stream changes it
+        let encoded_extension = Extension::find(metadata, header);
+        if program_changed || encoded_extension != &self.extension[..] {
+            self.header = None;
+            let extension = Extension::decode(metadata, header)?;
+            self.coding.update(header, &self.is_chroma, &extension);
+            self.extension.clear();
+            self.extension.extend_from_slice(encoded_extension);
+            self.header = Some(header.clone());
+        }
+
+        self.aux_data.clear();
+        self.aux_data
+            .resize(header.get_downsampled_image_size(downsampling), 0);
+        let Decompressor {
+            threads,
+            coding,
+            aux_data,
+            scratch,
+            ..
+        } = self;
+        threads.install(|| {
+            decompress_aux_data_with(data, header, coding, downsampling, test, aux_data, scratch)
+        })
+    }
+}
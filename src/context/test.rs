@@ -0,0 +1,710 @@
+use super::*;
+use crate::color_transform::ChannelTransformBuilder;
+use crate::header::{Encoder, Filter, HeaderBuilder, Intent, Weighting};
+use crate::quant::{QUALITY_SCALE_MAX, QUALITY_SCALE_ONE};
+
+#[test]
+fn test_compressor_matches_compress_simple() {
+    let header = HeaderBuilder {
+        width: 29,
+        height: 17,
+        metadata_size: 4,
+        ..HeaderBuilder::test_default()
+    }
+    .build()
+    .unwrap();
+    let color_transform = ColorTransformProgram::rgb_to_yuv();
+    let mut compressor = Compressor::new(&header, &color_transform).unwrap();
+    assert_eq!(compressor.header(), &header);
+
+    for seed in 0..3 {
+        let frame = header.test_frame(seed);
+        let mut expected = vec![0; frame.len() * 4];
+        let expected_size =
+            crate::compress_simple(&frame, &header, &color_transform, &mut expected).unwrap();
+
+        let mut actual = vec![0; frame.len() * 4];
+        let actual_size = compressor.compress(&frame, &mut actual).unwrap();
+        assert_eq!(&actual[..actual_size], &expected[..expected_size]);
+    }
+}
+
+#[test]
+fn test_decompressor_matches_decompress_simple() {
+    let headers = [
+        HeaderBuilder {
+            width: 29,
+            height: 17,
+            metadata_size: 4,
+            ..HeaderBuilder::test_default()
+        }
+        .build()
+        .unwrap(),
+        HeaderBuilder {
+            width: 29,
+            height: 17,
+            metadata_size: 4,
+            ..HeaderBuilder::test_default()
+        }
+        .build()
+        .unwrap(),
+        HeaderBuilder {
+            width: 16,
+            height: 33,
+            quality: 1024,
+            metadata_size: 4,
+            ..HeaderBuilder::test_default()
+        }
+        .build()
+        .unwrap(),
+    ];
+    // the same header with another program must not reuse the cached one
+    let programs = [
+        ColorTransformProgram::rgb_to_yuv(),
+        ColorTransformProgram::rgb_to_a710(),
+        ColorTransformProgram::new(),
+    ];
+    let mut decompressor = Decompressor::new();
+
+    for (seed, (header, color_transform)) in headers.iter().zip(&programs).enumerate() {
+        let frame = header.test_frame(seed);
+        let mut compressed = vec![0; frame.len() * 4];
+        let size =
+            crate::compress_simple(&frame, header, color_transform, &mut compressed).unwrap();
+        let data = &compressed[crate::header::HEADER_SIZE..size];
+
+        for &downsampling in &[0, 1, 0] {
+            let buffer_size = header.get_decompress_buffer_size(downsampling);
+            let mut expected = vec![0; buffer_size];
+            crate::decompress_simple(data, header, downsampling, false, &mut expected).unwrap();
+
+            let mut actual = vec![0; buffer_size];
+            decompressor
+                .decompress(data, header, downsampling, false, &mut actual)
+                .unwrap();
+            assert_eq!(actual, expected);
+        }
+    }
+}
+
+#[test]
+fn test_decompressor_recovers_after_error() {
+    let header = HeaderBuilder {
+        width: 29,
+        height: 17,
+        metadata_size: 4,
+        ..HeaderBuilder::test_default()
+    }
+    .build()
+    .unwrap();
+    let frame = header.test_frame(0);
+    let mut compressed = vec![0; frame.len() * 4];
+    let size = crate::compress_simple(
+        &frame,
+        &header,
+        &ColorTransformProgram::rgb_to_yuv(),
+        &mut compressed,
+    )
+    .unwrap();
+    let data = &compressed[crate::header::HEADER_SIZE..size];
+
+    let mut decompressor = Decompressor::new();
+    let mut buffer = vec![0; header.get_decompress_buffer_size(0)];
+    assert!(decompressor
+        .decompress(&data[..2], &header, 0, false, &mut buffer)
+        .is_err());
+
+    let mut expected = vec![0; buffer.len()];
+    crate::decompress_simple(data, &header, 0, false, &mut expected).unwrap();
+    decompressor
+        .decompress(data, &header, 0, false, &mut buffer)
+        .unwrap();
+    assert_eq!(buffer, expected);
+}
+
+#[cfg(feature = "rayon")]
+#[test]
+fn test_own_thread_pool() {
+    let header = HeaderBuilder {
+        width: 29,
+        height: 17,
+        metadata_size: 4,
+        ..HeaderBuilder::test_default()
+    }
+    .build()
+    .unwrap();
+    let color_transform = ColorTransformProgram::rgb_to_yuv();
+    let frame = header.test_frame(1);
+    let mut expected = vec![0; frame.len() * 4];
+    let expected_size =
+        crate::compress_simple(&frame, &header, &color_transform, &mut expected).unwrap();
+    let data = &expected[crate::header::HEADER_SIZE..expected_size];
+    let mut expected_image = vec![0; header.get_decompress_buffer_size(0)];
+    crate::decompress_simple(data, &header, 0, false, &mut expected_image).unwrap();
+
+    let pool = Arc::new(
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(2)
+            .build()
+            .unwrap(),
+    );
+    let compressors = vec![
+        Compressor::new(&header, &color_transform)
+            .unwrap()
+            .with_max_threads(1)
+            .unwrap(),
+        Compressor::new(&header, &color_transform)
+            .unwrap()
+            .with_thread_pool(pool.clone()),
+    ];
+    let decompressors = vec![
+        Decompressor::new().with_max_threads(1).unwrap(),
+        Decompressor::new().with_thread_pool(pool),
+    ];
+
+    for (mut compressor, mut decompressor) in compressors.into_iter().zip(decompressors) {
+        let mut actual = vec![0; frame.len() * 4];
+        let actual_size = compressor.compress(&frame, &mut actual).unwrap();
+        assert_eq!(&actual[..actual_size], &expected[..expected_size]);
+
+        let mut image = vec![0; expected_image.len()];
+        decompressor
+            .decompress(data, &header, 0, false, &mut image)
+            .unwrap();
+        assert_eq!(image, expected_image);
+    }
+}
+
+#[test]
+fn test_multithreading_factors() {
+    use crate::config::{Config, MultithreadingFactors};
+
+    let header = HeaderBuilder {
+        width: 29,
+        height: 17,
+        metadata_size: 4,
+        ..HeaderBuilder::test_default()
+    }
+    .build()
+    .unwrap();
+    let color_transform = ColorTransformProgram::rgb_to_yuv();
+    let frame = header.test_frame(2);
+    let mut expected = vec![0; frame.len() * 4];
+    let expected_size =
+        crate::compress_simple(&frame, &header, &color_transform, &mut expected).unwrap();
+    let data = &expected[crate::header::HEADER_SIZE..expected_size];
+    let mut expected_image = vec![0; header.get_decompress_buffer_size(0)];
+    crate::decompress_simple(data, &header, 0, false, &mut expected_image).unwrap();
+
+    // parallelize everything in one context only
+    let factors = MultithreadingFactors {
+        linear_horizontal_lifting: 0,
+        linear_vertical_lifting: 0,
+        cubic_horizontal_lifting: 0,
+        cubic_vertical_lifting: 0,
+        quantization: 0,
+        compress: 0,
+    };
+    let mut compressor = Compressor::new(&header, &color_transform)
+        .unwrap()
+        .with_multithreading_factors(factors);
+    let mut decompressor = Decompressor::new().with_multithreading_factors(factors);
+    assert_eq!(
+        *Config::multithreading_factors(),
+        MultithreadingFactors::default()
+    );
+
+    let mut actual = vec![0; frame.len() * 4];
+    let actual_size = compressor.compress(&frame, &mut actual).unwrap();
+    assert_eq!(&actual[..actual_size], &expected[..expected_size]);
+
+    let mut image = vec![0; expected_image.len()];
+    decompressor
+        .decompress(data, &header, 0, false, &mut image)
+        .unwrap();
+    assert_eq!(image, expected_image);
+}
+
+#[cfg(feature = "rayon")]
+#[test]
+fn test_no_threads() {
+    match Decompressor::new().with_max_threads(0) {
+        Err(Error::NoThreads) => (),
+        other => panic!("unexpected result: {:?}", other.map(|_| ())),
+    }
+}
+
+/// Noisy color over an alpha ramp with a fully transparent left half.
+fn build_rgba_frame(header: &Header) -> Vec<u8> {
+    let width = header.width as usize;
+    (0..header.get_image_size() / 4)
+        .flat_map(|i| {
+            let x = i % width;
+            let alpha = if x < width / 2 {
+                0
+            } else {
+                (x * 255 / width) as u8
+            };
+            let noise = ((i * 7919) ^ (i >> 2)) as u8;
+            vec![noise, noise / 2, 255 - noise, alpha]
+        })
+        .collect()
+}
+
+#[test]
+fn test_lossless_alpha() {
+    let header = HeaderBuilder {
+        width: 32,
+        height: 24,
+        channels: 4,
+        quality: 128,
+        intent: Intent::RGBA,
+        metadata_size: 4,
+        ..HeaderBuilder::test_default()
+    }
+    .build()
+    .unwrap();
+    let frame = build_rgba_frame(&header);
+    let options = AlphaOptions {
+        quality: QUALITY_MAX,
+        transparent: TransparentColor::Keep,
+    };
+    let mut compressor = Compressor::new(&header, &ColorTransformProgram::rgba_to_ycocg_r())
+        .unwrap()
+        .with_alpha(options)
+        .unwrap();
+    let stream_header = compressor.header().clone();
+    assert!(stream_header.metadata_size > header.metadata_size);
+    assert!(stream_header.has_extension && !header.has_extension);
+
+    let mut compressed = vec![0; frame.len() * 8];
+    let size = compressor.compress(&frame, &mut compressed).unwrap();
+    let mut data = &compressed[..size];
+    assert_eq!(
+        crate::header::Header::decode(&mut data).unwrap(),
+        stream_header
+    );
+
+    let mut decompressed = vec![0; frame.len()];
+    Decompressor::new()
+        .decompress(data, &stream_header, 0, false, &mut decompressed)
+        .unwrap();
+    for (decompressed, original) in decompressed.chunks(4).zip(frame.chunks(4)) {
+        assert_eq!(decompressed[3], original[3]);
+    }
+    assert_ne!(decompressed, frame);
+}
+
+#[test]
+fn test_transparent_color() {
+    let header = HeaderBuilder {
+        width: 64,
+        height: 32,
+        channels: 4,
+        intent: Intent::RGBA,
+        metadata_size: 4,
+        ..HeaderBuilder::test_default()
+    }
+    .build()
+    .unwrap();
+    let frame = build_rgba_frame(&header);
+
+    let mut sizes = vec![];
+    for &transparent in &[
+        TransparentColor::Keep,
+        TransparentColor::Zero,
+        TransparentColor::Smooth,
+    ] {
+        let options = AlphaOptions {
+            quality: QUALITY_MAX,
+            transparent,
+        };
+        let mut compressor = Compressor::new(&header, &ColorTransformProgram::rgb_to_yuv())
+            .unwrap()
+            .with_alpha(options)
+            .unwrap();
+        let mut compressed = vec![0; frame.len() * 8];
+        let size = compressor.compress(&frame, &mut compressed).unwrap();
+        sizes.push(size);
+
+        // the opaque half stays close to the original
+        let mut decompressed = vec![0; frame.len()];
+        Decompressor::new()
+            .decompress(
+                &compressed[crate::header::HEADER_SIZE..size],
+                compressor.header(),
+                0,
+                false,
+                &mut decompressed,
+            )
+            .unwrap();
+        for (decompressed, original) in decompressed.chunks(4).zip(frame.chunks(4)) {
+            assert_eq!(decompressed[3], original[3]);
+        }
+    }
+    assert!(sizes[1] < sizes[0], "sizes {:?}", sizes);
+    assert!(sizes[2] < sizes[0], "sizes {:?}", sizes);
+}
+
+#[test]
+fn test_premultiplied_alpha() {
+    let header = HeaderBuilder {
+        width: 16,
+        height: 8,
+        channels: 4,
+        quality: QUALITY_MAX,
+        intent: Intent::RGBA,
+        metadata_size: 4,
+        ..HeaderBuilder::test_default()
+    }
+    .build()
+    .unwrap();
+    let frame = build_rgba_frame(&header);
+    let mut compressed = vec![0; frame.len() * 8];
+    let size = crate::compress_simple(
+        &frame,
+        &header,
+        &ColorTransformProgram::new(),
+        &mut compressed,
+    )
+    .unwrap();
+    let data = &compressed[crate::header::HEADER_SIZE..size];
+
+    let mut decompressor = Decompressor::new().with_premultiplied_alpha(true);
+    let mut decompressed = vec![0; frame.len()];
+    decompressor
+        .decompress(data, &header, 0, false, &mut decompressed)
+        .unwrap();
+    for (decompressed, original) in decompressed.chunks(4).zip(frame.chunks(4)) {
+        let alpha = original[3] as u32;
+        for channel in 0..3 {
+            let expected = (original[channel] as u32 * alpha + 127) / 255;
+            assert_eq!(decompressed[channel] as u32, expected);
+        }
+        assert_eq!(decompressed[3], original[3]);
+    }
+}
+
+#[test]
+fn test_alpha_options_rejected() {
+    let options = AlphaOptions {
+        quality: QUALITY_MAX,
+        transparent: TransparentColor::Zero,
+    };
+    match Compressor::new(
+        &HeaderBuilder {
+            width: 8,
+            height: 8,
+            metadata_size: 4,
+            ..HeaderBuilder::test_default()
+        }
+        .build()
+        .unwrap(),
+        &ColorTransformProgram::new(),
+    )
+    .unwrap()
+    .with_alpha(options)
+    {
+        Err(Error::NoAlphaChannel) => (),
+        result => panic!("unexpected result {:?}", result.map(|_| ())),
+    }
+
+    let mut builder = ChannelTransformBuilder::with_dest_channel(3);
+    builder.add_channel_factor(0, 1);
+    let mut program = ColorTransformProgram::new();
+    program.add_channel_transform(builder.build());
+    match Compressor::new(
+        &HeaderBuilder {
+            width: 8,
+            height: 8,
+            channels: 4,
+            intent: Intent::RGBA,
+            metadata_size: 4,
+            ..HeaderBuilder::test_default()
+        }
+        .build()
+        .unwrap(),
+        &program,
+    )
+    .unwrap()
+    .with_alpha(options)
+    {
+        Err(Error::AlphaTransformed { channel: 3 }) => (),
+        result => panic!("unexpected result {:?}", result.map(|_| ())),
+    }
+}
+
+#[test]
+fn test_channel_qualities() {
+    let header = HeaderBuilder {
+        width: 24,
+        layers: 2,
+        channels: 2,
+        quality: 256,
+        chroma_scale: 1,
+        filter: Filter::Linear,
+        encoder: Encoder::Fast,
+        intent: Intent::Generic,
+        ..HeaderBuilder::test_default()
+    }
+    .build()
+    .unwrap();
+    let frame = header.test_frame(1);
+    let compress = |qualities: &[u16]| {
+        let mut compressor = Compressor::new(&header, &ColorTransformProgram::new())
+            .unwrap()
+            .with_channel_qualities(qualities)
+            .unwrap();
+        let mut compressed = vec![0; frame.len() * 8];
+        let size = compressor.compress(&frame, &mut compressed).unwrap();
+        compressed.truncate(size);
+        let mut decompressed = vec![0; frame.len()];
+        let data = &compressed[crate::header::HEADER_SIZE..];
+        Decompressor::new()
+            .decompress(data, compressor.header(), 0, false, &mut decompressed)
+            .unwrap();
+        (compressed, decompressed)
+    };
+    // the frame is interleaved, channels of the layers one after another
+    let channel = |image: &[u8], index: usize| {
+        let layer_size = image.len() / 2;
+        image[(index / 2) * layer_size..(index / 2 + 1) * layer_size]
+            .iter()
+            .skip(index % 2)
+            .step_by(2)
+            .copied()
+            .collect::<Vec<_>>()
+    };
+
+    let (_, decompressed) = compress(&[QUALITY_MAX, 0, 0, QUALITY_MAX]);
+    assert_eq!(channel(&decompressed, 0), channel(&frame, 0));
+    assert_ne!(channel(&decompressed, 1), channel(&frame, 1));
+    assert_ne!(channel(&decompressed, 2), channel(&frame, 2));
+    assert_eq!(channel(&decompressed, 3), channel(&frame, 3));
+
+    // a table of zeros codes the same as no table
+    let (with_zeros, _) = compress(&[0; 4]);
+    let (without, _) = compress(&[]);
+    let extension_size = with_zeros.len() - without.len();
+    assert_eq!(
+        &with_zeros[crate::header::HEADER_SIZE + extension_size..],
+        &without[crate::header::HEADER_SIZE..]
+    );
+
+    match Compressor::new(&header, &ColorTransformProgram::new())
+        .unwrap()
+        .with_channel_qualities(&[512; 3])
+    {
+        Err(Error::QualityTable {
+            expected: 4,
+            actual: 3,
+        }) => (),
+        result => panic!("unexpected result {:?}", result.map(|_| ())),
+    }
+}
+
+#[test]
+fn test_quality_map() {
+    let header = HeaderBuilder {
+        width: 32,
+        height: 16,
+        quality: 64,
+        metadata_size: 4,
+        ..HeaderBuilder::test_default()
+    }
+    .build()
+    .unwrap();
+    let frame = header.test_frame(2);
+    let compress = |map: Option<QualityMap>| {
+        let mut compressor = Compressor::new(&header, &ColorTransformProgram::new())
+            .unwrap()
+            .with_quality_map(map)
+            .unwrap();
+        let mut compressed = vec![0; frame.len() * 8];
+        let size = compressor.compress(&frame, &mut compressed).unwrap();
+        compressed.truncate(size);
+        compressed
+    };
+    let decompress = |compressed: &[u8], downsampling: usize| {
+        let header = Header::decode(&mut &compressed[..]).unwrap();
+        let mut decompressed = vec![0; header.get_decompress_buffer_size(downsampling)];
+        Decompressor::new()
+            .decompress(
+                &compressed[crate::header::HEADER_SIZE..],
+                &header,
+                downsampling,
+                false,
+                &mut decompressed,
+            )
+            .unwrap();
+        decompressed
+    };
+    // error of the left and the right half of the interleaved frame
+    let errors = |decompressed: &[u8]| {
+        let mut errors = (0, 0);
+        for (i, (&a, &b)) in frame.iter().zip(decompressed).enumerate() {
+            let error = (a as i32 - b as i32).abs();
+            if i / 3 % 32 < 16 {
+                errors.0 += error;
+            } else {
+                errors.1 += error;
+            }
+        }
+        errors
+    };
+
+    let mut map = QualityMap::new(32, 16, 3, QUALITY_SCALE_ONE);
+    map.set_rect(0, 0, 16, 16, QUALITY_SCALE_MAX);
+    let compressed = compress(Some(map));
+    let (roi, rest) = errors(&decompress(&compressed, 0));
+    assert!(roi < rest / 2, "{} {}", roi, rest);
+    let downsampled = decompress(&compressed, 1);
+    assert_eq!(downsampled.len(), 16 * 8 * 3);
+
+    // a neutral map codes the same as no map
+    let neutral = compress(Some(QualityMap::new(32, 16, 3, QUALITY_SCALE_ONE)));
+    let without = compress(None);
+    let prefix_size = crate::header::HEADER_SIZE + header.metadata_size as usize;
+    let extension_size = neutral.len() - without.len();
+    assert_eq!(
+        &neutral[prefix_size + extension_size..],
+        &without[prefix_size..]
+    );
+
+    match Compressor::new(&header, &ColorTransformProgram::new())
+        .unwrap()
+        .with_quality_map(Some(QualityMap::new(16, 16, 3, QUALITY_SCALE_ONE)))
+    {
+        Err(Error::QualityMapShape {
+            expected: (4, 2),
+            actual: (2, 2),
+        }) => (),
+        result => panic!("unexpected result {:?}", result.map(|_| ())),
+    }
+}
+
+#[test]
+fn test_dead_zone() {
+    let header = HeaderBuilder {
+        width: 64,
+        height: 48,
+        metadata_size: 4,
+        ..HeaderBuilder::test_default()
+    }
+    .build()
+    .unwrap();
+    // smooth gradients with a little noise, like a photo
+    let frame = (0..header.get_image_size())
+        .map(|i| {
+            let (x, y) = (i / 3 % 64, i / 3 / 64);
+            (x * 2 + y + i % 3 * 40 + (i * 7919) % 7) as u8
+        })
+        .collect::<Vec<_>>();
+    let color_transform = ColorTransformProgram::rgb_to_yuv();
+    let compress = |compressor: Compressor| {
+        let mut compressor = compressor;
+        let mut compressed = vec![0; frame.len() * 8];
+        let size = compressor.compress(&frame, &mut compressed).unwrap();
+        compressed.truncate(size);
+        compressed
+    };
+
+    let compressed = compress(
+        Compressor::new(&header, &color_transform)
+            .unwrap()
+            .with_dead_zone(DeadZone::default())
+            .unwrap(),
+    );
+    let scalar = compress(Compressor::new(&header, &color_transform).unwrap());
+    assert!(compressed.len() < scalar.len());
+
+    // the header records the quantization, so the default parameters give the same stream
+    let decoded_header = Header::decode(&mut &compressed[..]).unwrap();
+    assert_eq!(decoded_header.quantization, Quantization::DeadZone);
+    assert_eq!(
+        compress(Compressor::new(&decoded_header, &color_transform).unwrap()),
+        compressed
+    );
+
+    // sum of absolute errors of the decompressed frame
+    let error = |compressed: &[u8]| {
+        let header = Header::decode(&mut &compressed[..]).unwrap();
+        let mut decompressed = vec![0; frame.len()];
+        let data = &compressed[crate::header::HEADER_SIZE..];
+        Decompressor::new()
+            .decompress(data, &header, 0, false, &mut decompressed)
+            .unwrap();
+        frame
+            .iter()
+            .zip(&decompressed)
+            .map(|(&a, &b)| (a.max(b) - a.min(b)) as u32)
+            .sum::<u32>()
+    };
+    // reconstructing closer to zero also lowers the error
+    assert!(error(&compressed) < error(&scalar));
+}
+
+#[test]
+fn test_csf_weighting() {
+    let flat_header = HeaderBuilder {
+        width: 64,
+        height: 48,
+        quality: 256,
+        metadata_size: 4,
+        ..HeaderBuilder::test_default()
+    }
+    .build()
+    .unwrap();
+    let header = Header {
+        weighting: Weighting::Csf,
+        ..flat_header.clone()
+    };
+    let frame = header.test_frame(5);
+    let color_transform = ColorTransformProgram::rgb_to_yuv();
+    let compress = |header: &Header| {
+        let mut compressed = vec![0; frame.len() * 8];
+        let size =
+            crate::compress_simple(&frame, header, &color_transform, &mut compressed).unwrap();
+        compressed.truncate(size);
+        compressed
+    };
+
+    // the fine details get a lower quality
+    let compressed = compress(&header);
+    assert!(compressed.len() < compress(&flat_header).len());
+
+    let decoded_header = Header::decode(&mut &compressed[..]).unwrap();
+    assert_eq!(decoded_header.weighting, Weighting::Csf);
+    let data = &compressed[crate::header::HEADER_SIZE..];
+    let mut decompressed = vec![0; frame.len()];
+    Decompressor::new()
+        .decompress(data, &decoded_header, 0, false, &mut decompressed)
+        .unwrap();
+    let mut downsampled = vec![0; decoded_header.get_decompress_buffer_size(1)];
+    Decompressor::new()
+        .decompress(data, &decoded_header, 1, false, &mut downsampled)
+        .unwrap();
+
+    // lossless channels stay lossless
+    let lossless = Header {
+        weighting: Weighting::Csf,
+        ..HeaderBuilder {
+            width: 64,
+            height: 48,
+            quality: QUALITY_MAX,
+            metadata_size: 4,
+            ..HeaderBuilder::test_default()
+        }
+        .build()
+        .unwrap()
+    };
+    let mut compressed = vec![0; frame.len() * 8];
+    crate::compress_lossless(
+        &frame,
+        &lossless,
+        &ColorTransformProgram::new(),
+        true,
+        &mut compressed,
+    )
+    .unwrap();
+}
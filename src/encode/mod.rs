@@ -1,16 +1,15 @@
-use std::io;
-
 use num_traits;
 
 use crate::bits;
 use crate::header::Encoder;
+use crate::io::IOError;
 use crate::processing::image::ImageChunkMut;
 
 #[cfg(test)]
 mod test;
 
 // limited length power-of-two Golomb-Rice code
-pub fn unsigned_code(x: u32, stream: &mut impl bits::BitsWriter, pot: u32) -> io::Result<()> {
+pub fn unsigned_code(x: u32, stream: &mut impl bits::BitsWriter, pot: u32) -> Result<(), IOError> {
     let y = x >> pot;
     if y >= 12 {
         stream.put_bits(0, 12)?; // escape to larger code
@@ -24,7 +23,7 @@ pub fn unsigned_code(x: u32, stream: &mut impl bits::BitsWriter, pot: u32) -> io
     Ok(())
 }
 
-pub fn unsigned_decode(stream: &mut impl bits::BitsReader, pot: u32) -> io::Result<u32> {
+pub fn unsigned_decode(stream: &mut impl bits::BitsReader, pot: u32) -> Result<u32, IOError> {
     let x = stream.get_zeros(12)?;
     let p = pot.min(24); // actual pot. The max 108 below is to prevent unlimited recursion in malformed files, yet admit 2^32 - 1.
     if pot < 108 && x == 12 {
@@ -37,23 +36,27 @@ pub fn unsigned_decode(stream: &mut impl bits::BitsReader, pot: u32) -> io::Resu
     }
 }
 
-pub fn interleaved_code(x: i32, stream: &mut impl bits::BitsWriter, pot: u32) -> io::Result<()> {
+pub fn interleaved_code(
+    x: i32,
+    stream: &mut impl bits::BitsWriter,
+    pot: u32,
+) -> Result<(), IOError> {
     let x = if x <= 0 { -2 * x } else { 2 * x - 1 } as u32;
     unsigned_code(x, stream, pot)?;
 
     Ok(())
 }
 
-pub fn interleaved_decode(stream: &mut impl bits::BitsReader, pot: u32) -> io::Result<i32> {
+pub fn interleaved_decode(stream: &mut impl bits::BitsReader, pot: u32) -> Result<i32, IOError> {
     let x = unsigned_decode(stream, pot)? as i32;
     if (x & 1) != 0 {
-        Ok((x + 1) / 2)
+        Ok(x.wrapping_add(1) / 2)
     } else {
-        Ok(-x / 2)
+        Ok(x.wrapping_neg() / 2)
     }
 }
 
-pub fn signed_code(x: i32, stream: &mut impl bits::BitsWriter, pot: u32) -> io::Result<()> {
+pub fn signed_code(x: i32, stream: &mut impl bits::BitsWriter, pot: u32) -> Result<(), IOError> {
     unsigned_code(x.abs() as u32, stream, pot)?;
     if x != 0 {
         stream.put_bits(if x > 0 { 1 } else { 0 }, 1)?;
@@ -62,7 +65,7 @@ pub fn signed_code(x: i32, stream: &mut impl bits::BitsWriter, pot: u32) -> io::
     Ok(())
 }
 
-pub fn signed_decode(stream: &mut impl bits::BitsReader, pot: u32) -> io::Result<i32> {
+pub fn signed_decode(stream: &mut impl bits::BitsReader, pot: u32) -> Result<i32, IOError> {
     let x = unsigned_decode(stream, pot)? as i32;
 
     if x == 0 {
@@ -70,7 +73,7 @@ pub fn signed_decode(stream: &mut impl bits::BitsReader, pot: u32) -> io::Result
     } else if stream.get_bits(1)? != 0 {
         Ok(x)
     } else {
-        Ok(-x)
+        Ok(x.wrapping_neg())
     }
 }
 
@@ -79,9 +82,9 @@ pub fn square<T: num_traits::ops::wrapping::WrappingMul + Copy>(t: T) -> T::Outp
 }
 
 pub fn add_context(x: i16, w: i32, sum: &mut u32, sum2: &mut u32, count: &mut u32) {
-    let x = x.abs() as u32;
-    *sum += x * w as u32;
-    *sum2 += square(x.min(4096)) * w as u32;
+    let x = u32::from(x.unsigned_abs());
+    *sum = sum.wrapping_add(x.wrapping_mul(w as u32));
+    *sum2 = sum2.wrapping_add(square(x.min(4096)).wrapping_mul(w as u32));
     *count += w as u32;
 }
 
@@ -190,37 +193,44 @@ pub unsafe fn get_context(image: &ImageChunkMut<'_, i16>, x: i32, y: i32) -> (u3
         }
     }
 
+    // sum2 may exceed 2^28 for large coefficients, wrap like the reference implementation
     (
         (sum * 16 + count / 2) / count,
-        (sum2 * 16 + count / 2) / count,
+        sum2.wrapping_mul(16).wrapping_add(count / 2) / count,
     )
 }
 
+/// `factor * x + offset`, wrapping like the reference implementation, as the contexts of
+/// malformed streams can take any value.
+fn threshold(x: u32, factor: u32, offset: u32) -> u32 {
+    x.wrapping_mul(factor).wrapping_add(offset)
+}
+
 fn encode_s(
     stream: &mut impl bits::BitsWriter,
     s: i32,
     sum_sq: u32,
     context: (u32, u32),
     is_chroma: bool,
-) -> io::Result<()> {
-    if sum_sq < 2 * context.1 + (if is_chroma { 250 } else { 100 }) {
+) -> Result<(), IOError> {
+    if sum_sq < threshold(context.1, 2, if is_chroma { 250 } else { 100 }) {
         interleaved_code(s, stream, 0)
-    } else if sum_sq < 2 * context.1 + 950 {
+    } else if sum_sq < threshold(context.1, 2, 950) {
         interleaved_code(s, stream, 1)
-    } else if sum_sq < 3 * context.1 + 3000 {
-        if sum_sq < 5 * context.1 + 400 {
+    } else if sum_sq < threshold(context.1, 3, 3000) {
+        if sum_sq < threshold(context.1, 5, 400) {
             signed_code(s, stream, 1)
         } else {
             interleaved_code(s, stream, 2)
         }
-    } else if sum_sq < 3 * context.1 + 12000 {
-        if sum_sq < 5 * context.1 + 3000 {
+    } else if sum_sq < threshold(context.1, 3, 12000) {
+        if sum_sq < threshold(context.1, 5, 3000) {
             signed_code(s, stream, 2)
         } else {
             interleaved_code(s, stream, 3)
         }
-    } else if sum_sq < 4 * context.1 + 44000 {
-        if sum_sq < 6 * context.1 + 12000 {
+    } else if sum_sq < threshold(context.1, 4, 44000) {
+        if sum_sq < threshold(context.1, 6, 12000) {
             signed_code(s, stream, 3)
         } else {
             interleaved_code(s, stream, 4)
@@ -237,7 +247,7 @@ pub fn encode(
     q: i32,
     has_dc: bool,
     is_chroma: bool,
-) -> io::Result<()> {
+) -> Result<(), IOError> {
     let step = image.step as i32;
     let x_range = (image.x_range.0 as i32, image.x_range.1 as i32);
     let y_range = (image.y_range.0 as i32, image.y_range.1 as i32);
@@ -304,11 +314,7 @@ pub fn encode(
             encode_s(stream, s, sum_sq, context, is_chroma)?;
 
             if scheme == Encoder::Fast {
-                let t = s.abs() as u32;
-                context = (
-                    ((context.0 * 15 + 7) >> 4) + t,
-                    ((context.1 * 15 + 7) >> 4) + square(t.min(4096)),
-                );
+                context = update_fast_context(context, s);
                 run_coder = get_run_coder_fast(context, s, run_coder);
             } else {
                 run_coder = get_run_coder(context, s, q, run_coder, sum_sq);
@@ -329,25 +335,25 @@ fn get_s(
     sum_sq: u32,
     context: (u32, u32),
     is_chroma: bool,
-) -> io::Result<i32> {
-    if sum_sq < 2 * context.1 + (if is_chroma { 250 } else { 100 }) {
+) -> Result<i32, IOError> {
+    if sum_sq < threshold(context.1, 2, if is_chroma { 250 } else { 100 }) {
         interleaved_decode(stream, 0)
-    } else if sum_sq < 2 * context.1 + 950 {
+    } else if sum_sq < threshold(context.1, 2, 950) {
         interleaved_decode(stream, 1)
-    } else if sum_sq < 3 * context.1 + 3000 {
-        if sum_sq < 5 * context.1 + 400 {
+    } else if sum_sq < threshold(context.1, 3, 3000) {
+        if sum_sq < threshold(context.1, 5, 400) {
             signed_decode(stream, 1)
         } else {
             interleaved_decode(stream, 2)
         }
-    } else if sum_sq < 3 * context.1 + 12000 {
-        if sum_sq < 5 * context.1 + 3000 {
+    } else if sum_sq < threshold(context.1, 3, 12000) {
+        if sum_sq < threshold(context.1, 5, 3000) {
             signed_decode(stream, 2)
         } else {
             interleaved_decode(stream, 3)
         }
-    } else if sum_sq < 4 * context.1 + 44000 {
-        if sum_sq < 6 * context.1 + 12000 {
+    } else if sum_sq < threshold(context.1, 4, 44000) {
+        if sum_sq < threshold(context.1, 6, 12000) {
             signed_decode(stream, 3)
         } else {
             interleaved_decode(stream, 4)
@@ -357,6 +363,15 @@ fn get_s(
     }
 }
 
+// decaying first and second moments, wrapping like the reference implementation
+fn update_fast_context(context: (u32, u32), s: i32) -> (u32, u32) {
+    let t = s.wrapping_abs() as u32;
+    (
+        (context.0.wrapping_mul(15).wrapping_add(7) >> 4).wrapping_add(t),
+        (context.1.wrapping_mul(15).wrapping_add(7) >> 4).wrapping_add(square(t.min(4096))),
+    )
+}
+
 fn get_run_coder_fast(context: (u32, u32), s: i32, run_coder: u32) -> u32 {
     // use decaying first and second moment
     if (s == 0) == (run_coder == 0) {
@@ -388,9 +403,9 @@ fn get_run_coder(context: (u32, u32), s: i32, q: i32, run_coder: u32, sum_sq: u3
             4
         } else if context.0 < 8 && context.1 < 4 {
             3
-        } else if 2 * sum_sq < 3 * context.1 + 48 {
+        } else if sum_sq.wrapping_mul(2) < threshold(context.1, 3, 48) {
             2
-        } else if 2 * sum_sq < 5 * context.1 + 32 {
+        } else if sum_sq.wrapping_mul(2) < threshold(context.1, 5, 32) {
             1
         } else {
             0
@@ -407,7 +422,7 @@ pub fn decode(
     q: i32,
     has_dc: bool,
     is_chroma: bool,
-) -> io::Result<()> {
+) -> Result<(), IOError> {
     let step = image.step as i32;
     let x_range = (image.x_range.0 as i32, image.x_range.1 as i32);
     let y_range = (image.y_range.0 as i32, image.y_range.1 as i32);
@@ -449,18 +464,14 @@ pub fn decode(
                     s = get_s(&mut *stream, sum_sq, context, is_chroma)?;
 
                     if scheme == Encoder::Fast {
-                        let t = s.abs() as u32;
-                        context = (
-                            ((context.0 * 15 + 7) >> 4) + t,
-                            ((context.1 * 15 + 7) >> 4) + square(t.min(4096)),
-                        );
+                        context = update_fast_context(context, s);
                         run_coder = get_run_coder_fast(context, s, run_coder);
                     } else {
                         run_coder = get_run_coder(context, s, q, run_coder, sum_sq);
                     }
                 }
                 if run == 0 && s <= 0 {
-                    s -= 1; // s can't be zero, so shift negatives by 1
+                    s = s.wrapping_sub(1); // s can't be zero, so shift negatives by 1
                 }
                 run = -1;
             } else {
@@ -1,80 +1,324 @@
-use std::{error::Error, fmt, io};
+use alloc::string::String;
+use core::{error, fmt};
 
-#[derive(Debug)]
-pub enum CompressError {
-    IOErr(io::Error),
-    Overflow,
-    Malformed,
-}
+use crate::checksum::ChecksumLocation;
+use crate::header::LossyReason;
+use crate::io::IOError;
 
 #[derive(Debug)]
-pub enum DecompressError {
-    IOErr(io::Error),
-    Unsupported,
-    Underflow,
-    Malformed,
-    TypeMismatch,
+pub enum Error {
+    /// I/O error outside of block payloads: header, color transform program or block tables.
+    IOErr(IOError),
+    /// Stream header is missing or has invalid values.
+    Header(HeaderErr),
+    /// Caller supplied buffer doesn't match the size required by the header.
+    BufferSize { expected: usize, actual: usize },
+    /// `block_size` is too large for the image dimensions.
+    BlockSize(u8),
+    /// Output buffer can't hold the block table of a step level.
+    BlockTableOverflow {
+        step: usize,
+        offset: usize,
+        expected: usize,
+        actual: usize,
+    },
+    /// Encoded block doesn't fit into the space left in the output buffer.
+    BlockOverflow {
+        step: usize,
+        block_index: usize,
+        channel: usize,
+        offset: usize,
+    },
+    /// Block table of a step level points past the end of the stream.
+    Truncated {
+        step: usize,
+        expected: usize,
+        actual: usize,
+    },
+    /// Block data ended before all of its coefficients were decoded.
+    BlockUnderflow {
+        step: usize,
+        block_index: usize,
+        channel: usize,
+        offset: usize,
+    },
+    /// Color transform program references a channel the image doesn't have.
+    TransformChannel { channel: usize, channels: usize },
+    /// Color transform program has a zero denominator for the destination channel.
+    TransformDenominator { channel: usize },
+    /// Color transform program is longer than allowed by `DecodeLimits`.
+    TransformLength { max: usize },
+    /// Textual color transform program doesn't parse, `offset` is in bytes.
+    TransformSyntax {
+        offset: usize,
+        expected: &'static str,
+    },
+    /// Image view dimensions `(width, height, layers, channels)` don't match the header.
+    ViewShape {
+        expected: (usize, usize, usize, usize),
+        actual: (usize, usize, usize, usize),
+    },
+    /// Image view stride is smaller than a row of samples.
+    ViewStride { stride: usize, row: usize },
+    /// Number of planes doesn't match the number of channels in the header.
+    PlaneCount { expected: usize, actual: usize },
+    /// Stream doesn't carry the checksum extension.
+    ChecksumMissing,
+    /// Part of the stream doesn't match its stored checksum.
+    ChecksumMismatch(ChecksumLocation),
+    /// Lossless compression was requested for a lossy configuration.
+    Lossy(LossyReason),
+    /// Decompressed image differs from the original at the sample `offset`.
+    LosslessMismatch { offset: usize },
+    /// Alpha handling was requested for an image whose intent has no alpha channel.
+    NoAlphaChannel,
+    /// Color transform program writes to the alpha channel of the layer.
+    AlphaTransformed { channel: usize },
+    /// Record of the coding extension is unknown or has invalid values.
+    Extension { tag: u32 },
+    /// Per-channel quality table doesn't have a quality for every channel of every layer.
+    QualityTable { expected: usize, actual: usize },
+    /// Quality map `(width, height)` in cells doesn't cover the image.
+    QualityMapShape {
+        expected: (usize, usize),
+        actual: (usize, usize),
+    },
+    /// Palette tile of an `Encoder::Screen` stream has invalid values or is cut off.
+    Palette { layer: usize, tile: usize },
+    /// Thread pool of no threads was requested.
+    NoThreads,
+    /// Color transform program was to be selected out of no candidates.
+    NoCandidates,
 }
 
-impl From<io::Error> for CompressError {
-    fn from(err: io::Error) -> Self {
-        CompressError::IOErr(err)
+impl From<IOError> for Error {
+    fn from(err: IOError) -> Self {
+        Error::IOErr(err)
     }
 }
 
-impl fmt::Display for CompressError {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            CompressError::IOErr(e) => write!(f, "{}", e),
-            CompressError::Overflow => write!(f, "Buffer is too small"),
-            CompressError::Malformed => write!(f, "Invalid arguments"),
-        }
+#[cfg(feature = "std")]
+impl From<std::io::Error> for Error {
+    fn from(err: std::io::Error) -> Self {
+        Error::IOErr(err.into())
     }
 }
 
-impl Error for CompressError {}
-
-impl From<io::Error> for DecompressError {
-    fn from(err: io::Error) -> Self {
-        DecompressError::IOErr(err)
+impl From<HeaderErr> for Error {
+    fn from(err: HeaderErr) -> Self {
+        Error::Header(err)
     }
 }
 
-impl fmt::Display for DecompressError {
+impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            DecompressError::IOErr(e) => write!(f, "{}", e),
-            DecompressError::Unsupported => write!(f, "Unsupported image format"),
-            DecompressError::Malformed => write!(f, "Invalid arguments"),
-            DecompressError::Underflow => write!(f, "Buffer underflow detected"),
-            DecompressError::TypeMismatch => write!(f, "Image data doesn't match the header"),
+            Error::IOErr(e) => write!(f, "{}", e),
+            Error::Header(e) => write!(f, "{}", e),
+            Error::BufferSize { expected, actual } => write!(
+                f,
+                "Buffer size doesn't match the header: expected {} elements, got {}",
+                expected, actual
+            ),
+            Error::BlockSize(block_size) => write!(
+                f,
+                "Block size {} is too large for the image dimensions",
+                block_size
+            ),
+            Error::BlockTableOverflow {
+                step,
+                offset,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "Buffer is too small for the block table of step {} at offset {}: \
+                 expected at least {} bytes, got {}",
+                step, offset, expected, actual
+            ),
+            Error::BlockOverflow {
+                step,
+                block_index,
+                channel,
+                offset,
+            } => write!(
+                f,
+                "Buffer is too small for block {} (channel {}) of step {} at offset {}",
+                block_index, channel, step, offset
+            ),
+            Error::Truncated {
+                step,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "Stream is truncated at step {}: expected at least {} bytes, got {}",
+                step, expected, actual
+            ),
+            Error::BlockUnderflow {
+                step,
+                block_index,
+                channel,
+                offset,
+            } => write!(
+                f,
+                "Buffer underflow in block {} (channel {}) of step {} at offset {}",
+                block_index, channel, step, offset
+            ),
+            Error::TransformChannel { channel, channels } => write!(
+                f,
+                "Color transform references channel {}, but image has {} channels",
+                channel, channels
+            ),
+            Error::TransformDenominator { channel } => write!(
+                f,
+                "Color transform of channel {} has zero denominator",
+                channel
+            ),
+            Error::TransformLength { max } => write!(
+                f,
+                "Color transform program exceeds the decode limit of {} entries",
+                max
+            ),
+            Error::TransformSyntax { offset, expected } => write!(
+                f,
+                "Color transform syntax error at offset {}: expected {}",
+                offset, expected
+            ),
+            Error::ViewShape { expected, actual } => write!(
+                f,
+                "Image view is {}x{} with {} layers of {} channels, but {}x{} with {} layers of {} \
+                 channels is required",
+                actual.0, actual.1, actual.2, actual.3, expected.0, expected.1, expected.2, expected.3
+            ),
+            Error::ViewStride { stride, row } => write!(
+                f,
+                "Image view stride {} is smaller than a row of {} samples",
+                stride, row
+            ),
+            Error::PlaneCount { expected, actual } => write!(
+                f,
+                "Expected {} planes (channels * layers), got {}",
+                expected, actual
+            ),
+            Error::ChecksumMissing => write!(f, "Stream doesn't contain checksums"),
+            Error::ChecksumMismatch(location) => match location {
+                ChecksumLocation::Header => write!(f, "Header checksum mismatch"),
+                ChecksumLocation::BlockTable { step } => {
+                    write!(f, "Checksum mismatch in block table of step {}", step)
+                }
+                ChecksumLocation::Block { step, block_index } => write!(
+                    f,
+                    "Checksum mismatch in block {} of step {}",
+                    block_index, step
+                ),
+                ChecksumLocation::Image => write!(f, "Decoded image checksum mismatch"),
+            },
+            Error::Lossy(reason) => match reason {
+                LossyReason::Quality => write!(f, "Quality below maximum is lossy"),
+                LossyReason::ChromaScale => {
+                    write!(f, "Chroma scale above 1 is lossy for chroma channels")
+                }
+                LossyReason::BitDepth => {
+                    write!(f, "Only unsigned samples of up to 8 bits are lossless")
+                }
+                LossyReason::Transform { channel } => write!(
+                    f,
+                    "Color transform of channel {} is not exactly invertible",
+                    channel
+                ),
+            },
+            Error::LosslessMismatch { offset } => write!(
+                f,
+                "Decompressed image differs from the original at sample {}",
+                offset
+            ),
+            Error::NoAlphaChannel => write!(f, "Image intent has no alpha channel"),
+            Error::AlphaTransformed { channel } => write!(
+                f,
+                "Color transform writes to alpha channel {}",
+                channel
+            ),
+            Error::Extension { tag } => write!(
+                f,
+                "Coding extension record {} is unknown or invalid",
+                tag
+            ),
+            Error::QualityTable { expected, actual } => write!(
+                f,
+                "Expected {} channel qualities (channels * layers), got {}",
+                expected, actual
+            ),
+            Error::QualityMapShape { expected, actual } => write!(
+                f,
+                "Quality map is {}x{} cells, but the image needs {}x{}",
+                actual.0, actual.1, expected.0, expected.1
+            ),
+            Error::Palette { layer, tile } => {
+                write!(f, "Palette tile {} of layer {} is malformed", tile, layer)
+            }
+            Error::NoThreads => write!(f, "Thread pool needs at least one thread"),
+            Error::NoCandidates => write!(f, "No candidate color transform programs"),
         }
     }
 }
 
-impl Error for DecompressError {}
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::IOErr(e) => Some(e),
+            Error::Header(e) => Some(e),
+            _ => None,
+        }
+    }
+}
 
 #[derive(Debug)]
 pub enum HeaderErr {
-    IOErr(io::Error),
+    IOErr(IOError),
     WrongMagic,
     WrongValue(String),
+    /// Header value is larger than allowed by `DecodeLimits`.
+    LimitExceeded {
+        name: &'static str,
+        value: u64,
+        max: u64,
+    },
 }
 
-impl From<io::Error> for HeaderErr {
-    fn from(err: io::Error) -> HeaderErr {
+impl From<IOError> for HeaderErr {
+    fn from(err: IOError) -> HeaderErr {
         HeaderErr::IOErr(err)
     }
 }
 
+#[cfg(feature = "std")]
+impl From<std::io::Error> for HeaderErr {
+    fn from(err: std::io::Error) -> HeaderErr {
+        HeaderErr::IOErr(err.into())
+    }
+}
+
 impl fmt::Display for HeaderErr {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             HeaderErr::IOErr(e) => write!(f, "{}", e),
             HeaderErr::WrongMagic => write!(f, "Header doesn't contain GFWX magic"),
             HeaderErr::WrongValue(e) => write!(f, "Invalid filed value in header: {}", e),
+            HeaderErr::LimitExceeded { name, value, max } => write!(
+                f,
+                "Header {} is {}, which exceeds the decode limit of {}",
+                name, value, max
+            ),
         }
     }
 }
 
-impl Error for HeaderErr {}
+impl error::Error for HeaderErr {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            HeaderErr::IOErr(e) => Some(e),
+            _ => None,
+        }
+    }
+}
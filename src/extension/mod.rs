@@ -0,0 +1,328 @@
+use alloc::vec::Vec;
+use core::convert::TryFrom;
+
+use byteorder::{ByteOrder, LittleEndian};
+
+use crate::errors::Error;
+use crate::header::{Header, Quantization, Weighting, QUALITY_MAX};
+use crate::io::{IOError, Write};
+use crate::quant::{DeadZone, QualityMap, Quantizer, Scales, CSF_WEIGHTS, QUALITY_SCALE_MAX};
+
+#[cfg(test)]
+mod test;
+
+const MAGIC: u32 = 'G' as u32 | (('F' as u32) << 8) | (('X' as u32) << 16) | (('E' as u32) << 24);
+const TAG_ALPHA_QUALITY: u32 = 1;
+const TAG_CHANNEL_QUALITIES: u32 = 2;
+const TAG_QUALITY_MAP: u32 = 3;
+
+/// Coding parameters on top of the header that are needed to decode the stream, stored as an
+/// extension at the end of the metadata area. `Header::has_extension` marks the streams that
+/// carry it, and decoders that don't know about the extension reject them.
+///
+/// Layout (little-endian `u32` words): records, each made of a tag, the payload size in words
+/// and the payload, then the size of the whole extension in bytes and the magic.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Extension {
+    /// Quality of the alpha channel of every layer instead of `header.quality`, see
+    /// `Header::get_alpha_channel`. `QUALITY_MAX` codes alpha losslessly.
+    pub alpha_quality: Option<u16>,
+    /// Quality of every channel of every layer, channels of the second and further layers
+    /// following the channels of the first one. 0 keeps the quality from the header or
+    /// `alpha_quality`, `QUALITY_MAX` codes the channel losslessly. Empty if there is no table.
+    pub channel_qualities: Vec<u16>,
+    /// Quality scale of the regions of the image, applied to every channel that isn't coded
+    /// losslessly.
+    pub quality_map: Option<QualityMap>,
+}
+
+impl Extension {
+    pub fn is_empty(&self) -> bool {
+        self.alpha_quality.is_none()
+            && self.channel_qualities.is_empty()
+            && self.quality_map.is_none()
+    }
+
+    /// Size of the encoded extension in bytes, 0 if it is empty.
+    pub fn encoded_size(&self) -> usize {
+        if self.is_empty() {
+            return 0;
+        }
+        let mut words = 2;
+        if self.alpha_quality.is_some() {
+            words += 3;
+        }
+        if !self.channel_qualities.is_empty() {
+            words += 2 + self.channel_qualities.len();
+        }
+        if let Some(map) = &self.quality_map {
+            words += 5 + map.scales.len().div_ceil(2);
+        }
+        words * 4
+    }
+
+    /// Checks the parameters against the images described by `header`.
+    pub fn check(&self, header: &Header) -> Result<(), Error> {
+        if let Some(quality) = self.alpha_quality {
+            if header.get_alpha_channel().is_none() {
+                return Err(Error::NoAlphaChannel);
+            }
+            if quality == 0 || quality > QUALITY_MAX {
+                return Err(Error::Extension {
+                    tag: TAG_ALPHA_QUALITY,
+                });
+            }
+        }
+        if !self.channel_qualities.is_empty() {
+            let channels = usize::from(header.channels) * usize::from(header.layers);
+            if self.channel_qualities.len() != channels {
+                return Err(Error::QualityTable {
+                    expected: channels,
+                    actual: self.channel_qualities.len(),
+                });
+            }
+            if self.channel_qualities.iter().any(|&q| q > QUALITY_MAX) {
+                return Err(Error::Extension {
+                    tag: TAG_CHANNEL_QUALITIES,
+                });
+            }
+        }
+        if let Some(map) = &self.quality_map {
+            if map.cell_shift > 30 {
+                return Err(Error::Extension {
+                    tag: TAG_QUALITY_MAP,
+                });
+            }
+            let expected = QualityMap::cells(
+                header.width as usize,
+                header.height as usize,
+                map.cell_shift,
+            );
+            if (map.width, map.height) != expected || map.scales.len() != map.width * map.height {
+                return Err(Error::QualityMapShape {
+                    expected,
+                    actual: (map.width, map.height),
+                });
+            }
+            if map
+                .scales
+                .iter()
+                .any(|&scale| scale == 0 || scale > QUALITY_SCALE_MAX)
+            {
+                return Err(Error::Extension {
+                    tag: TAG_QUALITY_MAP,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads the extension from the end of the metadata area. Returns an empty extension if
+    /// `header` doesn't mark the stream as carrying one.
+    pub fn decode(metadata: &[u8], header: &Header) -> Result<Self, Error> {
+        if !header.has_extension {
+            return Ok(Extension::default());
+        }
+        let len = metadata.len();
+        if len < 8 {
+            return Err(IOError::UnexpectedEof.into());
+        }
+        if LittleEndian::read_u32(&metadata[len - 4..]) != MAGIC {
+            return Err(Error::Extension { tag: MAGIC });
+        }
+        let size = LittleEndian::read_u32(&metadata[len - 8..]) as usize;
+        if size < 8 || size > len || size & 3 != 0 {
+            return Err(IOError::UnexpectedEof.into());
+        }
+
+        let mut extension = Extension::default();
+        let mut records = &metadata[len - size..len - 8];
+        while !records.is_empty() {
+            if records.len() < 8 {
+                return Err(IOError::UnexpectedEof.into());
+            }
+            let tag = LittleEndian::read_u32(records);
+            let payload_size = (LittleEndian::read_u32(&records[4..]) as usize).saturating_mul(4);
+            if records.len() - 8 < payload_size {
+                return Err(IOError::UnexpectedEof.into());
+            }
+            let payload = &records[8..8 + payload_size];
+            records = &records[8 + payload_size..];
+
+            match (tag, payload.len()) {
+                (TAG_ALPHA_QUALITY, 4) => {
+                    let quality = LittleEndian::read_u32(payload).min(u32::from(u16::MAX));
+                    extension.alpha_quality = Some(quality as u16);
+                }
+                (TAG_CHANNEL_QUALITIES, size) if size > 0 => {
+                    extension.channel_qualities = payload
+                        .chunks_exact(4)
+                        .map(|word| LittleEndian::read_u32(word).min(u32::from(u16::MAX)) as u16)
+                        .collect();
+                }
+                (TAG_QUALITY_MAP, size) if size >= 12 => {
+                    let cell_shift = u8::try_from(LittleEndian::read_u32(payload))
+                        .map_err(|_| Error::Extension { tag })?;
+                    let width = LittleEndian::read_u32(&payload[4..]) as usize;
+                    let height = LittleEndian::read_u32(&payload[8..]) as usize;
+                    let cells = width.saturating_mul(height);
+                    if (size - 12) / 2 < cells {
+                        return Err(IOError::UnexpectedEof.into());
+                    }
+                    extension.quality_map = Some(QualityMap {
+                        cell_shift,
+                        width,
+                        height,
+                        scales: payload[12..]
+                            .chunks_exact(2)
+                            .take(cells)
+                            .map(LittleEndian::read_u16)
+                            .collect(),
+                    });
+                }
+                _ => return Err(Error::Extension { tag }),
+            }
+        }
+
+        extension.check(header)?;
+        Ok(extension)
+    }
+
+    /// Encoded extension at the end of `metadata`, empty if `header` doesn't mark the stream as
+    /// carrying one. The bytes are only validated by `decode`.
+    pub(crate) fn find<'a>(metadata: &'a [u8], header: &Header) -> &'a [u8] {
+        let len = metadata.len();
+        if !header.has_extension || len < 8 {
+            return &[];
+        }
+        let size = LittleEndian::read_u32(&metadata[len - 8..]) as usize;
+        &metadata[len - size.clamp(8, len)..]
+    }
+
+    pub fn encode(&self, buffer: &mut impl Write) -> Result<(), IOError> {
+        if self.is_empty() {
+            return Ok(());
+        }
+        if let Some(quality) = self.alpha_quality {
+            buffer.write_u32(TAG_ALPHA_QUALITY)?;
+            buffer.write_u32(1)?;
+            buffer.write_u32(u32::from(quality))?;
+        }
+        if !self.channel_qualities.is_empty() {
+            buffer.write_u32(TAG_CHANNEL_QUALITIES)?;
+            buffer.write_u32(self.channel_qualities.len() as u32)?;
+            for &quality in &self.channel_qualities {
+                buffer.write_u32(u32::from(quality))?;
+            }
+        }
+        if let Some(map) = &self.quality_map {
+            buffer.write_u32(TAG_QUALITY_MAP)?;
+            buffer.write_u32((3 + map.scales.len().div_ceil(2)) as u32)?;
+            buffer.write_u32(u32::from(map.cell_shift))?;
+            buffer.write_u32(map.width as u32)?;
+            buffer.write_u32(map.height as u32)?;
+            for pair in map.scales.chunks(2) {
+                let high = pair.get(1).copied().unwrap_or(0);
+                buffer.write_u32(u32::from(pair[0]) | (u32::from(high) << 16))?;
+            }
+        }
+        buffer.write_u32(self.encoded_size() as u32)?;
+        buffer.write_u32(MAGIC)?;
+        Ok(())
+    }
+
+    /// Header for streams carrying the extension: `header` with the extension added to the
+    /// metadata area and marked in the header, if it isn't empty.
+    pub(crate) fn extend_header(&self, header: &Header) -> Header {
+        let mut header = header.clone();
+        header.metadata_size += self.encoded_size() as u32;
+        header.has_extension = !self.is_empty();
+        header
+    }
+}
+
+/// Quality every channel is quantized with, in the range of `quant::quantize` with
+/// `QUALITY_MAX * boost` as the maximum, and whether it is coded as chroma.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct ChannelCoding {
+    pub quality: i32,
+    pub is_chroma: bool,
+}
+
+/// Everything quantization and coding need besides the header.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(crate) struct Coding {
+    /// Codings of all channels of all layers.
+    pub channels: Vec<ChannelCoding>,
+    pub quality_map: Option<QualityMap>,
+    /// Encoder parameters of `Quantization::DeadZone`, kept by `update`.
+    pub dead_zone: DeadZone,
+}
+
+impl Coding {
+    pub fn new(header: &Header, is_chroma: &[bool], extension: &Extension) -> Self {
+        let mut coding = Coding::default();
+        coding.update(header, is_chroma, extension);
+        coding
+    }
+
+    /// Codings of all channels of all layers, from the header, the chroma flags of the color
+    /// transform program and the extension. A quality from the table of the extension wins over
+    /// `alpha_quality`, which wins over the header.
+    pub fn update(&mut self, header: &Header, is_chroma: &[bool], extension: &Extension) {
+        let chroma_quality = header.get_chroma_quality();
+        let max_quality = i32::from(QUALITY_MAX) * i32::from(header.get_boost());
+        let channels = usize::from(header.channels);
+        let alpha = header.get_alpha_channel();
+        let quantizer_quality = |quality: u16| {
+            if quality >= QUALITY_MAX {
+                max_quality
+            } else {
+                i32::from(quality)
+            }
+        };
+
+        self.channels.clear();
+        self.channels
+            .extend(is_chroma.iter().enumerate().map(|(channel, &is_chroma)| {
+                let table_quality = extension
+                    .channel_qualities
+                    .get(channel)
+                    .copied()
+                    .filter(|&quality| quality > 0);
+                let quality = match (table_quality, extension.alpha_quality) {
+                    (Some(quality), _) => quantizer_quality(quality),
+                    (None, Some(quality)) if Some(channel % channels) == alpha => {
+                        quantizer_quality(quality)
+                    }
+                    _ if is_chroma => chroma_quality,
+                    _ => i32::from(header.quality),
+                };
+                ChannelCoding { quality, is_chroma }
+            }));
+        self.quality_map.clone_from(&extension.quality_map);
+    }
+
+    /// Quality scales of `channel`, none for channels coded losslessly so that they stay
+    /// lossless.
+    pub fn scales(&self, header: &Header, channel: &ChannelCoding) -> Scales<'_> {
+        let max_quality = i32::from(QUALITY_MAX) * i32::from(header.get_boost());
+        if channel.quality >= max_quality {
+            return Scales::default();
+        }
+        Scales {
+            map: self.quality_map.as_ref(),
+            subbands: match header.weighting {
+                Weighting::Flat => None,
+                Weighting::Csf => Some(&CSF_WEIGHTS),
+            },
+        }
+    }
+
+    pub fn quantizer(&self, header: &Header) -> Quantizer {
+        match header.quantization {
+            Quantization::Scalar => Quantizer::Scalar,
+            Quantization::DeadZone => Quantizer::DeadZone(self.dead_zone),
+        }
+    }
+}
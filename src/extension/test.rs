@@ -0,0 +1,315 @@
+use super::*;
+use crate::header::{Encoder, Filter, HeaderBuilder, Intent};
+use crate::quant::QUALITY_SCALE_ONE;
+
+#[test]
+fn test_encode_decode() {
+    let header = Header {
+        has_extension: true,
+        ..HeaderBuilder {
+            width: 8,
+            height: 8,
+            channels: 4,
+            filter: Filter::Linear,
+            encoder: Encoder::Turbo,
+            intent: Intent::RGBA,
+            ..HeaderBuilder::test_default()
+        }
+        .build()
+        .unwrap()
+    };
+    let extension = Extension {
+        alpha_quality: Some(QUALITY_MAX),
+        channel_qualities: vec![0, 100, 200, QUALITY_MAX],
+        // a single cell, so the last word holds half a pair
+        quality_map: Some(QualityMap::new(8, 8, 3, QUALITY_SCALE_MAX)),
+    };
+    // the extension follows the application metadata
+    let mut metadata = vec![7; 8];
+    extension.encode(&mut metadata).unwrap();
+    assert_eq!(metadata.len(), 8 + extension.encoded_size());
+
+    assert_eq!(Extension::decode(&metadata, &header).unwrap(), extension);
+    assert_eq!(Extension::find(&metadata, &header), &metadata[8..]);
+}
+
+#[test]
+fn test_unmarked_metadata() {
+    let mut metadata = vec![7; 8];
+    Extension {
+        alpha_quality: Some(QUALITY_MAX),
+        ..Extension::default()
+    }
+    .encode(&mut metadata)
+    .unwrap();
+
+    // metadata of streams without the extension is never read, whatever it ends with
+    let mut header = Header {
+        has_extension: true,
+        ..HeaderBuilder {
+            width: 8,
+            height: 8,
+            channels: 4,
+            filter: Filter::Linear,
+            encoder: Encoder::Turbo,
+            intent: Intent::RGBA,
+            ..HeaderBuilder::test_default()
+        }
+        .build()
+        .unwrap()
+    };
+    header.has_extension = false;
+    assert_eq!(
+        Extension::decode(&metadata, &header).unwrap(),
+        Extension::default()
+    );
+    assert!(Extension::find(&metadata, &header).is_empty());
+
+    // while streams marked as carrying it must end with it
+    header.has_extension = true;
+    match Extension::decode(&metadata[..12], &header) {
+        Err(Error::Extension { tag: MAGIC }) => (),
+        result => panic!("unexpected result {:?}", result),
+    }
+}
+
+#[test]
+fn test_empty() {
+    let extension = Extension::default();
+    let mut metadata = vec![];
+    extension.encode(&mut metadata).unwrap();
+    assert!(metadata.is_empty());
+    assert_eq!(extension.encoded_size(), 0);
+}
+
+#[test]
+fn test_decode_invalid() {
+    let mut metadata = vec![];
+    Extension {
+        alpha_quality: Some(256),
+        ..Extension::default()
+    }
+    .encode(&mut metadata)
+    .unwrap();
+
+    match Extension::decode(
+        &metadata,
+        &Header {
+            has_extension: true,
+            ..HeaderBuilder {
+                width: 8,
+                height: 8,
+                filter: Filter::Linear,
+                encoder: Encoder::Turbo,
+                ..HeaderBuilder::test_default()
+            }
+            .build()
+            .unwrap()
+        },
+    ) {
+        Err(Error::NoAlphaChannel) => (),
+        result => panic!("unexpected result {:?}", result),
+    }
+
+    // alpha quality above the maximum, which doesn't fit into 16 bits either
+    metadata[8..12].copy_from_slice(&0x1_0001u32.to_le_bytes());
+    match Extension::decode(
+        &metadata,
+        &Header {
+            has_extension: true,
+            ..HeaderBuilder {
+                width: 8,
+                height: 8,
+                channels: 4,
+                filter: Filter::Linear,
+                encoder: Encoder::Turbo,
+                intent: Intent::RGBA,
+                ..HeaderBuilder::test_default()
+            }
+            .build()
+            .unwrap()
+        },
+    ) {
+        Err(Error::Extension {
+            tag: TAG_ALPHA_QUALITY,
+        }) => (),
+        result => panic!("unexpected result {:?}", result),
+    }
+
+    // unknown record
+    metadata[0] = 0xff;
+    match Extension::decode(
+        &metadata,
+        &Header {
+            has_extension: true,
+            ..HeaderBuilder {
+                width: 8,
+                height: 8,
+                channels: 4,
+                filter: Filter::Linear,
+                encoder: Encoder::Turbo,
+                intent: Intent::RGBA,
+                ..HeaderBuilder::test_default()
+            }
+            .build()
+            .unwrap()
+        },
+    ) {
+        Err(Error::Extension { tag: 0xff }) => (),
+        result => panic!("unexpected result {:?}", result),
+    }
+
+    // size pointing before the metadata area
+    let len = metadata.len();
+    metadata[len - 8] = 0xf0;
+    match Extension::decode(
+        &metadata,
+        &Header {
+            has_extension: true,
+            ..HeaderBuilder {
+                width: 8,
+                height: 8,
+                channels: 4,
+                filter: Filter::Linear,
+                encoder: Encoder::Turbo,
+                intent: Intent::RGBA,
+                ..HeaderBuilder::test_default()
+            }
+            .build()
+            .unwrap()
+        },
+    ) {
+        Err(Error::IOErr(IOError::UnexpectedEof)) => (),
+        result => panic!("unexpected result {:?}", result),
+    }
+}
+
+#[test]
+fn test_channel_codings() {
+    let header = Header {
+        has_extension: true,
+        ..HeaderBuilder {
+            width: 8,
+            height: 8,
+            channels: 4,
+            filter: Filter::Linear,
+            encoder: Encoder::Turbo,
+            intent: Intent::RGBA,
+            ..HeaderBuilder::test_default()
+        }
+        .build()
+        .unwrap()
+    };
+    let is_chroma = [true, false, true, false];
+    let qualities = |extension: &Extension| {
+        Coding::new(&header, &is_chroma, extension)
+            .channels
+            .iter()
+            .map(|c| c.quality)
+            .collect::<Vec<_>>()
+    };
+
+    assert_eq!(qualities(&Extension::default()), vec![64, 512, 64, 512]);
+
+    let mut extension = Extension {
+        alpha_quality: Some(QUALITY_MAX),
+        ..Extension::default()
+    };
+    assert_eq!(qualities(&extension), vec![64, 512, 64, 8 * 1024]);
+    let coding = Coding::new(&header, &is_chroma, &extension);
+    assert!(coding.channels[0].is_chroma && !coding.channels[3].is_chroma);
+
+    // the table wins over the alpha quality, 0 keeps the quality without the table
+    extension.channel_qualities = vec![QUALITY_MAX, 0, 300, 200];
+    assert_eq!(qualities(&extension), vec![8 * 1024, 512, 300, 200]);
+}
+
+#[test]
+fn test_channel_qualities_length() {
+    let mut metadata = vec![];
+    Extension {
+        channel_qualities: vec![512; 3],
+        ..Extension::default()
+    }
+    .encode(&mut metadata)
+    .unwrap();
+
+    assert_eq!(
+        Extension::decode(
+            &metadata,
+            &Header {
+                has_extension: true,
+                ..HeaderBuilder {
+                    width: 8,
+                    height: 8,
+                    filter: Filter::Linear,
+                    encoder: Encoder::Turbo,
+                    ..HeaderBuilder::test_default()
+                }
+                .build()
+                .unwrap()
+            }
+        )
+        .unwrap()
+        .channel_qualities,
+        vec![512; 3]
+    );
+    match Extension::decode(
+        &metadata,
+        &Header {
+            has_extension: true,
+            ..HeaderBuilder {
+                width: 8,
+                height: 8,
+                channels: 4,
+                filter: Filter::Linear,
+                encoder: Encoder::Turbo,
+                intent: Intent::RGBA,
+                ..HeaderBuilder::test_default()
+            }
+            .build()
+            .unwrap()
+        },
+    ) {
+        Err(Error::QualityTable {
+            expected: 4,
+            actual: 3,
+        }) => (),
+        result => panic!("unexpected result {:?}", result),
+    }
+}
+
+#[test]
+fn test_quality_map_shape() {
+    let header = Header {
+        has_extension: true,
+        ..HeaderBuilder {
+            width: 8,
+            height: 8,
+            filter: Filter::Linear,
+            encoder: Encoder::Turbo,
+            ..HeaderBuilder::test_default()
+        }
+        .build()
+        .unwrap()
+    };
+    let mut extension = Extension {
+        quality_map: Some(QualityMap::new(16, 8, 2, QUALITY_SCALE_ONE)),
+        ..Extension::default()
+    };
+    match extension.check(&header) {
+        Err(Error::QualityMapShape {
+            expected: (2, 2),
+            actual: (4, 2),
+        }) => (),
+        result => panic!("unexpected result {:?}", result),
+    }
+
+    extension.quality_map = Some(QualityMap::new(8, 8, 2, 0));
+    match extension.check(&header) {
+        Err(Error::Extension {
+            tag: TAG_QUALITY_MAP,
+        }) => (),
+        result => panic!("unexpected result {:?}", result),
+    }
+}
@@ -1,5 +1,6 @@
-use super::{Encoder, Filter, Header, HeaderErr, Intent, Quantization};
-use std::fmt::Display;
+use super::{Encoder, Filter, Header, HeaderErr, Intent, Quantization, Weighting};
+use alloc::{format, string::String};
+use core::fmt::Display;
 
 pub struct HeaderBuilder {
     pub width: u32,
@@ -13,6 +14,8 @@ pub struct HeaderBuilder {
     pub encoder: Encoder,
     pub intent: Intent,
     pub metadata_size: u32,
+    pub quantization: Quantization,
+    pub weighting: Weighting,
 }
 
 impl HeaderBuilder {
@@ -46,7 +49,9 @@ impl HeaderBuilder {
             chroma_scale: self.chroma_scale,
             block_size: check_range(self.block_size, 0, 31, "Block size")?,
             filter: self.filter,
-            quantization: Quantization::Scalar,
+            quantization: self.quantization,
+            weighting: self.weighting,
+            has_extension: false,
             encoder: self.encoder,
             intent: self.intent,
             metadata_size: self.metadata_size,
@@ -56,6 +61,38 @@ impl HeaderBuilder {
     }
 }
 
+#[cfg(test)]
+impl HeaderBuilder {
+    /// Small RGB image the tests override the fields they care about of.
+    pub(crate) fn test_default() -> Self {
+        HeaderBuilder {
+            width: 16,
+            height: 16,
+            layers: 1,
+            channels: 3,
+            quality: 512,
+            chroma_scale: 8,
+            block_size: 2,
+            filter: Filter::Cubic,
+            encoder: Encoder::Contextual,
+            intent: Intent::RGB,
+            metadata_size: 0,
+            quantization: Quantization::Scalar,
+            weighting: Weighting::Flat,
+        }
+    }
+}
+
+#[cfg(test)]
+impl Header {
+    /// Deterministic noise of `get_image_size()` bytes, different for every `seed`.
+    pub(crate) fn test_frame(&self, seed: usize) -> alloc::vec::Vec<u8> {
+        (0..self.get_image_size())
+            .map(|i| ((i * (seed + 3)) ^ (i >> 3) ^ seed) as u8)
+            .collect()
+    }
+}
+
 fn check_range<T>(value: T, min: T, max: T, name: &str) -> Result<T, HeaderErr>
 where
     T: PartialOrd + Display + Copy,
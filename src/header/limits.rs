@@ -0,0 +1,72 @@
+use crate::errors::HeaderErr;
+
+/// Upper bounds for the values read from a stream. They are checked by `Header::decode` and
+/// `ColorTransformProgram::decode` before anything is allocated, so a small malicious header
+/// can't make the caller allocate gigabytes of memory.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DecodeLimits {
+    pub max_width: u32,
+    pub max_height: u32,
+    /// Maximum of `width * height * channels * layers`.
+    pub max_samples: u64,
+    /// Maximum of `channels * layers`.
+    pub max_channels_layers: u32,
+    /// Maximum number of channel transforms and channel factors in a color transform program.
+    pub max_transform_program_len: usize,
+}
+
+impl Default for DecodeLimits {
+    fn default() -> Self {
+        DecodeLimits {
+            max_width: 1 << 16,
+            max_height: 1 << 16,
+            max_samples: 1 << 28,
+            max_channels_layers: 1 << 10,
+            max_transform_program_len: 1 << 10,
+        }
+    }
+}
+
+impl DecodeLimits {
+    /// Limits that accept every header `HeaderBuilder` accepts.
+    pub fn unlimited() -> Self {
+        DecodeLimits {
+            max_width: u32::MAX,
+            max_height: u32::MAX,
+            max_samples: u64::MAX,
+            max_channels_layers: u32::MAX,
+            max_transform_program_len: usize::MAX,
+        }
+    }
+
+    pub(crate) fn check_dimensions(
+        &self,
+        width: u32,
+        height: u32,
+        channels: u16,
+        layers: u16,
+    ) -> Result<(), HeaderErr> {
+        check_limit("width", u64::from(width), u64::from(self.max_width))?;
+        check_limit("height", u64::from(height), u64::from(self.max_height))?;
+
+        let channels_layers = u64::from(channels) * u64::from(layers);
+        check_limit(
+            "channels * layers",
+            channels_layers,
+            u64::from(self.max_channels_layers),
+        )?;
+        check_limit(
+            "samples",
+            (u64::from(width) * u64::from(height)).saturating_mul(channels_layers),
+            self.max_samples,
+        )
+    }
+}
+
+fn check_limit(name: &'static str, value: u64, max: u64) -> Result<(), HeaderErr> {
+    if value <= max {
+        Ok(())
+    } else {
+        Err(HeaderErr::LimitExceeded { name, value, max })
+    }
+}
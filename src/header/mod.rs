@@ -2,21 +2,27 @@
 // https://github.com/rust-num/num-derive/issues/20 is fixed
 #![cfg_attr(feature = "cargo-clippy", allow(clippy::useless_attribute))]
 
-use std::{io, usize};
+use alloc::string::String;
+use core::usize;
 
+use crate::color_transform::ColorTransformProgram;
 use crate::errors::HeaderErr;
-use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use crate::io::{IOError, Read, Write};
 use num_traits::{FromPrimitive, ToPrimitive};
 
 mod builder;
+mod limits;
 #[cfg(test)]
 mod test;
 
 pub use self::builder::HeaderBuilder;
+pub use self::limits::DecodeLimits;
 
 pub const QUALITY_MAX: u16 = 1024;
 pub const BLOCK_DEFAULT: u8 = 7;
 pub const BLOCK_MAX: u8 = 30;
+/// Size of the encoded header in bytes. The metadata area starts right after it.
+pub const HEADER_SIZE: usize = 32;
 
 const MAGIC: u32 = 'G' as u32 | (('F' as u32) << 8) | (('W' as u32) << 16) | (('X' as u32) << 24);
 
@@ -29,6 +35,18 @@ pub enum Filter {
 #[derive(Clone, Copy, Debug, PartialEq, ToPrimitive, FromPrimitive)]
 pub enum Quantization {
     Scalar = 0,
+    /// Dead-zone quantizer with rate-distortion optimized zeroing, see `quant::DeadZone`.
+    DeadZone = 1,
+}
+
+/// Quality weighting of the wavelet levels and orientations on top of doubling the quality per
+/// level. It is stored in the upper half of the quantization byte of the header.
+#[derive(Clone, Copy, Debug, PartialEq, ToPrimitive, FromPrimitive)]
+pub enum Weighting {
+    Flat = 0,
+    /// Contrast sensitivity function weighting, see `quant::CSF_WEIGHTS`. The fine diagonal
+    /// details the eye is least sensitive to get the lowest quality.
+    Csf = 1,
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, ToPrimitive, FromPrimitive)]
@@ -36,6 +54,19 @@ pub enum Encoder {
     Turbo = 0,
     Fast = 1,
     Contextual = 2,
+    /// Screen content: tiles with few colors are coded exactly with a palette and runs, see
+    /// `palette::TILE_SIZE`, the rest with the wavelets like `Contextual`.
+    Screen = 3,
+}
+
+impl Encoder {
+    /// Scheme the wavelet coefficients are coded with.
+    pub fn coefficient_scheme(self) -> Encoder {
+        match self {
+            Encoder::Screen => Encoder::Contextual,
+            scheme => scheme,
+        }
+    }
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, ToPrimitive, FromPrimitive)]
@@ -48,7 +79,20 @@ pub enum Intent {
     YUV444 = 12,
 }
 
-#[derive(Debug, PartialEq)]
+/// Reason why decompression doesn't give back the compressed image exactly.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LossyReason {
+    /// `quality` is below `QUALITY_MAX`, so the coefficients are quantized.
+    Quality,
+    /// `chroma_scale` lowers the quality of chroma channels below `QUALITY_MAX`.
+    ChromaScale,
+    /// Decompressed samples are clamped to unsigned 8 bits.
+    BitDepth,
+    /// Color transform of the channel can't be inverted exactly.
+    Transform { channel: usize },
+}
+
+#[derive(Clone, Debug, PartialEq)]
 pub struct Header {
     pub version: u32,
     pub width: u32,
@@ -62,6 +106,11 @@ pub struct Header {
     pub block_size: u8,
     pub filter: Filter,
     pub quantization: Quantization,
+    pub weighting: Weighting,
+    /// The metadata area ends with the coding extension, see `extension::Extension`. Stored
+    /// in bit 3 of the quantization byte, so decoders that don't know the extension reject the
+    /// stream instead of decoding it wrong.
+    pub has_extension: bool,
     pub encoder: Encoder,
     pub intent: Intent,
     pub metadata_size: u32,
@@ -70,24 +119,31 @@ pub struct Header {
 }
 
 impl Header {
-    pub fn decode(encoded: &mut impl io::Read) -> Result<Header, HeaderErr> {
-        if encoded.read_u32::<LittleEndian>()? != MAGIC {
+    pub fn decode(encoded: &mut impl Read) -> Result<Header, HeaderErr> {
+        Header::decode_with_limits(encoded, &DecodeLimits::default())
+    }
+
+    pub fn decode_with_limits(
+        encoded: &mut impl Read,
+        limits: &DecodeLimits,
+    ) -> Result<Header, HeaderErr> {
+        if encoded.read_u32()? != MAGIC {
             return Err(HeaderErr::WrongMagic);
         }
 
-        let version = encoded.read_u32::<LittleEndian>()?;
-        let width = encoded.read_u32::<LittleEndian>()?;
-        let height = encoded.read_u32::<LittleEndian>()?;
+        let version = encoded.read_u32()?;
+        let width = encoded.read_u32()?;
+        let height = encoded.read_u32()?;
         let channels = encoded
-            .read_u16::<LittleEndian>()?
+            .read_u16()?
             .checked_add(1)
             .ok_or_else(|| HeaderErr::WrongValue(String::from("Wrong channels value")))?;
         let layers = encoded
-            .read_u16::<LittleEndian>()?
+            .read_u16()?
             .checked_add(1)
             .ok_or_else(|| HeaderErr::WrongValue(String::from("Wrong layers value")))?;
 
-        let tmp = encoded.read_u24::<LittleEndian>()?;
+        let tmp = encoded.read_u24()?;
         let block_size = ((tmp & 0b11111) as u8)
             .checked_add(2)
             .ok_or_else(|| HeaderErr::WrongValue(String::from("Wrong block_size value")))?;
@@ -107,12 +163,15 @@ impl Header {
             .ok_or_else(|| HeaderErr::WrongValue(String::from("Wrong intent value")))?;
         let encoder = Encoder::from_u8(encoded.read_u8()?)
             .ok_or_else(|| HeaderErr::WrongValue(String::from("Wrong encoder value")))?;
-        let quantization = Quantization::from_u8(encoded.read_u8()?)
+        let quantization_byte = encoded.read_u8()?;
+        let quantization = Quantization::from_u8(quantization_byte & 0x7)
             .ok_or_else(|| HeaderErr::WrongValue(String::from("Wrong quantization value")))?;
+        let weighting = Weighting::from_u8(quantization_byte >> 4)
+            .ok_or_else(|| HeaderErr::WrongValue(String::from("Wrong weighting value")))?;
         let filter = Filter::from_u8(encoded.read_u8()?)
             .ok_or_else(|| HeaderErr::WrongValue(String::from("Wrong filter value")))?;
         let metadata_size = encoded
-            .read_u32::<LittleEndian>()?
+            .read_u32()?
             .checked_mul(4)
             .ok_or_else(|| HeaderErr::WrongValue(String::from("Wrong metadata_size value")))?;
 
@@ -122,6 +181,8 @@ impl Header {
             )));
         }
 
+        limits.check_dimensions(width, height, channels, layers)?;
+
         let builder = HeaderBuilder {
             width,
             height,
@@ -134,34 +195,71 @@ impl Header {
             encoder,
             intent,
             metadata_size,
+            quantization,
+            weighting,
         };
         let mut header = builder.build()?;
         header.version = version;
         header.bit_depth = bit_depth;
         header.is_signed = is_signed;
-        header.quantization = quantization;
+        header.has_extension = quantization_byte & 0x8 != 0;
 
         Ok(header)
     }
 
-    pub fn encode(&self, buff: &mut impl io::Write) -> io::Result<()> {
-        buff.write_u32::<LittleEndian>(MAGIC)?;
-        buff.write_u32::<LittleEndian>(self.version)?;
-        buff.write_u32::<LittleEndian>(self.width)?;
-        buff.write_u32::<LittleEndian>(self.height)?;
-        buff.write_u16::<LittleEndian>(self.channels - 1)?;
-        buff.write_u16::<LittleEndian>(self.layers - 1)?;
+    pub fn encode(&self, buff: &mut impl Write) -> Result<(), IOError> {
+        buff.write_u32(MAGIC)?;
+        buff.write_u32(self.version)?;
+        buff.write_u32(self.width)?;
+        buff.write_u32(self.height)?;
+        buff.write_u16(self.channels - 1)?;
+        buff.write_u16(self.layers - 1)?;
         let tmp = u32::from(self.block_size - 2)
             | (u32::from(self.chroma_scale - 1) << 5)
             | (u32::from(self.quality - 1) << 13)
             | ((if self.is_signed { 1 } else { 0 }) << 23);
-        buff.write_u24::<LittleEndian>(tmp)?;
+        buff.write_u24(tmp)?;
         buff.write_u8(self.bit_depth - 1)?;
         buff.write_u8(self.intent.to_u8().unwrap())?;
         buff.write_u8(self.encoder.to_u8().unwrap())?;
-        buff.write_u8(self.quantization.to_u8().unwrap())?;
+        buff.write_u8(
+            self.quantization.to_u8().unwrap()
+                | (u8::from(self.has_extension) << 3)
+                | (self.weighting.to_u8().unwrap() << 4),
+        )?;
         buff.write_u8(self.filter.to_u8().unwrap())?;
-        buff.write_u32::<LittleEndian>(self.metadata_size / 4)?;
+        buff.write_u32(self.metadata_size / 4)?;
+
+        Ok(())
+    }
+
+    pub fn is_lossless(&self, color_transform: &ColorTransformProgram) -> bool {
+        self.check_lossless(color_transform).is_ok()
+    }
+
+    /// Checks that decompressing images compressed with this header and `color_transform` gives
+    /// them back exactly. Lifting and color transforms wrap around on overflow, so they are
+    /// exact whenever nothing is quantized and every transform can be undone.
+    pub fn check_lossless(
+        &self,
+        color_transform: &ColorTransformProgram,
+    ) -> Result<(), LossyReason> {
+        if self.quality < QUALITY_MAX {
+            return Err(LossyReason::Quality);
+        }
+        if self.bit_depth > 8 || self.is_signed {
+            return Err(LossyReason::BitDepth);
+        }
+
+        let channels = self.channels as usize * self.layers as usize;
+        if let Some(channel) = color_transform.find_non_invertible_channel(channels) {
+            return Err(LossyReason::Transform { channel });
+        }
+        if self.get_chroma_quality() < i32::from(QUALITY_MAX)
+            && color_transform.iter().any(|t| t.is_chroma)
+        {
+            return Err(LossyReason::ChromaScale);
+        }
 
         Ok(())
     }
@@ -178,6 +276,14 @@ impl Header {
         }
     }
 
+    /// Index of the alpha channel within a layer, for RGBA and BGRA intents.
+    pub fn get_alpha_channel(&self) -> Option<usize> {
+        match self.intent {
+            Intent::RGBA | Intent::BGRA if self.channels >= 4 => Some(3),
+            _ => None,
+        }
+    }
+
     pub fn get_downsampled_width(&self, downsampling: usize) -> usize {
         (self.width as usize + (1 << downsampling) - 1) >> downsampling
     }
@@ -19,6 +19,8 @@ fn test_header_encoding() {
         block_size: BLOCK_DEFAULT,
         filter: Filter::Linear,
         quantization: Quantization::Scalar,
+        weighting: Weighting::Flat,
+        has_extension: false,
         encoder: Encoder::Contextual,
         intent: Intent::RGBA,
         metadata_size: 0,
@@ -54,6 +56,8 @@ fn test_header_decoding() {
         block_size: BLOCK_DEFAULT,
         filter: Filter::Linear,
         quantization: Quantization::Scalar,
+        weighting: Weighting::Flat,
+        has_extension: false,
         encoder: Encoder::Contextual,
         intent: Intent::RGBA,
         metadata_size: 0,
@@ -65,8 +69,7 @@ fn test_header_decoding() {
         71, 70, 87, 88, 1, 0, 0, 0, 128, 7, 0, 0, 56, 4, 0, 0, 3, 0, 0, 0, 5, 224, 127, 7, 8, 2, 0,
         0, 0, 0, 0, 0,
     ];
-    let mut encoded = io::Cursor::new(buff);
-    let header = Header::decode(&mut encoded).unwrap();
+    let header = Header::decode(&mut &buff[..]).unwrap();
     assert_eq!(header, expected_header);
 }
 
@@ -89,6 +92,8 @@ fn test_encoding_decoding() {
         block_size: BLOCK_DEFAULT,
         filter: Filter::Linear,
         quantization: Quantization::Scalar,
+        weighting: Weighting::Flat,
+        has_extension: false,
         encoder: Encoder::Contextual,
         intent: Intent::RGBA,
         metadata_size: 0,
@@ -98,20 +103,123 @@ fn test_encoding_decoding() {
 
     let mut encoded = vec![];
     header.encode(&mut encoded).unwrap();
-    let mut cursor = io::Cursor::new(encoded);
-    let decoded = Header::decode(&mut cursor).unwrap();
+    let decoded = Header::decode(&mut &encoded[..]).unwrap();
     assert_eq!(header, decoded);
+
+    // the weighting and the extension flag share the byte with the quantization
+    let header = Header {
+        quantization: Quantization::DeadZone,
+        weighting: Weighting::Csf,
+        has_extension: true,
+        ..header
+    };
+    let mut encoded = vec![];
+    header.encode(&mut encoded).unwrap();
+    assert_eq!(encoded[26], 0x19);
+    assert_eq!(Header::decode(&mut &encoded[..]).unwrap(), header);
+
+    encoded[26] = 0x20;
+    match Header::decode(&mut &encoded[..]) {
+        Err(HeaderErr::WrongValue(_)) => (),
+        result => panic!("unexpected result {:?}", result),
+    }
+}
+
+#[test]
+fn test_builder_coding_choices() {
+    let header = HeaderBuilder {
+        quantization: Quantization::DeadZone,
+        weighting: Weighting::Csf,
+        ..HeaderBuilder::test_default()
+    }
+    .build()
+    .unwrap();
+    assert_eq!(header.quantization, Quantization::DeadZone);
+    assert_eq!(header.weighting, Weighting::Csf);
 }
 
 #[test]
 fn test_wrong_magic() {
-    let mut encoded = io::Cursor::new(vec![0u8; 32]);
-    match Header::decode(&mut encoded) {
+    match Header::decode(&mut &[0u8; 32][..]) {
         Err(HeaderErr::WrongMagic) => (),
         _ => panic!("wrong result of decoding with invalid magic"),
     }
 }
 
+fn encode_header(width: u32, height: u32, layers: u16, channels: u16) -> Vec<u8> {
+    let header = HeaderBuilder {
+        width,
+        height,
+        layers,
+        channels,
+        quality: QUALITY_MAX,
+        chroma_scale: 1,
+        block_size: BLOCK_DEFAULT,
+        filter: Filter::Linear,
+        intent: Intent::Generic,
+        ..HeaderBuilder::test_default()
+    }
+    .build()
+    .unwrap();
+    let mut encoded = vec![];
+    header.encode(&mut encoded).unwrap();
+    encoded
+}
+
+#[test]
+fn test_decode_limits() {
+    let limits = DecodeLimits {
+        max_width: 100,
+        max_height: 50,
+        max_samples: 100 * 50 * 2,
+        max_channels_layers: 4,
+        max_transform_program_len: 0,
+    };
+
+    let decode = |encoded: Vec<u8>| Header::decode_with_limits(&mut encoded.as_slice(), &limits);
+    assert!(decode(encode_header(100, 50, 1, 2)).is_ok());
+    match decode(encode_header(101, 1, 1, 1)) {
+        Err(HeaderErr::LimitExceeded {
+            name: "width",
+            value: 101,
+            max: 100,
+        }) => (),
+        r => panic!("width limit is not checked: {:?}", r),
+    }
+    match decode(encode_header(1, 51, 1, 1)) {
+        Err(HeaderErr::LimitExceeded { name: "height", .. }) => (),
+        r => panic!("height limit is not checked: {:?}", r),
+    }
+    match decode(encode_header(1, 1, 5, 1)) {
+        Err(HeaderErr::LimitExceeded {
+            name: "channels * layers",
+            ..
+        }) => (),
+        r => panic!("channels * layers limit is not checked: {:?}", r),
+    }
+    match decode(encode_header(100, 50, 1, 3)) {
+        Err(HeaderErr::LimitExceeded {
+            name: "samples",
+            value: 15000,
+            max: 10000,
+        }) => (),
+        r => panic!("samples limit is not checked: {:?}", r),
+    }
+}
+
+#[test]
+fn test_default_decode_limits() {
+    let encoded = encode_header(1 << 29, 1 << 29, 1, 1);
+    match Header::decode(&mut encoded.as_slice()) {
+        Err(HeaderErr::LimitExceeded { .. }) => (),
+        r => panic!("default limits must reject huge images: {:?}", r),
+    }
+
+    let header =
+        Header::decode_with_limits(&mut encoded.as_slice(), &DecodeLimits::unlimited()).unwrap();
+    assert_eq!(header.width, 1 << 29);
+}
+
 #[test]
 fn test_builder_small_width() {
     let small_width = 0;
@@ -127,6 +235,8 @@ fn test_builder_small_width() {
         encoder: Encoder::Contextual,
         intent: Intent::RGBA,
         metadata_size: 0,
+        quantization: Quantization::Scalar,
+        weighting: Weighting::Flat,
     };
     match builder.build() {
         Err(HeaderErr::WrongValue(_)) => (),
@@ -152,6 +262,8 @@ fn test_builder_large_width() {
         encoder: Encoder::Contextual,
         intent: Intent::RGBA,
         metadata_size: 0,
+        quantization: Quantization::Scalar,
+        weighting: Weighting::Flat,
     };
     match builder.build() {
         Err(HeaderErr::WrongValue(_)) => (),
@@ -177,6 +289,8 @@ fn test_builder_small_height() {
         encoder: Encoder::Contextual,
         intent: Intent::RGBA,
         metadata_size: 0,
+        quantization: Quantization::Scalar,
+        weighting: Weighting::Flat,
     };
     match builder.build() {
         Err(HeaderErr::WrongValue(_)) => (),
@@ -202,6 +316,8 @@ fn test_builder_large_height() {
         encoder: Encoder::Contextual,
         intent: Intent::RGBA,
         metadata_size: 0,
+        quantization: Quantization::Scalar,
+        weighting: Weighting::Flat,
     };
     match builder.build() {
         Err(HeaderErr::WrongValue(_)) => (),
@@ -227,6 +343,8 @@ fn test_builder_small_quality() {
         encoder: Encoder::Contextual,
         intent: Intent::RGBA,
         metadata_size: 0,
+        quantization: Quantization::Scalar,
+        weighting: Weighting::Flat,
     };
     match builder.build() {
         Err(HeaderErr::WrongValue(_)) => (),
@@ -252,6 +370,8 @@ fn test_builder_large_quality() {
         encoder: Encoder::Contextual,
         intent: Intent::RGBA,
         metadata_size: 0,
+        quantization: Quantization::Scalar,
+        weighting: Weighting::Flat,
     };
     match builder.build() {
         Err(HeaderErr::WrongValue(_)) => (),
@@ -277,6 +397,8 @@ fn test_builder_small_block_size() {
         encoder: Encoder::Contextual,
         intent: Intent::RGBA,
         metadata_size: 0,
+        quantization: Quantization::Scalar,
+        weighting: Weighting::Flat,
     };
     match builder.build() {
         Err(HeaderErr::WrongValue(_)) => (),
@@ -302,6 +424,8 @@ fn test_builder_large_block_size() {
         encoder: Encoder::Contextual,
         intent: Intent::RGBA,
         metadata_size: 0,
+        quantization: Quantization::Scalar,
+        weighting: Weighting::Flat,
     };
     match builder.build() {
         Err(HeaderErr::WrongValue(_)) => (),
@@ -326,6 +450,8 @@ fn test_builder_large_layer_size() {
         encoder: Encoder::Contextual,
         intent: Intent::RGBA,
         metadata_size: 0,
+        quantization: Quantization::Scalar,
+        weighting: Weighting::Flat,
     };
     match builder.build() {
         Err(HeaderErr::WrongValue(_)) => (),
@@ -347,9 +473,76 @@ fn test_builder_large_image_size() {
         encoder: Encoder::Contextual,
         intent: Intent::RGBA,
         metadata_size: 0,
+        quantization: Quantization::Scalar,
+        weighting: Weighting::Flat,
     };
     match builder.build() {
         Err(HeaderErr::WrongValue(_)) => (),
         _ => panic!("HeaderBuilder must return Err for the large image size",),
     }
 }
+
+#[test]
+fn test_check_lossless() {
+    use crate::color_transform::ChannelTransformBuilder;
+
+    let build = |quality, chroma_scale| {
+        HeaderBuilder {
+            quality,
+            chroma_scale,
+            block_size: BLOCK_DEFAULT,
+            ..HeaderBuilder::test_default()
+        }
+        .build()
+        .unwrap()
+    };
+    let header = build(QUALITY_MAX, 1);
+    let rgb_to_yuv = ColorTransformProgram::rgb_to_yuv();
+
+    assert!(header.is_lossless(&ColorTransformProgram::new()));
+    assert!(header.is_lossless(&rgb_to_yuv));
+    assert!(header.is_lossless(&ColorTransformProgram::rgb_to_a710()));
+    assert_eq!(
+        build(QUALITY_MAX - 1, 1).check_lossless(&ColorTransformProgram::new()),
+        Err(LossyReason::Quality)
+    );
+    // chroma scale only matters with chroma channels
+    assert!(build(QUALITY_MAX, 8).is_lossless(&ColorTransformProgram::new()));
+    assert_eq!(
+        build(QUALITY_MAX, 8).check_lossless(&rgb_to_yuv),
+        Err(LossyReason::ChromaScale)
+    );
+
+    let mut twice = rgb_to_yuv.clone();
+    twice.add_channel_transform(
+        ChannelTransformBuilder::with_dest_channel(0)
+            .add_channel_factor(2, 1)
+            .build(),
+    );
+    assert_eq!(
+        header.check_lossless(&twice),
+        Err(LossyReason::Transform { channel: 0 })
+    );
+
+    let mut reads_itself = ColorTransformProgram::new();
+    reads_itself.add_channel_transform(
+        ChannelTransformBuilder::with_dest_channel(1)
+            .add_channel_factor(1, 2)
+            .build(),
+    );
+    assert_eq!(
+        header.check_lossless(&reads_itself),
+        Err(LossyReason::Transform { channel: 1 })
+    );
+
+    let mut out_of_range = ColorTransformProgram::new();
+    out_of_range.add_channel_transform(
+        ChannelTransformBuilder::with_dest_channel(0)
+            .add_channel_factor(3, 1)
+            .build(),
+    );
+    assert_eq!(
+        header.check_lossless(&out_of_range),
+        Err(LossyReason::Transform { channel: 0 })
+    );
+}
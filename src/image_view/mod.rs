@@ -0,0 +1,355 @@
+use crate::errors::Error;
+
+#[cfg(test)]
+mod test;
+
+/// Interleaved image in caller-owned memory whose rows may be padded, e.g. a frame grabber or
+/// GPU readback buffer with a row pitch larger than `width * channels`.
+///
+/// Layers are stored one after another, each `height` rows of `stride` samples long.
+#[derive(Clone, Copy, Debug)]
+pub struct ImageView<'a, T> {
+    data: &'a [T],
+    shape: Shape,
+}
+
+/// Mutable counterpart of `ImageView`, filled by decompression.
+#[derive(Debug)]
+pub struct ImageViewMut<'a, T> {
+    data: &'a mut [T],
+    shape: Shape,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct Shape {
+    pub width: usize,
+    pub height: usize,
+    pub layers: usize,
+    pub channels: usize,
+    /// Distance between the starts of two rows, in samples.
+    pub stride: usize,
+}
+
+impl Shape {
+    fn new(
+        width: usize,
+        height: usize,
+        layers: usize,
+        channels: usize,
+        stride: usize,
+        len: usize,
+    ) -> Result<Self, Error> {
+        let row = width * channels;
+        if stride < row {
+            return Err(Error::ViewStride { stride, row });
+        }
+
+        let rows = layers * height;
+        let expected = if rows == 0 || row == 0 {
+            0
+        } else {
+            (rows - 1) * stride + row
+        };
+        if len < expected {
+            return Err(Error::BufferSize {
+                expected,
+                actual: len,
+            });
+        }
+
+        Ok(Shape {
+            width,
+            height,
+            layers,
+            channels,
+            stride,
+        })
+    }
+
+    pub fn check(
+        &self,
+        width: usize,
+        height: usize,
+        layers: usize,
+        channels: usize,
+    ) -> Result<(), Error> {
+        let expected = (width, height, layers, channels);
+        let actual = (self.width, self.height, self.layers, self.channels);
+        if expected == actual {
+            Ok(())
+        } else {
+            Err(Error::ViewShape { expected, actual })
+        }
+    }
+
+    /// Index of the first sample of `channel` in row `y`. Channels of the second and further
+    /// layers are numbered after the channels of the first one.
+    fn row_start(&self, channel: usize, y: usize) -> usize {
+        let layer = channel / self.channels;
+        (layer * self.height + y) * self.stride + channel % self.channels
+    }
+
+    fn row_len(&self) -> usize {
+        if self.width == 0 {
+            0
+        } else {
+            (self.width - 1) * self.channels + 1
+        }
+    }
+}
+
+impl<'a, T> ImageView<'a, T> {
+    /// Single layer view with rows `stride` samples apart.
+    pub fn new(
+        data: &'a [T],
+        width: usize,
+        height: usize,
+        channels: usize,
+        stride: usize,
+    ) -> Result<Self, Error> {
+        ImageView::with_layers(data, width, height, 1, channels, stride)
+    }
+
+    pub fn with_layers(
+        data: &'a [T],
+        width: usize,
+        height: usize,
+        layers: usize,
+        channels: usize,
+        stride: usize,
+    ) -> Result<Self, Error> {
+        let shape = Shape::new(width, height, layers, channels, stride, data.len())?;
+        Ok(ImageView { data, shape })
+    }
+
+    pub fn width(&self) -> usize {
+        self.shape.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.shape.height
+    }
+
+    pub fn layers(&self) -> usize {
+        self.shape.layers
+    }
+
+    pub fn channels(&self) -> usize {
+        self.shape.channels
+    }
+
+    pub fn stride(&self) -> usize {
+        self.shape.stride
+    }
+
+    pub(crate) fn shape(&self) -> &Shape {
+        &self.shape
+    }
+}
+
+impl<'a, T> ImageViewMut<'a, T> {
+    /// Single layer view with rows `stride` samples apart.
+    pub fn new(
+        data: &'a mut [T],
+        width: usize,
+        height: usize,
+        channels: usize,
+        stride: usize,
+    ) -> Result<Self, Error> {
+        ImageViewMut::with_layers(data, width, height, 1, channels, stride)
+    }
+
+    pub fn with_layers(
+        data: &'a mut [T],
+        width: usize,
+        height: usize,
+        layers: usize,
+        channels: usize,
+        stride: usize,
+    ) -> Result<Self, Error> {
+        let shape = Shape::new(width, height, layers, channels, stride, data.len())?;
+        Ok(ImageViewMut { data, shape })
+    }
+
+    pub fn width(&self) -> usize {
+        self.shape.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.shape.height
+    }
+
+    pub fn layers(&self) -> usize {
+        self.shape.layers
+    }
+
+    pub fn channels(&self) -> usize {
+        self.shape.channels
+    }
+
+    pub fn stride(&self) -> usize {
+        self.shape.stride
+    }
+
+    pub(crate) fn shape(&self) -> &Shape {
+        &self.shape
+    }
+}
+
+/// One channel of an image in its own buffer, e.g. a plane of a YUV video frame. Every plane
+/// may have its own stride.
+#[derive(Clone, Copy, Debug)]
+pub struct Plane<'a, T> {
+    data: &'a [T],
+    shape: Shape,
+}
+
+/// Mutable counterpart of `Plane`, filled by decompression.
+#[derive(Debug)]
+pub struct PlaneMut<'a, T> {
+    data: &'a mut [T],
+    shape: Shape,
+}
+
+impl<'a, T> Plane<'a, T> {
+    pub fn new(data: &'a [T], width: usize, height: usize, stride: usize) -> Result<Self, Error> {
+        let shape = Shape::new(width, height, 1, 1, stride, data.len())?;
+        Ok(Plane { data, shape })
+    }
+
+    pub fn width(&self) -> usize {
+        self.shape.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.shape.height
+    }
+
+    pub fn stride(&self) -> usize {
+        self.shape.stride
+    }
+
+    pub(crate) fn shape(&self) -> &Shape {
+        &self.shape
+    }
+}
+
+impl<'a, T> PlaneMut<'a, T> {
+    pub fn new(
+        data: &'a mut [T],
+        width: usize,
+        height: usize,
+        stride: usize,
+    ) -> Result<Self, Error> {
+        let shape = Shape::new(width, height, 1, 1, stride, data.len())?;
+        Ok(PlaneMut { data, shape })
+    }
+
+    pub fn width(&self) -> usize {
+        self.shape.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.shape.height
+    }
+
+    pub fn stride(&self) -> usize {
+        self.shape.stride
+    }
+
+    pub(crate) fn shape(&self) -> &Shape {
+        &self.shape
+    }
+}
+
+/// Checks that there is a plane of `width * height` samples for every channel.
+pub(crate) fn check_planes<'s>(
+    shapes: impl ExactSizeIterator<Item = &'s Shape>,
+    channels: usize,
+    width: usize,
+    height: usize,
+) -> Result<(), Error> {
+    if shapes.len() != channels {
+        return Err(Error::PlaneCount {
+            expected: channels,
+            actual: shapes.len(),
+        });
+    }
+    for shape in shapes {
+        shape.check(width, height, 1, 1)?;
+    }
+    Ok(())
+}
+
+/// Access to the samples of every channel row by row, independent of the memory layout.
+/// Each row holds `width` samples, `step` apart.
+pub(crate) trait ChannelRows {
+    type Sample;
+
+    fn row(&self, channel: usize, y: usize) -> (&[Self::Sample], usize);
+}
+
+pub(crate) trait ChannelRowsMut: ChannelRows {
+    fn row_mut(&mut self, channel: usize, y: usize) -> (&mut [Self::Sample], usize);
+}
+
+impl<T> ChannelRows for ImageView<'_, T> {
+    type Sample = T;
+
+    fn row(&self, channel: usize, y: usize) -> (&[T], usize) {
+        let start = self.shape.row_start(channel, y);
+        (
+            &self.data[start..start + self.shape.row_len()],
+            self.shape.channels,
+        )
+    }
+}
+
+impl<T> ChannelRows for ImageViewMut<'_, T> {
+    type Sample = T;
+
+    fn row(&self, channel: usize, y: usize) -> (&[T], usize) {
+        let start = self.shape.row_start(channel, y);
+        (
+            &self.data[start..start + self.shape.row_len()],
+            self.shape.channels,
+        )
+    }
+}
+
+impl<T> ChannelRowsMut for ImageViewMut<'_, T> {
+    fn row_mut(&mut self, channel: usize, y: usize) -> (&mut [T], usize) {
+        let start = self.shape.row_start(channel, y);
+        let len = self.shape.row_len();
+        (&mut self.data[start..start + len], self.shape.channels)
+    }
+}
+
+impl<T> ChannelRows for [Plane<'_, T>] {
+    type Sample = T;
+
+    fn row(&self, channel: usize, y: usize) -> (&[T], usize) {
+        let plane = &self[channel];
+        let start = plane.shape.row_start(0, y);
+        (&plane.data[start..start + plane.shape.row_len()], 1)
+    }
+}
+
+impl<T> ChannelRows for [PlaneMut<'_, T>] {
+    type Sample = T;
+
+    fn row(&self, channel: usize, y: usize) -> (&[T], usize) {
+        let plane = &self[channel];
+        let start = plane.shape.row_start(0, y);
+        (&plane.data[start..start + plane.shape.row_len()], 1)
+    }
+}
+
+impl<T> ChannelRowsMut for [PlaneMut<'_, T>] {
+    fn row_mut(&mut self, channel: usize, y: usize) -> (&mut [T], usize) {
+        let plane = &mut self[channel];
+        let start = plane.shape.row_start(0, y);
+        let len = plane.shape.row_len();
+        (&mut plane.data[start..start + len], 1)
+    }
+}
@@ -0,0 +1,254 @@
+use super::*;
+use crate::color_transform::ColorTransformProgram;
+use crate::header::{Filter, HeaderBuilder, Intent};
+
+const PADDING: u8 = 0xAB;
+
+/// Copies a packed image into rows of `stride` samples, filling the gaps with `PADDING`.
+fn pad_rows(image: &[u8], row: usize, stride: usize) -> Vec<u8> {
+    let mut padded = vec![PADDING; image.len() / row * stride];
+    for (src, dest) in image.chunks(row).zip(padded.chunks_mut(stride)) {
+        dest[..row].copy_from_slice(src);
+    }
+    padded
+}
+
+#[test]
+fn test_view_validation() {
+    let data = vec![0u8; 100];
+
+    let view = ImageView::new(&data, 4, 3, 3, 16).unwrap();
+    assert_eq!(
+        (view.width(), view.height(), view.channels(), view.stride()),
+        (4, 3, 3, 16)
+    );
+    // the last row doesn't need padding
+    assert!(ImageView::new(&data[..2 * 16 + 12], 4, 3, 3, 16).is_ok());
+
+    match ImageView::new(&data[..2 * 16 + 11], 4, 3, 3, 16) {
+        Err(Error::BufferSize {
+            expected: 44,
+            actual: 43,
+        }) => (),
+        r => panic!("short buffer must be rejected: {:?}", r),
+    }
+    match ImageView::new(&data, 4, 3, 3, 11) {
+        Err(Error::ViewStride {
+            stride: 11,
+            row: 12,
+        }) => (),
+        r => panic!("stride shorter than a row must be rejected: {:?}", r),
+    }
+    assert!(ImageView::with_layers(&data, 4, 3, 2, 3, 16).is_ok());
+    assert!(ImageView::with_layers(&data, 4, 3, 3, 3, 16).is_err());
+}
+
+#[test]
+fn test_compress_strided_view() {
+    let header = HeaderBuilder {
+        width: 13,
+        height: 7,
+        channels: 4,
+        quality: 1024,
+        chroma_scale: 1,
+        block_size: 4,
+        filter: Filter::Linear,
+        intent: Intent::RGBA,
+        ..HeaderBuilder::test_default()
+    }
+    .build()
+    .unwrap();
+    let image = (0..header.get_image_size())
+        .map(|i| ((i * 13) ^ (i >> 2)) as u8)
+        .collect::<Vec<_>>();
+    let stride = 13 * 4 + 9;
+    let padded = pad_rows(&image, 13 * 4, stride);
+    let color_transform = ColorTransformProgram::rgb_to_a710();
+
+    let mut expected = vec![0; image.len() * 2];
+    let expected_size =
+        crate::compress_simple(&image, &header, &color_transform, &mut expected).unwrap();
+
+    let view = ImageView::new(&padded, 13, 7, 4, stride).unwrap();
+    let mut actual = vec![0; image.len() * 2];
+    let actual_size = crate::compress_view(&view, &header, &color_transform, &mut actual).unwrap();
+
+    assert_eq!(&actual[..actual_size], &expected[..expected_size]);
+}
+
+#[test]
+fn test_decompress_strided_view() {
+    let header = HeaderBuilder {
+        width: 13,
+        height: 7,
+        quality: 1024,
+        chroma_scale: 1,
+        block_size: 4,
+        filter: Filter::Linear,
+        intent: Intent::RGBA,
+        ..HeaderBuilder::test_default()
+    }
+    .build()
+    .unwrap();
+    let image = (0..header.get_image_size())
+        .map(|i| ((i * 29) ^ (i >> 3)) as u8)
+        .collect::<Vec<_>>();
+    let color_transform = ColorTransformProgram::rgb_to_yuv();
+    let mut compressed = vec![0; image.len() * 2];
+    let size = crate::compress_simple(&image, &header, &color_transform, &mut compressed).unwrap();
+    let data = &compressed[crate::header::HEADER_SIZE..size];
+
+    for downsampling in 0..2 {
+        let width = header.get_downsampled_width(downsampling);
+        let height = header.get_downsampled_height(downsampling);
+        let mut expected = vec![0; header.get_decompress_buffer_size(downsampling)];
+        crate::decompress_simple(data, &header, downsampling, false, &mut expected).unwrap();
+
+        let stride = width * 3 + 5;
+        let mut actual = vec![PADDING; stride * height];
+        let mut view = ImageViewMut::new(&mut actual, width, height, 3, stride).unwrap();
+        crate::decompress_view(data, &header, downsampling, false, &mut view).unwrap();
+
+        assert_eq!(actual, pad_rows(&expected, width * 3, stride));
+    }
+}
+
+#[test]
+fn test_view_shape_mismatch() {
+    let header = HeaderBuilder {
+        width: 8,
+        height: 8,
+        quality: 1024,
+        chroma_scale: 1,
+        block_size: 4,
+        filter: Filter::Linear,
+        intent: Intent::RGBA,
+        ..HeaderBuilder::test_default()
+    }
+    .build()
+    .unwrap();
+    let image = vec![0u8; 8 * 8 * 4];
+    let view = ImageView::new(&image, 8, 8, 4, 8 * 4).unwrap();
+    let mut buffer = vec![0; image.len() * 2];
+
+    match crate::compress_view(&view, &header, &ColorTransformProgram::new(), &mut buffer) {
+        Err(Error::ViewShape { expected, actual }) => {
+            assert_eq!(expected, (8, 8, 1, 3));
+            assert_eq!(actual, (8, 8, 1, 4));
+        }
+        r => panic!("view with wrong channels must be rejected: {:?}", r),
+    }
+}
+
+#[test]
+fn test_planes_round_trip() {
+    let header = HeaderBuilder {
+        width: 13,
+        height: 7,
+        quality: 1024,
+        chroma_scale: 1,
+        block_size: 4,
+        filter: Filter::Linear,
+        intent: Intent::RGBA,
+        ..HeaderBuilder::test_default()
+    }
+    .build()
+    .unwrap();
+    let image = (0..header.get_image_size())
+        .map(|i| ((i * 31) ^ (i >> 2)) as u8)
+        .collect::<Vec<_>>();
+    let color_transform = ColorTransformProgram::rgb_to_yuv();
+    let strides = [13, 16, 21];
+
+    let padded = strides
+        .iter()
+        .enumerate()
+        .map(|(channel, &stride)| {
+            let plane = image[channel..]
+                .iter()
+                .step_by(3)
+                .cloned()
+                .collect::<Vec<_>>();
+            pad_rows(&plane, 13, stride)
+        })
+        .collect::<Vec<_>>();
+    let planes = padded
+        .iter()
+        .zip(&strides)
+        .map(|(data, &stride)| Plane::new(data, 13, 7, stride).unwrap())
+        .collect::<Vec<_>>();
+
+    let mut expected = vec![0; image.len() * 2];
+    let expected_size =
+        crate::compress_simple(&image, &header, &color_transform, &mut expected).unwrap();
+    let mut actual = vec![0; image.len() * 2];
+    let actual_size =
+        crate::compress_planes(&planes, &header, &color_transform, &mut actual).unwrap();
+    assert_eq!(&actual[..actual_size], &expected[..expected_size]);
+
+    let data = &expected[crate::header::HEADER_SIZE..expected_size];
+    for downsampling in 0..2 {
+        let width = header.get_downsampled_width(downsampling);
+        let height = header.get_downsampled_height(downsampling);
+        let mut interleaved = vec![0; header.get_decompress_buffer_size(downsampling)];
+        crate::decompress_simple(data, &header, downsampling, false, &mut interleaved).unwrap();
+
+        let mut buffers = strides
+            .iter()
+            .map(|&stride| vec![PADDING; stride * height])
+            .collect::<Vec<_>>();
+        {
+            let mut planes = buffers
+                .iter_mut()
+                .zip(&strides)
+                .map(|(data, &stride)| PlaneMut::new(data, width, height, stride).unwrap())
+                .collect::<Vec<_>>();
+            crate::decompress_planes(data, &header, downsampling, false, &mut planes).unwrap();
+        }
+
+        for (channel, (buffer, &stride)) in buffers.iter().zip(&strides).enumerate() {
+            let plane = interleaved[channel..]
+                .iter()
+                .step_by(3)
+                .cloned()
+                .collect::<Vec<_>>();
+            assert_eq!(buffer, &pad_rows(&plane, width, stride));
+        }
+    }
+}
+
+#[test]
+fn test_plane_count_mismatch() {
+    let header = HeaderBuilder {
+        width: 8,
+        height: 8,
+        quality: 1024,
+        chroma_scale: 1,
+        block_size: 4,
+        filter: Filter::Linear,
+        intent: Intent::RGBA,
+        ..HeaderBuilder::test_default()
+    }
+    .build()
+    .unwrap();
+    let data = vec![0u8; 8 * 8];
+    let planes = vec![Plane::new(&data, 8, 8, 8).unwrap(); 2];
+    let mut buffer = vec![0; data.len() * 8];
+
+    match crate::compress_planes(&planes, &header, &ColorTransformProgram::new(), &mut buffer) {
+        Err(Error::PlaneCount {
+            expected: 3,
+            actual: 2,
+        }) => (),
+        r => panic!("missing plane must be rejected: {:?}", r),
+    }
+
+    let planes = vec![Plane::new(&data, 8, 4, 8).unwrap(); 3];
+    match crate::compress_planes(&planes, &header, &ColorTransformProgram::new(), &mut buffer) {
+        Err(Error::ViewShape { expected, actual }) => {
+            assert_eq!(expected, (8, 8, 1, 1));
+            assert_eq!(actual, (8, 4, 1, 1));
+        }
+        r => panic!("plane with wrong height must be rejected: {:?}", r),
+    }
+}
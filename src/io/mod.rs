@@ -0,0 +1,193 @@
+//! Byte streams the codec reads from and writes to. With the `std` feature `Read` and `Write`
+//! are implemented for every `std::io::Read` and `std::io::Write`, without it only for slices
+//! and vectors, so that the codec works under `no_std` with `alloc`.
+
+#[cfg(not(feature = "std"))]
+use alloc::vec::Vec;
+use core::fmt;
+
+use byteorder::{ByteOrder, LittleEndian};
+
+#[cfg(test)]
+mod test;
+
+/// Error of `Read` and `Write`, which replaces `std::io::Error` in the codec.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum IOError {
+    /// Input ended before all the requested bytes were read.
+    UnexpectedEof,
+    /// Output is full.
+    WriteZero,
+    /// Any other error of a `std::io::Read` or `std::io::Write`.
+    #[cfg(feature = "std")]
+    Other(std::io::Error),
+}
+
+impl fmt::Display for IOError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IOError::UnexpectedEof => write!(f, "Unexpected end of input"),
+            IOError::WriteZero => write!(f, "Output is full"),
+            #[cfg(feature = "std")]
+            IOError::Other(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl core::error::Error for IOError {
+    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+        match self {
+            #[cfg(feature = "std")]
+            IOError::Other(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<std::io::Error> for IOError {
+    fn from(err: std::io::Error) -> Self {
+        match err.kind() {
+            std::io::ErrorKind::UnexpectedEof => IOError::UnexpectedEof,
+            std::io::ErrorKind::WriteZero => IOError::WriteZero,
+            _ => IOError::Other(err),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<IOError> for std::io::Error {
+    fn from(err: IOError) -> Self {
+        match err {
+            IOError::UnexpectedEof => std::io::ErrorKind::UnexpectedEof.into(),
+            IOError::WriteZero => std::io::ErrorKind::WriteZero.into(),
+            IOError::Other(err) => err,
+        }
+    }
+}
+
+/// Source of bytes. Multi-byte values are little-endian, like everywhere in the stream.
+pub trait Read {
+    /// Reads exactly `buffer.len()` bytes.
+    fn read_exact(&mut self, buffer: &mut [u8]) -> Result<(), IOError>;
+
+    fn read_u8(&mut self) -> Result<u8, IOError> {
+        let mut buffer = [0; 1];
+        self.read_exact(&mut buffer)?;
+        Ok(buffer[0])
+    }
+
+    fn read_u16(&mut self) -> Result<u16, IOError> {
+        let mut buffer = [0; 2];
+        self.read_exact(&mut buffer)?;
+        Ok(LittleEndian::read_u16(&buffer))
+    }
+
+    fn read_u24(&mut self) -> Result<u32, IOError> {
+        let mut buffer = [0; 3];
+        self.read_exact(&mut buffer)?;
+        Ok(LittleEndian::read_u24(&buffer))
+    }
+
+    fn read_u32(&mut self) -> Result<u32, IOError> {
+        let mut buffer = [0; 4];
+        self.read_exact(&mut buffer)?;
+        Ok(LittleEndian::read_u32(&buffer))
+    }
+}
+
+/// Sink of bytes. Multi-byte values are little-endian, like everywhere in the stream.
+pub trait Write {
+    /// Writes the whole `buffer`.
+    fn write_all(&mut self, buffer: &[u8]) -> Result<(), IOError>;
+
+    fn write_u8(&mut self, value: u8) -> Result<(), IOError> {
+        self.write_all(&[value])
+    }
+
+    fn write_u16(&mut self, value: u16) -> Result<(), IOError> {
+        let mut buffer = [0; 2];
+        LittleEndian::write_u16(&mut buffer, value);
+        self.write_all(&buffer)
+    }
+
+    fn write_u24(&mut self, value: u32) -> Result<(), IOError> {
+        let mut buffer = [0; 3];
+        LittleEndian::write_u24(&mut buffer, value);
+        self.write_all(&buffer)
+    }
+
+    fn write_u32(&mut self, value: u32) -> Result<(), IOError> {
+        let mut buffer = [0; 4];
+        LittleEndian::write_u32(&mut buffer, value);
+        self.write_all(&buffer)
+    }
+}
+
+#[cfg(feature = "std")]
+impl<R: std::io::Read + ?Sized> Read for R {
+    fn read_exact(&mut self, buffer: &mut [u8]) -> Result<(), IOError> {
+        std::io::Read::read_exact(self, buffer).map_err(IOError::from)
+    }
+}
+
+#[cfg(feature = "std")]
+impl<W: std::io::Write + ?Sized> Write for W {
+    fn write_all(&mut self, buffer: &[u8]) -> Result<(), IOError> {
+        std::io::Write::write_all(self, buffer).map_err(IOError::from)
+    }
+}
+
+// the implementations below behave like the ones of `std::io` for the same types
+
+#[cfg(not(feature = "std"))]
+impl Read for &[u8] {
+    fn read_exact(&mut self, buffer: &mut [u8]) -> Result<(), IOError> {
+        if buffer.len() > self.len() {
+            *self = &self[self.len()..];
+            return Err(IOError::UnexpectedEof);
+        }
+        let (data, rest) = self.split_at(buffer.len());
+        buffer.copy_from_slice(data);
+        *self = rest;
+        Ok(())
+    }
+}
+
+#[cfg(not(feature = "std"))]
+impl<R: Read + ?Sized> Read for &mut R {
+    fn read_exact(&mut self, buffer: &mut [u8]) -> Result<(), IOError> {
+        (**self).read_exact(buffer)
+    }
+}
+
+#[cfg(not(feature = "std"))]
+impl Write for &mut [u8] {
+    fn write_all(&mut self, buffer: &[u8]) -> Result<(), IOError> {
+        let size = buffer.len().min(self.len());
+        let (output, rest) = core::mem::take(self).split_at_mut(size);
+        output.copy_from_slice(&buffer[..size]);
+        *self = rest;
+        if size < buffer.len() {
+            Err(IOError::WriteZero)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[cfg(not(feature = "std"))]
+impl Write for Vec<u8> {
+    fn write_all(&mut self, buffer: &[u8]) -> Result<(), IOError> {
+        self.extend_from_slice(buffer);
+        Ok(())
+    }
+}
+
+#[cfg(not(feature = "std"))]
+impl<W: Write + ?Sized> Write for &mut W {
+    fn write_all(&mut self, buffer: &[u8]) -> Result<(), IOError> {
+        (**self).write_all(buffer)
+    }
+}
@@ -0,0 +1,39 @@
+use super::*;
+
+#[test]
+fn test_little_endian_round_trip() {
+    let mut output = vec![];
+    output.write_u8(0x12).unwrap();
+    output.write_u16(0x3456).unwrap();
+    output.write_u24(0x78_9abc).unwrap();
+    output.write_u32(0xdef0_1234).unwrap();
+    assert_eq!(
+        output,
+        [0x12, 0x56, 0x34, 0xbc, 0x9a, 0x78, 0x34, 0x12, 0xf0, 0xde]
+    );
+
+    let mut input = &output[..];
+    assert_eq!(input.read_u8().unwrap(), 0x12);
+    assert_eq!(input.read_u16().unwrap(), 0x3456);
+    assert_eq!(input.read_u24().unwrap(), 0x78_9abc);
+    assert_eq!(input.read_u32().unwrap(), 0xdef0_1234);
+    assert!(input.is_empty());
+}
+
+#[test]
+fn test_slice_ends() {
+    let mut input: &[u8] = &[1, 2, 3];
+    match input.read_u32() {
+        Err(IOError::UnexpectedEof) => (),
+        result => panic!("unexpected result: {:?}", result),
+    }
+
+    let mut output = [0u8; 3];
+    let mut slice: &mut [u8] = &mut output;
+    slice.write_u16(0x0201).unwrap();
+    match slice.write_u16(0x0403) {
+        Err(IOError::WriteZero) => (),
+        result => panic!("unexpected result: {:?}", result),
+    }
+    assert_eq!(output, [1, 2, 3]);
+}
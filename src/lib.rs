@@ -1,72 +1,174 @@
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
 #![warn(rust_2018_idioms)]
 
+extern crate alloc;
+
 #[macro_use]
 extern crate num_derive;
 
+use alloc::vec;
+
+use num_traits::cast::NumCast;
+
+pub mod alpha;
+#[cfg(feature = "async")]
+pub mod async_io;
+#[cfg(feature = "capi")]
+pub mod capi;
+pub mod checksum;
 pub mod color_transform;
 pub mod compress;
 pub mod config;
+pub mod context;
 pub mod encode;
 pub mod errors;
+pub mod extension;
 pub mod header;
+pub mod image_view;
+pub mod io;
+pub mod palette;
 pub mod processing;
+pub mod thumbnail;
 
 // this 3 modules are public for criterion benchmarks
 pub mod bits;
 pub mod lifting;
 pub mod quant;
 
+pub use crate::alpha::{AlphaOptions, TransparentColor};
+#[cfg(feature = "async")]
+pub use crate::async_io::{AsyncDecoder, AsyncEncoder, DecodedLevel};
+pub use crate::checksum::{
+    compress_simple_with_checksums, decompress_simple_verified, verify, ChecksumLocation, Checksums,
+};
 pub use crate::color_transform::{
     interleaved_to_planar, planar_to_interleaved, ChannelTransform, ChannelTransformBuilder,
     ColorTransformProgram,
 };
 pub use crate::compress::{compress_aux_data, decompress_aux_data};
-pub use crate::errors::{CompressError, DecompressError};
+pub use crate::context::{Compressor, Decompressor};
+pub use crate::errors::{Error, HeaderErr};
+pub use crate::extension::Extension;
 pub use crate::header::{
-    Encoder, Filter, Header, HeaderBuilder, Intent, Quantization, BLOCK_DEFAULT, BLOCK_MAX,
-    QUALITY_MAX,
+    DecodeLimits, Encoder, Filter, Header, HeaderBuilder, Intent, LossyReason, Quantization,
+    Weighting, BLOCK_DEFAULT, BLOCK_MAX, QUALITY_MAX,
 };
+pub use crate::image_view::{ImageView, ImageViewMut, Plane, PlaneMut};
+pub use crate::io::IOError;
+pub use crate::quant::{DeadZone, QualityMap, QUALITY_SCALE_MAX, QUALITY_SCALE_ONE};
+pub use crate::thumbnail::{decode_thumbnail, thumbnail_downsampling, thumbnail_size, Thumbnail};
 
 pub fn compress_simple(
     image: &[u8],
     header: &Header,
     color_transform: &ColorTransformProgram,
-    mut buffer: &mut [u8],
-) -> Result<usize, CompressError> {
-    let original_len = buffer.len();
-    header.encode(&mut buffer)?;
-    let is_chroma = color_transform.encode(
-        header.channels as usize * header.layers as usize,
-        &mut buffer,
-    )?;
-    let service_len = original_len - buffer.len();
-
-    let mut aux_data = vec![0i16; header.get_image_size()];
-    color_transform.transform_and_to_planar(&image, &header, &mut aux_data);
-
-    Ok(service_len + compress_aux_data(&mut aux_data, &header, &is_chroma, &mut buffer)?)
+    buffer: &mut [u8],
+) -> Result<usize, Error> {
+    Compressor::new(header, color_transform)?.compress(image, buffer)
+}
+
+/// Same as `compress_simple`, but fails with `Error::Lossy` unless decompressing the stream
+/// gives back `image` exactly, see `Header::check_lossless`. With `verify` the stream is also
+/// decompressed and compared with `image`.
+pub fn compress_lossless(
+    image: &[u8],
+    header: &Header,
+    color_transform: &ColorTransformProgram,
+    verify: bool,
+    buffer: &mut [u8],
+) -> Result<usize, Error> {
+    header
+        .check_lossless(color_transform)
+        .map_err(Error::Lossy)?;
+    let size = compress_simple(image, header, color_transform, buffer)?;
+
+    if verify {
+        let mut decompressed = vec![0; header.get_decompress_buffer_size(0)];
+        decompress_simple(
+            &buffer[header::HEADER_SIZE..size],
+            header,
+            0,
+            false,
+            &mut decompressed,
+        )?;
+        if let Some(offset) = decompressed
+            .iter()
+            .zip(image)
+            .position(|(decompressed, sample)| decompressed != sample)
+        {
+            return Err(Error::LosslessMismatch { offset });
+        }
+    }
+
+    Ok(size)
+}
+
+/// Same as `compress_simple`, but reads the image through a possibly strided view, which must
+/// have the dimensions from `header`.
+pub fn compress_view<T>(
+    image: &ImageView<'_, T>,
+    header: &Header,
+    color_transform: &ColorTransformProgram,
+    buffer: &mut [u8],
+) -> Result<usize, Error>
+where
+    T: Into<i16> + Copy,
+{
+    Compressor::new(header, color_transform)?.compress_view(image, buffer)
+}
+
+/// Same as `compress_simple`, but reads every channel from its own plane. There must be a
+/// plane with the dimensions from `header` for every channel of every layer, channels of the
+/// second and further layers following the channels of the first one.
+pub fn compress_planes<T>(
+    planes: &[Plane<'_, T>],
+    header: &Header,
+    color_transform: &ColorTransformProgram,
+    buffer: &mut [u8],
+) -> Result<usize, Error>
+where
+    T: Into<i16> + Copy,
+{
+    Compressor::new(header, color_transform)?.compress_planes(planes, buffer)
 }
 
 pub fn decompress_simple(
-    mut data: &[u8],
+    data: &[u8],
+    header: &Header,
+    downsampling: usize,
+    test: bool,
+    buffer: &mut [u8],
+) -> Result<usize, Error> {
+    Decompressor::new().decompress(data, header, downsampling, test, buffer)
+}
+
+/// Same as `decompress_simple`, but writes the image through a possibly strided view, which
+/// must have the dimensions from `header` downsampled by `downsampling`.
+pub fn decompress_view<T>(
+    data: &[u8],
+    header: &Header,
+    downsampling: usize,
+    test: bool,
+    image: &mut ImageViewMut<'_, T>,
+) -> Result<usize, Error>
+where
+    T: NumCast,
+{
+    Decompressor::new().decompress_view(data, header, downsampling, test, image)
+}
+
+/// Same as `decompress_simple`, but writes every channel to its own plane. There must be a
+/// plane with the dimensions from `header` downsampled by `downsampling` for every channel of
+/// every layer.
+pub fn decompress_planes<T>(
+    data: &[u8],
     header: &Header,
     downsampling: usize,
     test: bool,
-    mut buffer: &mut [u8],
-) -> Result<usize, DecompressError> {
-    let mut is_chroma = vec![false; header.layers as usize * header.channels as usize];
-    let color_transform = ColorTransformProgram::decode(&mut data, &mut is_chroma)?;
-
-    let mut aux_data = vec![0i16; header.get_downsampled_image_size(downsampling)];
-    let next_point_of_interest =
-        decompress_aux_data(data, &header, &is_chroma, downsampling, test, &mut aux_data)?;
-
-    color_transform.detransform_and_to_interleaved(
-        &mut aux_data,
-        &header,
-        header.get_downsampled_channel_size(downsampling),
-        &mut buffer,
-    );
-
-    Ok(next_point_of_interest)
+    planes: &mut [PlaneMut<'_, T>],
+) -> Result<usize, Error>
+where
+    T: NumCast,
+{
+    Decompressor::new().decompress_planes(data, header, downsampling, test, planes)
 }
@@ -1,24 +1,36 @@
-use super::{get_hint_do_parallel, lift, unlift};
-use crate::config::Config;
+use super::{lift, unlift};
+use crate::config::{Config, MultithreadingFactors};
 use crate::processing::{
     process_maybe_parallel_for_each, DoubleOverlappingChunks, DoubleOverlappingChunksIterator,
 };
 
 #[inline(always)]
-pub fn lift_cubic(mut image: &mut [&mut [i16]]) {
+pub fn lift_cubic(image: &mut [&mut [i16]]) {
+    lift_cubic_with(image, Config::multithreading_factors());
+}
+
+#[inline(always)]
+pub(crate) fn lift_cubic_with(mut image: &mut [&mut [i16]], factors: &MultithreadingFactors) {
     lift(
         &mut image,
-        Config::multithreading_factors().cubic_horizontal_lifting,
+        factors.cubic_horizontal_lifting,
+        factors.cubic_vertical_lifting,
         horizontal_lift,
         vertical_lift,
     );
 }
 
 #[inline(always)]
-pub fn unlift_cubic(mut image: &mut [&mut [i16]]) {
+pub fn unlift_cubic(image: &mut [&mut [i16]]) {
+    unlift_cubic_with(image, Config::multithreading_factors());
+}
+
+#[inline(always)]
+pub(crate) fn unlift_cubic_with(mut image: &mut [&mut [i16]], factors: &MultithreadingFactors) {
     unlift(
         &mut image,
-        Config::multithreading_factors().cubic_horizontal_lifting,
+        factors.cubic_horizontal_lifting,
+        factors.cubic_vertical_lifting,
         horizontal_unlift,
         vertical_unlift,
     );
@@ -45,7 +57,8 @@ unsafe fn horizontal_lifting_base(
     if column.len() > 3 * step {
         while x < column.len() - 3 * step {
             let c3 = *column.get_unchecked(3 * step + x);
-            *column.get_unchecked_mut(x) += (cubic(c0, c1, c2, c3) / divider) as i16;
+            let value = column.get_unchecked_mut(x);
+            *value = value.wrapping_add((cubic(c0, c1, c2, c3) / divider) as i16);
             c0 = c1;
             c1 = c2;
             c2 = c3;
@@ -53,7 +66,8 @@ unsafe fn horizontal_lifting_base(
         }
     }
     while x < column.len() {
-        *column.get_unchecked_mut(x) += (cubic(c0, c1, c2, c2) / divider) as i16;
+        let value = column.get_unchecked_mut(x);
+        *value = value.wrapping_add((cubic(c0, c1, c2, c2) / divider) as i16);
         c0 = c1;
         c1 = c2;
         x += step * 2;
@@ -85,8 +99,8 @@ unsafe fn vertical_lifting_base(
             c2
         };
 
-        *chunks.middle.get_unchecked_mut(0).get_unchecked_mut(x) +=
-            (cubic(c0, c1, c2, c3) / divider) as i16;
+        let value = chunks.middle.get_unchecked_mut(0).get_unchecked_mut(x);
+        *value = value.wrapping_add((cubic(c0, c1, c2, c3) / divider) as i16);
         x += step;
     }
 }
@@ -104,9 +118,7 @@ unsafe fn horizontal_unlift(mut column: &mut [i16], step: usize) {
 }
 
 #[inline(always)]
-unsafe fn vertical_lift(mut image: &mut [&mut [i16]], step: usize) {
-    let config_factor = Config::multithreading_factors().cubic_vertical_lifting;
-    let hint_do_parallel = get_hint_do_parallel(&image, config_factor);
+unsafe fn vertical_lift(mut image: &mut [&mut [i16]], step: usize, hint_do_parallel: bool) {
     process_maybe_parallel_for_each(
         DoubleOverlappingChunksIterator::from_slice(&mut image, step),
         |mut chunks| {
@@ -124,9 +136,7 @@ unsafe fn vertical_lift(mut image: &mut [&mut [i16]], step: usize) {
 }
 
 #[inline(always)]
-unsafe fn vertical_unlift(mut image: &mut [&mut [i16]], step: usize) {
-    let config_factor = Config::multithreading_factors().cubic_vertical_lifting;
-    let hint_do_parallel = get_hint_do_parallel(&image, config_factor);
+unsafe fn vertical_unlift(mut image: &mut [&mut [i16]], step: usize, hint_do_parallel: bool) {
     process_maybe_parallel_for_each(
         DoubleOverlappingChunksIterator::from_slice(&mut image[step..], step),
         |mut chunks| {
@@ -144,7 +154,7 @@ unsafe fn vertical_unlift(mut image: &mut [&mut [i16]], step: usize) {
 }
 
 fn median(mut a: i32, mut b: i32, mut c: i32) -> i32 {
-    use std::mem;
+    use core::mem;
 
     if a > b {
         mem::swap(&mut a, &mut b);
@@ -1,22 +1,34 @@
-use super::{get_hint_do_parallel, lift, unlift};
-use crate::config::Config;
+use super::{lift, unlift};
+use crate::config::{Config, MultithreadingFactors};
 use crate::processing::{process_maybe_parallel_for_each, OverlappingChunksIterator};
 
 #[inline(always)]
-pub fn lift_linear(mut image: &mut [&mut [i16]]) {
+pub fn lift_linear(image: &mut [&mut [i16]]) {
+    lift_linear_with(image, Config::multithreading_factors());
+}
+
+#[inline(always)]
+pub(crate) fn lift_linear_with(mut image: &mut [&mut [i16]], factors: &MultithreadingFactors) {
     lift(
         &mut image,
-        Config::multithreading_factors().linear_horizontal_lifting,
+        factors.linear_horizontal_lifting,
+        factors.linear_vertical_lifting,
         horizontal_lift,
         vertical_lift,
     );
 }
 
 #[inline(always)]
-pub fn unlift_linear(mut image: &mut [&mut [i16]]) {
+pub fn unlift_linear(image: &mut [&mut [i16]]) {
+    unlift_linear_with(image, Config::multithreading_factors());
+}
+
+#[inline(always)]
+pub(crate) fn unlift_linear_with(mut image: &mut [&mut [i16]], factors: &MultithreadingFactors) {
     unlift(
         &mut image,
-        Config::multithreading_factors().linear_horizontal_lifting,
+        factors.linear_horizontal_lifting,
+        factors.linear_vertical_lifting,
         horizontal_unlift,
         vertical_unlift,
     );
@@ -31,15 +43,17 @@ unsafe fn horizontal_lifting_base(
 ) {
     let mut x = step * step_multiplier;
     while x < column.len() - step {
-        let a = *column.get_unchecked_mut(x - step);
-        let b = *column.get_unchecked_mut(x + step);
-        *column.get_unchecked_mut(x) += (a + b) / (divider * 2);
+        let a = i32::from(*column.get_unchecked_mut(x - step));
+        let b = i32::from(*column.get_unchecked_mut(x + step));
+        let value = column.get_unchecked_mut(x);
+        *value = value.wrapping_add(((a + b) / (i32::from(divider) * 2)) as i16);
         x += step * 2;
     }
 
     if x < column.len() {
-        *column.get_unchecked_mut(x) +=
-            (i32::from(*column.get_unchecked_mut(x - step)) / i32::from(divider)) as i16;
+        let a = i32::from(*column.get_unchecked_mut(x - step));
+        let value = column.get_unchecked_mut(x);
+        *value = value.wrapping_add((a / i32::from(divider)) as i16);
     }
 }
 
@@ -67,24 +81,22 @@ unsafe fn vertical_lifting_base(
 
     let mut x = 0;
     while x < middle_value.len() {
-        let c1 = *left.get_unchecked(0).get_unchecked(x);
+        let c1 = i32::from(*left.get_unchecked(0).get_unchecked(x));
         let c2 = if let Some(right_value) = right.first() {
-            *right_value.get_unchecked(x)
+            i32::from(*right_value.get_unchecked(x))
         } else {
             c1
         };
 
-        *middle_value.get_unchecked_mut(x) += (c1 + c2) / divider;
+        let value = middle_value.get_unchecked_mut(x);
+        *value = value.wrapping_add(((c1 + c2) / i32::from(divider)) as i16);
 
         x += step;
     }
 }
 
 #[inline(always)]
-unsafe fn vertical_lift(mut image: &mut [&mut [i16]], step: usize) {
-    let config_factor = Config::multithreading_factors().linear_vertical_lifting;
-    let hint_do_parallel = get_hint_do_parallel(&image, config_factor);
-
+unsafe fn vertical_lift(mut image: &mut [&mut [i16]], step: usize, hint_do_parallel: bool) {
     process_maybe_parallel_for_each(
         OverlappingChunksIterator::from_slice(&mut image, step),
         |(left, middle, right)| {
@@ -103,10 +115,7 @@ unsafe fn vertical_lift(mut image: &mut [&mut [i16]], step: usize) {
 }
 
 #[inline(always)]
-unsafe fn vertical_unlift(mut image: &mut [&mut [i16]], step: usize) {
-    let config_factor = Config::multithreading_factors().linear_vertical_lifting;
-    let hint_do_parallel = get_hint_do_parallel(&image, config_factor);
-
+unsafe fn vertical_unlift(mut image: &mut [&mut [i16]], step: usize, hint_do_parallel: bool) {
     process_maybe_parallel_for_each(
         OverlappingChunksIterator::from_slice(&mut image[step..], step),
         |(left, middle, right)| {
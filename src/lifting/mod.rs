@@ -5,18 +5,22 @@ mod linear;
 mod test;
 
 pub use self::cubic::{lift_cubic, unlift_cubic};
+pub(crate) use self::cubic::{lift_cubic_with, unlift_cubic_with};
 pub use self::linear::{lift_linear, unlift_linear};
+pub(crate) use self::linear::{lift_linear_with, unlift_linear_with};
 
 use crate::processing::process_maybe_parallel_for_each;
 
 fn lift(
     image: &mut [&mut [i16]],
-    config_factor: usize,
+    horizontal_factor: usize,
+    vertical_factor: usize,
     horizontal_lift: unsafe fn(&mut [i16], usize),
-    vertical_lift: unsafe fn(&mut [&mut [i16]], usize),
+    vertical_lift: unsafe fn(&mut [&mut [i16]], usize, bool),
 ) {
     let mut step = 1;
-    let hint_do_parallel = get_hint_do_parallel(&image, config_factor);
+    let hint_do_parallel = get_hint_do_parallel(&image, horizontal_factor);
+    let vertical_hint_do_parallel = get_hint_do_parallel(&image, vertical_factor);
 
     while step < image.len() || step < image[0].len() {
         if step < image[0].len() {
@@ -28,7 +32,7 @@ fn lift(
         }
 
         if step < image.len() {
-            unsafe { vertical_lift(image, step) };
+            unsafe { vertical_lift(image, step, vertical_hint_do_parallel) };
         }
 
         step *= 2;
@@ -37,11 +41,13 @@ fn lift(
 
 fn unlift(
     image: &mut [&mut [i16]],
-    config_factor: usize,
+    horizontal_factor: usize,
+    vertical_factor: usize,
     horizontal_unlift: unsafe fn(&mut [i16], usize),
-    vertical_unlift: unsafe fn(&mut [&mut [i16]], usize),
+    vertical_unlift: unsafe fn(&mut [&mut [i16]], usize, bool),
 ) {
-    let hint_do_parallel = get_hint_do_parallel(&image, config_factor);
+    let hint_do_parallel = get_hint_do_parallel(&image, horizontal_factor);
+    let vertical_hint_do_parallel = get_hint_do_parallel(&image, vertical_factor);
 
     let mut step = 1;
     while 2 * step < image.len() || 2 * step < image[0].len() {
@@ -50,7 +56,7 @@ fn unlift(
 
     while step > 0 {
         if step < image.len() {
-            unsafe { vertical_unlift(image, step) };
+            unsafe { vertical_unlift(image, step, vertical_hint_do_parallel) };
         }
 
         if step < image[0].len() {
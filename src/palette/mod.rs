@@ -0,0 +1,511 @@
+use alloc::vec::Vec;
+
+use byteorder::{ByteOrder, LittleEndian};
+
+use crate::bits::{BitsIOWriter, BitsReader, BitsWriter, SliceBitsReader};
+use crate::encode::{interleaved_code, interleaved_decode, unsigned_code, unsigned_decode};
+use crate::errors::Error;
+use crate::header::Header;
+use crate::io::{IOError, Write};
+
+#[cfg(test)]
+mod test;
+
+/// Width and height of the tiles `Encoder::Screen` chooses between palette and wavelet coding.
+pub const TILE_SIZE: usize = 16;
+/// Most colors of a palette tile.
+pub const MAX_COLORS: usize = 16;
+
+const VALUE_POT: u32 = 3;
+const MAX_RUN_POT: u32 = 8;
+const SKIP_POT: u32 = 2;
+const WAVELET_BITS_ONE: usize = 16;
+const WAVELET_BITS_ZERO: usize = 2;
+
+/// Working buffers of the palette section, kept between calls by `Compressor` and
+/// `Decompressor`.
+#[derive(Debug, Default)]
+pub(crate) struct Scratch {
+    /// Pixel of the first occurrence of every color of the tile.
+    colors: Vec<usize>,
+    /// Color of every pixel of the tile, in row order.
+    indices: Vec<u8>,
+    /// Channel values of every color of the tile.
+    palette: Vec<i16>,
+    /// Palette of the previous palette tile of the layer.
+    previous: Vec<i16>,
+    /// Encoded tiles.
+    tiles: Vec<u8>,
+}
+
+/// Counts the bits instead of writing them.
+#[derive(Default)]
+struct BitCounter(usize);
+
+impl BitsWriter for BitCounter {
+    fn put_bits(&mut self, _x: u32, bits: u32) -> Result<(), IOError> {
+        self.0 += bits as usize;
+        Ok(())
+    }
+
+    fn flush_write_word(&mut self) -> Result<(), IOError> {
+        Ok(())
+    }
+}
+
+/// Golomb-Rice parameters of the run lengths of both run kinds, adapted to the runs coded so
+/// far in the layer.
+#[derive(Clone, Copy, Default)]
+struct RunPots([u32; 2]);
+
+impl RunPots {
+    fn update(&mut self, kind: usize, x: u32) {
+        let pot = &mut self.0[kind];
+        if x >> *pot >= 2 {
+            *pot = (*pot + 1).min(MAX_RUN_POT);
+        } else if *pot > 0 && x < (1 << (*pot - 1)) {
+            *pot -= 1;
+        }
+    }
+}
+
+/// Number of tiles in a row and in a column of a layer.
+fn tile_grid(header: &Header) -> (usize, usize) {
+    (
+        (header.width as usize).div_ceil(TILE_SIZE),
+        (header.height as usize).div_ceil(TILE_SIZE),
+    )
+}
+
+/// Tile `tile` of a layer, counted row by row, as `(x, y, width, height)`.
+fn tile_rect(header: &Header, tile: usize) -> (usize, usize, usize, usize) {
+    let (columns, _) = tile_grid(header);
+    let x = tile % columns * TILE_SIZE;
+    let y = tile / columns * TILE_SIZE;
+    (
+        x,
+        y,
+        TILE_SIZE.min(header.width as usize - x),
+        TILE_SIZE.min(header.height as usize - y),
+    )
+}
+
+/// Bits of a color index that can take `values` values.
+fn index_bits(values: usize) -> u32 {
+    usize::BITS - values.saturating_sub(1).leading_zeros()
+}
+
+/// Codes the color indices of a tile `tile_width` pixels wide as runs. A run either copies the
+/// indices of the row above or repeats an index, which is coded without the index of the
+/// previous run if that one repeated an index too.
+fn encode_indices(
+    indices: &[u8],
+    tile_width: usize,
+    colors: usize,
+    pots: &mut RunPots,
+    writer: &mut impl BitsWriter,
+) -> Result<(), IOError> {
+    let mut previous_index = None;
+    let mut i = 0;
+    while i < indices.len() {
+        let index_run = indices[i..]
+            .iter()
+            .take_while(|&&x| x == indices[i])
+            .count();
+        let above_run = if i >= tile_width {
+            (i..indices.len())
+                .take_while(|&k| indices[k] == indices[k - tile_width])
+                .count()
+        } else {
+            0
+        };
+
+        let run = if i >= tile_width && above_run >= index_run {
+            writer.put_bits(1, 1)?;
+            previous_index = None;
+            above_run
+        } else {
+            if i >= tile_width {
+                writer.put_bits(0, 1)?;
+            }
+            let index = indices[i];
+            match previous_index {
+                Some(previous) => {
+                    let bits = index_bits(colors - 1);
+                    if bits > 0 {
+                        writer.put_bits(u32::from(index - u8::from(index > previous)), bits)?;
+                    }
+                }
+                None => writer.put_bits(u32::from(index), index_bits(colors))?,
+            }
+            previous_index = Some(index);
+            index_run
+        };
+
+        let kind = usize::from(previous_index.is_none());
+        unsigned_code(run as u32 - 1, writer, pots.0[kind])?;
+        pots.update(kind, run as u32 - 1);
+        i += run;
+    }
+    Ok(())
+}
+
+/// Decodes the color indices coded by `encode_indices` into `indices`, which must be empty.
+/// Returns `None` if they are malformed.
+fn decode_indices(
+    reader: &mut impl BitsReader,
+    pixels: usize,
+    tile_width: usize,
+    colors: usize,
+    pots: &mut RunPots,
+    indices: &mut Vec<u8>,
+) -> Option<()> {
+    let mut previous_index = None;
+    while indices.len() < pixels {
+        let i = indices.len();
+        let is_above = i >= tile_width && reader.get_bits(1).ok()? == 1;
+        if is_above {
+            previous_index = None;
+        } else {
+            let index = match previous_index {
+                Some(previous) => {
+                    let bits = index_bits(colors - 1);
+                    let index = if bits > 0 {
+                        reader.get_bits(bits).ok()? as u8
+                    } else {
+                        0
+                    };
+                    index + u8::from(index >= previous)
+                }
+                None => reader.get_bits(index_bits(colors)).ok()? as u8,
+            };
+            if usize::from(index) >= colors {
+                return None;
+            }
+            previous_index = Some(index);
+        }
+
+        let kind = usize::from(previous_index.is_none());
+        let x = unsigned_decode(reader, pots.0[kind]).ok()?;
+        pots.update(kind, x);
+        let run = (x as usize).saturating_add(1);
+        if run > pixels - i {
+            return None;
+        }
+        match previous_index {
+            Some(index) => indices.resize(i + run, index),
+            None => {
+                for k in i..i + run {
+                    indices.push(indices[k - tile_width]);
+                }
+            }
+        }
+    }
+    Some(())
+}
+
+/// Writes the palette section of `Encoder::Screen` streams for the transformed channels in
+/// `aux_data` to `buffer` and returns its size.
+///
+/// Tiles with 2 to `MAX_COLORS` colors over all channels of the layer are coded exactly with a
+/// palette and runs if that takes fewer bits than the wavelets would spend on them according to
+/// `wavelet_bits`, with `coefficients` the quantized coefficients of `aux_data`. Palette tiles
+/// are filled with their most frequent color, which the wavelets code almost for free and
+/// without ringing around sharp edges.
+///
+/// Layout: the size of the tile data in words, then for every layer its palette tiles in row
+/// order, each preceded by the number of tiles skipped since the previous one, and the number
+/// of tiles left after the last one if there are any. Palette tiles start with a bit telling
+/// whether they reuse the colors of the previous palette tile of the layer, otherwise the
+/// number of colors minus one in 4 bits followed by the channel values of every color, as the
+/// difference to the previous color. The color indices of the pixels follow, see
+/// `encode_indices`.
+pub(crate) fn encode_section(
+    aux_data: &mut [i16],
+    coefficients: &[i16],
+    header: &Header,
+    scratch: &mut Scratch,
+    buffer: &mut [u8],
+) -> Result<usize, Error> {
+    let width = header.width as usize;
+    let channels = usize::from(header.channels);
+    let channel_size = header.get_channel_size();
+
+    let Scratch {
+        colors,
+        indices,
+        palette,
+        previous,
+        tiles: encoded,
+    } = scratch;
+    encoded.clear();
+    let mut writer = BitsIOWriter::new(encoded);
+
+    for (layer, coefficients) in aux_data
+        .chunks_mut(channel_size * channels)
+        .zip(coefficients.chunks(channel_size * channels))
+    {
+        previous.clear();
+        let mut pots = RunPots::default();
+        let (columns, rows) = tile_grid(header);
+        let mut skipped = 0;
+        for tile in 0..columns * rows {
+            let (x0, y0, tile_width, tile_height) = tile_rect(header, tile);
+            let pixels = tile_width * tile_height;
+            let pixel = |i: usize| (y0 + i / tile_width) * width + x0 + i % tile_width;
+
+            colors.clear();
+            indices.clear();
+            {
+                let layer = &*layer;
+                let same = |a: usize, b: usize| {
+                    (0..channels)
+                        .all(|c| layer[c * channel_size + a] == layer[c * channel_size + b])
+                };
+                for i in 0..pixels {
+                    let index = match colors.iter().position(|&color| same(color, pixel(i))) {
+                        Some(index) => index,
+                        None if colors.len() < MAX_COLORS => {
+                            colors.push(pixel(i));
+                            colors.len() - 1
+                        }
+                        None => break,
+                    };
+                    indices.push(index as u8);
+                }
+                palette.clear();
+                palette.extend(colors.iter().flat_map(|&color| {
+                    (0..channels).map(move |c| layer[c * channel_size + color])
+                }));
+            }
+
+            // the colors of the previous palette are reused in its order
+            let is_reused = palette.len() == previous.len()
+                && palette
+                    .chunks(channels)
+                    .all(|color| previous.chunks(channels).any(|other| other == color));
+            if is_reused {
+                let mut order = [0; MAX_COLORS];
+                for (index, color) in palette.chunks(channels).enumerate() {
+                    order[index] = previous
+                        .chunks(channels)
+                        .position(|other| other == color)
+                        .unwrap_or(index) as u8;
+                }
+                for index in indices.iter_mut() {
+                    *index = order[usize::from(*index)];
+                }
+                palette.clone_from(previous);
+            }
+
+            let is_palette_tile = indices.len() == pixels && colors.len() >= 2 && {
+                let mut counter = BitCounter::default();
+                if !is_reused {
+                    encode_palette(palette, channels, &mut counter)?;
+                }
+                encode_indices(
+                    indices,
+                    tile_width,
+                    colors.len(),
+                    &mut pots.clone(),
+                    &mut counter,
+                )?;
+                let wavelet = (0..pixels)
+                    .flat_map(|i| (0..channels).map(move |c| c * channel_size + pixel(i)))
+                    .map(|i| wavelet_bits(coefficients[i]))
+                    .sum::<usize>();
+                // a palette tile is estimated at 3/2 of its coded bits, as the wavelets still
+                // spend about half as many again on the edges around the filled tile
+                3 * counter.0 * WAVELET_BITS_ONE < 2 * wavelet
+            };
+            if !is_palette_tile {
+                skipped += 1;
+                continue;
+            }
+
+            unsigned_code(skipped, &mut writer, SKIP_POT)?;
+            skipped = 0;
+            if is_reused {
+                writer.put_bits(1, 1)?;
+            } else {
+                writer.put_bits(0, 1)?;
+                encode_palette(palette, channels, &mut writer)?;
+                previous.clone_from(palette);
+            }
+            encode_indices(indices, tile_width, colors.len(), &mut pots, &mut writer)?;
+
+            let mut counts = [0; MAX_COLORS];
+            for &index in indices.iter() {
+                counts[usize::from(index)] += 1;
+            }
+            let fill = (0..colors.len()).max_by_key(|&i| counts[i]).unwrap_or(0);
+            for i in 0..pixels {
+                for c in 0..channels {
+                    layer[c * channel_size + pixel(i)] = palette[fill * channels + c];
+                }
+            }
+        }
+        if skipped > 0 {
+            unsigned_code(skipped, &mut writer, SKIP_POT)?;
+        }
+    }
+    writer.flush_write_word()?;
+
+    let mut output = buffer;
+    output.write_u32((encoded.len() / 4) as u32)?;
+    output.write_all(encoded)?;
+    Ok(4 + encoded.len())
+}
+
+/// Rough size of a quantized coefficient in `1 / WAVELET_BITS_ONE` bits. Zeros mostly go into
+/// runs and cost a fraction of a bit.
+fn wavelet_bits(coefficient: i16) -> usize {
+    let magnitude = coefficient.unsigned_abs();
+    if magnitude == 0 {
+        WAVELET_BITS_ZERO
+    } else {
+        (4 + 2 * (15 - magnitude.leading_zeros() as usize)) * WAVELET_BITS_ONE
+    }
+}
+
+fn encode_palette(
+    palette: &[i16],
+    channels: usize,
+    writer: &mut impl BitsWriter,
+) -> Result<(), IOError> {
+    writer.put_bits((palette.len() / channels) as u32 - 1, 4)?;
+    for (i, &value) in palette.iter().enumerate() {
+        let base = if i >= channels {
+            palette[i - channels]
+        } else {
+            0
+        };
+        interleaved_code(i32::from(value) - i32::from(base), writer, VALUE_POT)?;
+    }
+    Ok(())
+}
+
+/// Splits `data` into the tile data of the palette section at its start and the rest of the
+/// stream, or gives the size of the section if `data` ends before it.
+pub(crate) fn split_section(data: &[u8]) -> Result<(&[u8], &[u8]), usize> {
+    if data.len() < 4 {
+        return Err(4);
+    }
+    let size = (LittleEndian::read_u32(data) as usize)
+        .saturating_mul(4)
+        .saturating_add(4);
+    if data.len() < size {
+        return Err(size);
+    }
+    Ok((&data[4..size], &data[size..]))
+}
+
+/// Overwrites the palette tiles of the transformed channels in `aux_data`, downsampled by
+/// `downsampling`, with the pixels coded in `tiles`.
+pub(crate) fn decode_tiles(
+    tiles: &[u8],
+    aux_data: &mut [i16],
+    header: &Header,
+    downsampling: usize,
+    scratch: &mut Scratch,
+) -> Result<(), Error> {
+    let channel_size = header.get_downsampled_channel_size(downsampling);
+    let channels = usize::from(header.channels);
+    let mut reader = SliceBitsReader::new(tiles);
+
+    for (layer_index, layer) in aux_data
+        .chunks_mut(channel_size * channels)
+        .take(usize::from(header.layers))
+        .enumerate()
+    {
+        let malformed = |tile| Error::Palette {
+            layer: layer_index,
+            tile,
+        };
+        scratch.palette.clear();
+        let mut pots = RunPots::default();
+        let (columns, rows) = tile_grid(header);
+        let mut tile = 0;
+        while tile < columns * rows {
+            let skipped = unsigned_decode(&mut reader, SKIP_POT).map_err(|_| malformed(tile))?;
+            tile = tile.saturating_add(skipped as usize);
+            if tile > columns * rows {
+                return Err(malformed(columns * rows));
+            } else if tile == columns * rows {
+                break;
+            }
+            decode_tile(
+                &mut reader,
+                tile_rect(header, tile),
+                layer,
+                header,
+                downsampling,
+                &mut pots,
+                scratch,
+            )
+            .ok_or_else(|| malformed(tile))?;
+            tile += 1;
+        }
+    }
+    Ok(())
+}
+
+/// Decodes a palette tile and writes its pixels to `layer`. `scratch.palette` holds the
+/// palette of the previous palette tile of the layer. Returns `None` if the tile is malformed.
+fn decode_tile(
+    reader: &mut SliceBitsReader<'_>,
+    (x0, y0, tile_width, tile_height): (usize, usize, usize, usize),
+    layer: &mut [i16],
+    header: &Header,
+    downsampling: usize,
+    pots: &mut RunPots,
+    scratch: &mut Scratch,
+) -> Option<()> {
+    let channels = usize::from(header.channels);
+    let palette = &mut scratch.palette;
+    if reader.get_bits(1).ok()? == 0 {
+        let colors = reader.get_bits(4).ok()? as usize + 1;
+        if colors < 2 {
+            return None;
+        }
+        palette.clear();
+        for i in 0..colors * channels {
+            let base = if i >= channels {
+                palette[i - channels]
+            } else {
+                0
+            };
+            let value = i32::from(base).wrapping_add(interleaved_decode(reader, VALUE_POT).ok()?);
+            palette.push(value as i16);
+        }
+    } else if palette.is_empty() {
+        return None;
+    }
+
+    let pixels = tile_width * tile_height;
+    let indices = &mut scratch.indices;
+    indices.clear();
+    decode_indices(
+        reader,
+        pixels,
+        tile_width,
+        palette.len() / channels,
+        pots,
+        indices,
+    )?;
+
+    let width = header.get_downsampled_width(downsampling);
+    let channel_size = header.get_downsampled_channel_size(downsampling);
+    let mask = (1 << downsampling) - 1;
+    for (i, &index) in indices.iter().enumerate() {
+        let x = x0 + i % tile_width;
+        let y = y0 + i / tile_width;
+        if x & mask == 0 && y & mask == 0 {
+            let pixel = (y >> downsampling) * width + (x >> downsampling);
+            for c in 0..channels {
+                layer[c * channel_size + pixel] = palette[usize::from(index) * channels + c];
+            }
+        }
+    }
+    Some(())
+}
@@ -0,0 +1,188 @@
+use alloc::{vec, vec::Vec};
+
+use super::*;
+use crate::header::{Encoder, Filter, HeaderBuilder, Intent, QUALITY_MAX};
+
+/// Encodes the palette section as if the wavelets spent many bits on every tile.
+fn encode(
+    aux_data: &mut [i16],
+    header: &Header,
+    scratch: &mut Scratch,
+    buffer: &mut [u8],
+) -> usize {
+    let coefficients = vec![i16::MAX; aux_data.len()];
+    encode_section(aux_data, &coefficients, header, scratch, buffer).unwrap()
+}
+
+/// 40x20 planar image: text on the left and right, noise in the middle and a flat bottom row
+/// of tiles.
+fn screen_image(header: &Header) -> Vec<i16> {
+    let (width, height) = (header.width as usize, header.height as usize);
+    let mut state = 7u32;
+    let mut image = vec![0; header.get_image_size()];
+    for (plane, channel) in image.chunks_mut(width * height).zip((0..3).cycle()) {
+        for (i, value) in plane.iter_mut().enumerate() {
+            let (x, y) = (i % width, i / width);
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            *value = if y >= 16 {
+                50
+            } else if (16..32).contains(&x) {
+                (state % 512) as i16 - 256
+            } else if x % 7 == 3 || (y % 8 == 2 && x % 7 < 5) {
+                -100 + channel * 30
+            } else {
+                200 - channel
+            };
+        }
+    }
+    image
+}
+
+#[test]
+fn test_encode_decode_tiles() {
+    let header = HeaderBuilder {
+        width: 40,
+        height: 20,
+        layers: 2,
+        quality: QUALITY_MAX,
+        chroma_scale: 1,
+        filter: Filter::Linear,
+        encoder: Encoder::Screen,
+        intent: Intent::Generic,
+        ..HeaderBuilder::test_default()
+    }
+    .build()
+    .unwrap();
+    let original = screen_image(&header);
+    let (width, height) = (40, 20);
+    let is_palette_tile = |i: usize| {
+        let (x, y) = (i % (width * height) % width, i % (width * height) / width);
+        y < 16 && !(16..32).contains(&x)
+    };
+
+    let mut aux_data = original.clone();
+    let mut buffer = vec![0; 4096];
+    let mut scratch = Scratch::default();
+    let size = encode(&mut aux_data, &header, &mut scratch, &mut buffer);
+    let (tiles, rest) = split_section(&buffer[..size]).unwrap();
+    assert!(rest.is_empty());
+
+    // palette tiles are filled with their most frequent color, the rest is left as is
+    for (i, (&filled, &value)) in aux_data.iter().zip(&original).enumerate() {
+        if is_palette_tile(i) {
+            let channel = i / (width * height) % 3;
+            assert_eq!(filled, 200 - channel as i16, "sample {}", i);
+        } else {
+            assert_eq!(filled, value, "sample {}", i);
+        }
+    }
+
+    let mut decoded = vec![0; header.get_image_size()];
+    decode_tiles(tiles, &mut decoded, &header, 0, &mut scratch).unwrap();
+    for (i, (&decoded, &value)) in decoded.iter().zip(&original).enumerate() {
+        let expected = if is_palette_tile(i) { value } else { 0 };
+        assert_eq!(decoded, expected, "sample {}", i);
+    }
+
+    let mut downsampled = vec![0; header.get_downsampled_image_size(1)];
+    decode_tiles(tiles, &mut downsampled, &header, 1, &mut scratch).unwrap();
+    for (i, &decoded) in downsampled.iter().enumerate() {
+        let (plane, pixel) = (i / 200, i % 200);
+        let original_index = plane * width * height + (pixel / 20) * 2 * width + pixel % 20 * 2;
+        let expected = if is_palette_tile(original_index) {
+            original[original_index]
+        } else {
+            0
+        };
+        assert_eq!(decoded, expected, "sample {}", i);
+    }
+}
+
+#[test]
+fn test_reused_palette() {
+    let header = HeaderBuilder {
+        width: 32,
+        height: 16,
+        quality: QUALITY_MAX,
+        chroma_scale: 1,
+        filter: Filter::Linear,
+        encoder: Encoder::Screen,
+        intent: Intent::Generic,
+        ..HeaderBuilder::test_default()
+    }
+    .build()
+    .unwrap();
+    let section_size = |right_background: i16| {
+        let mut image = vec![0; header.get_image_size()];
+        for (i, value) in image.iter_mut().enumerate() {
+            let (x, y) = (i % 32, i / 32 % 16);
+            *value = if x % 7 == 3 || (y % 8 == 2 && x % 7 < 5) || x == 16 {
+                -100
+            } else if x < 16 {
+                200
+            } else {
+                right_background
+            };
+        }
+        let mut buffer = vec![0; 4096];
+        encode(&mut image, &header, &mut Scratch::default(), &mut buffer)
+    };
+
+    // the colors of the right tile appear in another order than in the left one
+    let reused = section_size(200);
+    let own_palette = section_size(201);
+    assert!(reused < own_palette, "{} vs {}", reused, own_palette);
+}
+
+#[test]
+fn test_split_section() {
+    assert_eq!(split_section(&[1, 0]), Err(4));
+    assert_eq!(split_section(&[2, 0, 0, 0, 1, 2, 3, 4]), Err(12));
+    assert_eq!(
+        split_section(&[1, 0, 0, 0, 1, 2, 3, 4, 5]),
+        Ok((&[1, 2, 3, 4][..], &[5][..]))
+    );
+}
+
+#[test]
+fn test_malformed_tiles() {
+    let header = HeaderBuilder {
+        width: 40,
+        height: 20,
+        quality: QUALITY_MAX,
+        chroma_scale: 1,
+        filter: Filter::Linear,
+        encoder: Encoder::Screen,
+        intent: Intent::Generic,
+        ..HeaderBuilder::test_default()
+    }
+    .build()
+    .unwrap();
+    let mut aux_data = screen_image(&header);
+    let mut buffer = vec![0; 4096];
+    let mut scratch = Scratch::default();
+    let size = encode(&mut aux_data, &header, &mut scratch, &mut buffer);
+    let (tiles, _) = split_section(&buffer[..size]).unwrap();
+
+    let mut decoded = vec![0; header.get_image_size()];
+    match decode_tiles(&tiles[..4], &mut decoded, &header, 0, &mut scratch) {
+        Err(Error::Palette { layer: 0, .. }) => (),
+        other => panic!("unexpected result: {:?}", other),
+    }
+
+    // more tiles are skipped than there are
+    match decode_tiles(&[0, 0, 0, 1], &mut decoded, &header, 0, &mut scratch) {
+        Err(Error::Palette { layer: 0, tile: 6 }) => (),
+        other => panic!("unexpected result: {:?}", other),
+    }
+
+    // the first tile reuses a palette that isn't there
+    let mut reusing = tiles.to_vec();
+    reusing[3] |= 0x10;
+    match decode_tiles(&reusing, &mut decoded, &header, 0, &mut scratch) {
+        Err(Error::Palette { layer: 0, tile: 0 }) => (),
+        other => panic!("unexpected result: {:?}", other),
+    }
+}
@@ -1,4 +1,4 @@
-use std::mem;
+use core::mem;
 
 pub struct DoubleOverlappingChunks<'a, T> {
     pub prev_left: &'a [T],
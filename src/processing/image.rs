@@ -1,4 +1,4 @@
-use std::{
+use core::{
     marker::PhantomData,
     ops::{Index, IndexMut},
     slice,
@@ -11,7 +11,13 @@ pub struct Image<'a, T> {
 }
 
 impl<'a, T> Image<'a, T> {
+    /// Panics if `data` is smaller than `size` and `channels` describe. Chunks rely on this
+    /// to access their samples without bounds checks.
     pub fn from_slice(data: &'a mut [T], size: (usize, usize), channels: usize) -> Image<'a, T> {
+        assert!(
+            data.len() >= size.0 * size.1 * channels,
+            "Image data is smaller than its dimensions"
+        );
         Image {
             data,
             size,
@@ -21,70 +27,86 @@ impl<'a, T> Image<'a, T> {
 
     pub fn into_chunks_mut(self, chunk_size: usize, step: usize) -> ImageChunkIteratorMut<'a, T> {
         let (width, height) = self.size;
+        // an empty dimension still makes one chunk per row or column of chunks
+        let chunks_x = width.div_ceil(chunk_size).max(1);
+        let chunks_y = height.div_ceil(chunk_size).max(1);
 
         ImageChunkIteratorMut {
-            data: self.data,
-            state: ImageChunkIteratorState {
-                chunk_size,
-                step,
-                x: 0,
-                y: 0,
-                channel: 0,
-                width,
-                height,
-                channels: self.channels,
-            },
+            data_ptr: self.data.as_mut_ptr(),
+            data_len: self.data.len(),
+            phantom_data: PhantomData,
+            chunk_size,
+            step,
+            width,
+            height,
+            chunks_x,
+            chunks_y,
+            index: 0,
+            end: chunks_x * chunks_y * self.channels,
         }
     }
 }
 
-struct ImageChunkIteratorState {
+type Range = (usize, usize);
+
+/// Iterates over chunks of every channel in row-major order. Chunks are computed from their
+/// index, so the iterator can be split for indexed parallel processing.
+pub struct ImageChunkIteratorMut<'a, T> {
+    data_ptr: *mut T,
+    data_len: usize,
+    phantom_data: PhantomData<&'a mut [T]>,
     chunk_size: usize,
     step: usize,
-    pub x: usize,
-    pub y: usize,
-    channel: usize,
     width: usize,
     height: usize,
-    channels: usize,
+    chunks_x: usize,
+    chunks_y: usize,
+    index: usize,
+    end: usize,
 }
 
-type Range = (usize, usize);
-
-impl ImageChunkIteratorState {
-    pub fn next_chunk(&mut self) -> Option<(Range, Range, usize, usize)> {
-        if self.channel >= self.channels {
-            return None;
-        }
-
-        let (x, y) = (self.x, self.y);
-        let channel = self.channel;
-
-        self.x += self.chunk_size;
-
-        if self.x >= self.width {
-            self.x = 0;
-            self.y += self.chunk_size;
-        }
-
-        if self.y >= self.height {
-            self.y = 0;
-            self.channel += 1;
+impl<'a, T> ImageChunkIteratorMut<'a, T> {
+    fn chunk(&self, index: usize) -> ImageChunkMut<'a, T> {
+        let channel = index / (self.chunks_x * self.chunks_y);
+        let index_in_channel = index % (self.chunks_x * self.chunks_y);
+        let x = index_in_channel % self.chunks_x * self.chunk_size;
+        let y = index_in_channel / self.chunks_x * self.chunk_size;
+        let x_range: Range = (x, (x + self.chunk_size).min(self.width));
+        let y_range: Range = (y, (y + self.chunk_size).min(self.height));
+
+        ImageChunkMut {
+            data_ptr: self.data_ptr,
+            data_len: self.data_len,
+            phantom_data: PhantomData,
+            image_width: self.width,
+            channel_size: self.width * self.height,
+            channel_start: channel * self.width * self.height,
+            channel,
+            x_range,
+            y_range,
+            step: self.step,
         }
+    }
 
-        Some((
-            (x, (x + self.chunk_size).min(self.width)),
-            (y, (y + self.chunk_size).min(self.height)),
-            channel,
-            channel * self.width * self.height,
-        ))
+    /// Splits into the first `index` remaining chunks and the rest.
+    pub fn split_at(self, index: usize) -> (Self, Self) {
+        assert!(index <= self.end - self.index);
+        let middle = self.index + index;
+        (
+            ImageChunkIteratorMut {
+                end: middle,
+                ..self
+            },
+            ImageChunkIteratorMut {
+                index: middle,
+                ..self
+            },
+        )
     }
 }
 
-pub struct ImageChunkIteratorMut<'a, T> {
-    data: &'a mut [T],
-    state: ImageChunkIteratorState,
-}
+// Chunks only write to their own samples, see `ImageChunkMut`.
+unsafe impl<T: Send> Send for ImageChunkIteratorMut<'_, T> {}
 
 pub struct ImageChunkMut<'a, T> {
     data_ptr: *mut T,
@@ -101,6 +123,9 @@ pub struct ImageChunkMut<'a, T> {
 }
 
 impl<T> ImageChunkMut<'_, T> {
+    /// # Safety
+    ///
+    /// `(y, x)` must lie inside the image the chunk was created from.
     pub unsafe fn get_unchecked(&self, y: usize, x: usize) -> &T {
         let index = self.channel_start + y * self.image_width + x;
 
@@ -117,6 +142,10 @@ impl<T> ImageChunkMut<'_, T> {
         &*self.data_ptr.add(index)
     }
 
+    /// # Safety
+    ///
+    /// `(y, x)` must lie inside the image the chunk was created from and must be owned and
+    /// writeable by this chunk.
     pub unsafe fn get_unchecked_mut(&mut self, y: usize, x: usize) -> &mut T {
         let index = self.channel_start + y * self.image_width + x;
 
@@ -206,19 +235,29 @@ impl<'a, T> Iterator for ImageChunkIteratorMut<'a, T> {
     type Item = ImageChunkMut<'a, T>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        self.state
-            .next_chunk()
-            .map(|(x_range, y_range, channel, channel_start)| ImageChunkMut {
-                data_ptr: self.data.as_mut_ptr(),
-                data_len: self.data.len(),
-                phantom_data: PhantomData,
-                image_width: self.state.width,
-                channel_size: self.state.width * self.state.height,
-                channel_start,
-                channel,
-                x_range,
-                y_range,
-                step: self.state.step,
-            })
+        if self.index < self.end {
+            self.index += 1;
+            Some(self.chunk(self.index - 1))
+        } else {
+            None
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = self.end - self.index;
+        (len, Some(len))
+    }
+}
+
+impl<T> DoubleEndedIterator for ImageChunkIteratorMut<'_, T> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.index < self.end {
+            self.end -= 1;
+            Some(self.chunk(self.end))
+        } else {
+            None
+        }
     }
 }
+
+impl<T> ExactSizeIterator for ImageChunkIteratorMut<'_, T> {}
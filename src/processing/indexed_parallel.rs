@@ -0,0 +1,107 @@
+use rayon::iter::plumbing::{bridge, Consumer, Producer, ProducerCallback, UnindexedConsumer};
+use rayon::iter::{IndexedParallelIterator, IntoParallelIterator, ParallelIterator};
+
+use super::image::ImageChunkIteratorMut;
+use super::VariableChunksIterator;
+
+/// Exact size iterator that can be split at an index, which is all rayon needs to process it
+/// as an `IndexedParallelIterator`.
+pub trait SplitIterator: DoubleEndedIterator + ExactSizeIterator + Sized {
+    fn split_at(self, index: usize) -> (Self, Self);
+}
+
+impl<T> SplitIterator for ImageChunkIteratorMut<'_, T> {
+    fn split_at(self, index: usize) -> (Self, Self) {
+        ImageChunkIteratorMut::split_at(self, index)
+    }
+}
+
+impl<T> SplitIterator for VariableChunksIterator<'_, '_, T> {
+    fn split_at(self, index: usize) -> (Self, Self) {
+        VariableChunksIterator::split_at(self, index)
+    }
+}
+
+/// Indexed parallel iterator over the items of a `SplitIterator`, in order.
+pub struct ParallelSplitIterator<I>(I);
+
+impl<I> ParallelIterator for ParallelSplitIterator<I>
+where
+    I: SplitIterator + Send,
+    I::Item: Send,
+{
+    type Item = I::Item;
+
+    fn drive_unindexed<C>(self, consumer: C) -> C::Result
+    where
+        C: UnindexedConsumer<Self::Item>,
+    {
+        bridge(self, consumer)
+    }
+
+    fn opt_len(&self) -> Option<usize> {
+        Some(self.0.len())
+    }
+}
+
+impl<I> IndexedParallelIterator for ParallelSplitIterator<I>
+where
+    I: SplitIterator + Send,
+    I::Item: Send,
+{
+    fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    fn drive<C>(self, consumer: C) -> C::Result
+    where
+        C: Consumer<Self::Item>,
+    {
+        bridge(self, consumer)
+    }
+
+    fn with_producer<CB>(self, callback: CB) -> CB::Output
+    where
+        CB: ProducerCallback<Self::Item>,
+    {
+        callback.callback(SplitProducer(self.0))
+    }
+}
+
+struct SplitProducer<I>(I);
+
+impl<I> Producer for SplitProducer<I>
+where
+    I: SplitIterator + Send,
+    I::Item: Send,
+{
+    type Item = I::Item;
+    type IntoIter = I;
+
+    fn into_iter(self) -> I {
+        self.0
+    }
+
+    fn split_at(self, index: usize) -> (Self, Self) {
+        let (left, right) = self.0.split_at(index);
+        (SplitProducer(left), SplitProducer(right))
+    }
+}
+
+impl<'a, T: Send> IntoParallelIterator for ImageChunkIteratorMut<'a, T> {
+    type Iter = ParallelSplitIterator<Self>;
+    type Item = <Self as Iterator>::Item;
+
+    fn into_par_iter(self) -> Self::Iter {
+        ParallelSplitIterator(self)
+    }
+}
+
+impl<'a, T: Sync> IntoParallelIterator for VariableChunksIterator<'a, '_, T> {
+    type Iter = ParallelSplitIterator<Self>;
+    type Item = &'a [T];
+
+    fn into_par_iter(self) -> Self::Iter {
+        ParallelSplitIterator(self)
+    }
+}
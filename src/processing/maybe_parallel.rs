@@ -1,3 +1,5 @@
+use alloc::vec::Vec;
+
 #[cfg(feature = "rayon")]
 use rayon::prelude::*;
 
@@ -62,3 +64,31 @@ where
 {
     items.map(action).collect()
 }
+
+/// Same as `process_maybe_parallel_for_each`, but splits `items` by index instead of pulling
+/// them through a shared iterator. Processed sequentially on the calling thread if the hint is
+/// not taken.
+#[cfg(feature = "rayon")]
+#[inline(always)]
+pub fn process_maybe_parallel_indexed_for_each<I, A>(items: I, action: A, hint_do_parallel: bool)
+where
+    I: IndexedParallelIterator,
+    A: Fn(I::Item) + Sync + Send,
+{
+    if cfg!(not(feature = "adaptive_multithreading")) || hint_do_parallel {
+        items.for_each(action);
+    } else {
+        // never split, so the only job runs on the calling thread
+        items.with_min_len(usize::MAX).for_each(action);
+    }
+}
+
+#[cfg(not(feature = "rayon"))]
+#[inline(always)]
+pub fn process_maybe_parallel_indexed_for_each<I, A>(items: I, action: A, _hint_do_parallel: bool)
+where
+    I: Iterator,
+    A: Fn(I::Item),
+{
+    items.for_each(action);
+}
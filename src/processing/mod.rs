@@ -1,4 +1,6 @@
 mod double_overlapping_chunks_iterator;
+#[cfg(feature = "rayon")]
+mod indexed_parallel;
 mod maybe_parallel;
 mod overlapping_chunks_iterator;
 mod variable_chunks_iterator;
@@ -11,8 +13,11 @@ mod test;
 pub use self::double_overlapping_chunks_iterator::{
     DoubleOverlappingChunks, DoubleOverlappingChunksIterator,
 };
+#[cfg(feature = "rayon")]
+pub use self::indexed_parallel::{ParallelSplitIterator, SplitIterator};
 pub use self::maybe_parallel::{
-    process_maybe_parallel_for_each, process_maybe_parallel_map_collect,
+    process_maybe_parallel_for_each, process_maybe_parallel_indexed_for_each,
+    process_maybe_parallel_map_collect,
 };
 pub use self::overlapping_chunks_iterator::OverlappingChunksIterator;
 pub use self::variable_chunks_iterator::VariableChunksIterator;
@@ -1,4 +1,4 @@
-use std::mem;
+use core::mem;
 
 pub struct OverlappingChunksIterator<'a, T> {
     head: &'a [T],
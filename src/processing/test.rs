@@ -594,6 +594,56 @@ mod image {
         assert_eq!(v, expected);
     }
 
+    fn chunk_ranges(chunks: ImageChunkIteratorMut<'_, u8>) -> Vec<(usize, Range, Range)> {
+        chunks
+            .map(|chunk| (chunk.channel, chunk.x_range, chunk.y_range))
+            .collect()
+    }
+
+    type Range = (usize, usize);
+
+    #[test]
+    fn test_split_at() {
+        let mut v = vec![0u8; 5 * 3 * 2];
+        let expected = chunk_ranges(Image::from_slice(&mut v, (5, 3), 2).into_chunks_mut(2, 1));
+        assert_eq!(expected.len(), 3 * 2 * 2);
+
+        for index in 0..=expected.len() {
+            let chunks = Image::from_slice(&mut v, (5, 3), 2).into_chunks_mut(2, 1);
+            let (left, right) = chunks.split_at(index);
+            assert_eq!((left.len(), right.len()), (index, expected.len() - index));
+
+            let mut actual = chunk_ranges(left);
+            actual.extend(chunk_ranges(right));
+            assert_eq!(actual, expected);
+        }
+
+        let mut reversed = Image::from_slice(&mut v, (5, 3), 2)
+            .into_chunks_mut(2, 1)
+            .rev()
+            .map(|chunk| (chunk.channel, chunk.x_range, chunk.y_range))
+            .collect::<Vec<_>>();
+        reversed.reverse();
+        assert_eq!(reversed, expected);
+    }
+
+    #[test]
+    #[cfg(feature = "rayon")]
+    fn test_indexed_parallel_order() {
+        use rayon::prelude::*;
+
+        let mut v = vec![0u8; 37 * 29];
+        let expected = chunk_ranges(Image::from_slice(&mut v, (37, 29), 1).into_chunks_mut(4, 2));
+        let actual = Image::from_slice(&mut v, (37, 29), 1)
+            .into_chunks_mut(4, 2)
+            .into_par_iter()
+            .with_max_len(1)
+            .map(|chunk| (chunk.channel, chunk.x_range, chunk.y_range))
+            .collect::<Vec<_>>();
+
+        assert_eq!(actual, expected);
+    }
+
     // If this function can be compiled - bad sings may happen.
     #[cfg(feature = "test_build_fails")]
     fn test_playground() {
@@ -780,4 +830,43 @@ mod variable_chunks_iterator {
         // [1, <2>], [3, 4, <5>], [6, 7, <8>], [<9>] => 2 + 5 + 8 + 9 = 24
         assert_eq!(result, 24)
     }
+
+    #[test]
+    fn test_split_at() {
+        let data = (1..=9).collect::<Vec<u8>>();
+        for chunk_sizes in &[
+            vec![2, 0, 3, 3, 42],
+            vec![4, 5, 0, 0],
+            vec![1, 10, 55],
+            vec![],
+        ] {
+            let expected = VariableChunksIterator::new(&data, chunk_sizes).collect::<Vec<_>>();
+
+            for index in 0..=expected.len() {
+                let (left, right) = VariableChunksIterator::new(&data, chunk_sizes).split_at(index);
+                assert_eq!((left.len(), right.len()), (index, expected.len() - index));
+
+                let mut actual = left.collect::<Vec<_>>();
+                actual.extend(right.rev().collect::<Vec<_>>().into_iter().rev());
+                assert_eq!(actual, expected);
+            }
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "rayon")]
+    fn test_indexed_parallel_order() {
+        use rayon::prelude::*;
+
+        let data = (0..=255).collect::<Vec<u8>>();
+        let chunk_sizes = (0..40).map(|i| i % 11).collect::<Vec<_>>();
+
+        let expected = VariableChunksIterator::new(&data, &chunk_sizes).collect::<Vec<_>>();
+        let actual = VariableChunksIterator::new(&data, &chunk_sizes)
+            .into_par_iter()
+            .with_max_len(1)
+            .collect::<Vec<_>>();
+
+        assert_eq!(actual, expected);
+    }
 }
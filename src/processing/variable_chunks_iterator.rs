@@ -1,44 +1,61 @@
-use std::mem;
-
+/// Splits a slice into chunks of the given sizes. Only chunks starting inside the slice are
+/// returned, the last of them is cut at the end of the slice.
 pub struct VariableChunksIterator<'a, 'b, T> {
     slice: &'a [T],
     chunk_sizes: &'b [usize],
+    /// Start of the last chunk in `slice`.
+    last_chunk_start: usize,
 }
 
 impl<'a, 'b, T> VariableChunksIterator<'a, 'b, T> {
     pub fn new(slice: &'a [T], chunk_sizes: &'b [usize]) -> VariableChunksIterator<'a, 'b, T> {
-        VariableChunksIterator { slice, chunk_sizes }
-    }
+        let mut chunk_start = 0;
+        let mut last_chunk_start = 0;
+        let chunk_count = chunk_sizes
+            .iter()
+            .take_while(|&&chunk_size| {
+                if chunk_start >= slice.len() {
+                    return false;
+                }
+                last_chunk_start = chunk_start;
+                chunk_start = chunk_start.saturating_add(chunk_size);
+                true
+            })
+            .count();
 
-    fn get_next_chunk_size(&mut self) -> Option<usize> {
-        if self.chunk_sizes.is_empty() {
-            return None;
+        VariableChunksIterator {
+            slice,
+            chunk_sizes: &chunk_sizes[..chunk_count],
+            last_chunk_start,
         }
-
-        let mut chunk_sizes: &[usize] = &[];
-        mem::swap(&mut self.chunk_sizes, &mut chunk_sizes);
-        let (next_chunk_size, chunk_sizes) = chunk_sizes.split_first().unwrap();
-        self.chunk_sizes = chunk_sizes;
-        Some(*next_chunk_size)
     }
 
-    fn get_next_chunk(&mut self, size: usize) -> Option<&'a [T]> {
-        if self.slice.is_empty() {
-            None
+    /// Splits into the first `index` remaining chunks and the rest.
+    pub fn split_at(self, index: usize) -> (Self, Self) {
+        let (left_sizes, right_sizes) = self.chunk_sizes.split_at(index);
+        let middle = left_sizes
+            .iter()
+            .fold(0usize, |sum, &chunk_size| sum.saturating_add(chunk_size));
+        let (left, right) = self.slice.split_at(middle.min(self.slice.len()));
+        let left_last_chunk_start = if right_sizes.is_empty() {
+            self.last_chunk_start
         } else {
-            let mut slice: &[T] = &[];
-            mem::swap(&mut self.slice, &mut slice);
-
-            // for last chunk - return remainder
-            if slice.len() <= size {
-                return Some(slice);
-            }
-
-            let (chunk, remainder) = slice.split_at(size);
-            self.slice = remainder;
+            // the right part starts inside the slice, so `middle` is exact
+            middle - left_sizes.last().unwrap_or(&0)
+        };
 
-            Some(chunk)
-        }
+        (
+            VariableChunksIterator {
+                slice: left,
+                chunk_sizes: left_sizes,
+                last_chunk_start: left_last_chunk_start,
+            },
+            VariableChunksIterator {
+                slice: right,
+                chunk_sizes: right_sizes,
+                last_chunk_start: self.last_chunk_start.saturating_sub(middle),
+            },
+        )
     }
 }
 
@@ -46,12 +63,33 @@ impl<'a, 'b, T> Iterator for VariableChunksIterator<'a, 'b, T> {
     type Item = &'a [T];
 
     fn next(&mut self) -> Option<Self::Item> {
-        let next_chunk_size = if let Some(value) = self.get_next_chunk_size() {
-            value
-        } else {
-            return None;
-        };
+        let (&chunk_size, chunk_sizes) = self.chunk_sizes.split_first()?;
+        let (chunk, slice) = self.slice.split_at(chunk_size.min(self.slice.len()));
 
-        self.get_next_chunk(next_chunk_size)
+        self.chunk_sizes = chunk_sizes;
+        self.slice = slice;
+        self.last_chunk_start = self.last_chunk_start.saturating_sub(chunk_size);
+
+        Some(chunk)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.chunk_sizes.len(), Some(self.chunk_sizes.len()))
+    }
+}
+
+impl<T> DoubleEndedIterator for VariableChunksIterator<'_, '_, T> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        let (&chunk_size, chunk_sizes) = self.chunk_sizes.split_last()?;
+        let (slice, rest) = self.slice.split_at(self.last_chunk_start);
+        let chunk = &rest[..chunk_size.min(rest.len())];
+
+        self.chunk_sizes = chunk_sizes;
+        self.slice = slice;
+        self.last_chunk_start -= chunk_sizes.last().unwrap_or(&0);
+
+        Some(chunk)
     }
 }
+
+impl<T> ExactSizeIterator for VariableChunksIterator<'_, '_, T> {}
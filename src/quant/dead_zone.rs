@@ -0,0 +1,73 @@
+/// Bits an isolated ±1 is assumed to cost over a zero, for the rate-distortion decision.
+const ISOLATED_ONE_BITS: i64 = 4;
+
+/// Encoder parameters of `Quantization::DeadZone`. Nonzero coefficients are truncated like with
+/// `Quantization::Scalar`, but reconstructed 3/8 of a step above the quantized value instead of
+/// in the middle, because wavelet coefficients are denser towards zero. The parameters only
+/// decide which coefficients become zero, so the decoder doesn't need them and they aren't
+/// stored in the stream.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DeadZone {
+    /// Coefficients below `threshold / 256` quantization steps become zero. 256 gives the zero
+    /// interval of `Quantization::Scalar`, larger values zero more noise at low bitrates.
+    pub threshold: u16,
+    /// Distortion in 1/256 of a squared step that zeroing an isolated ±1 may add per saved bit,
+    /// 0 turns the rate-distortion pass off. A coefficient is isolated if its left and right
+    /// neighbours in the level become zero.
+    pub lambda: u16,
+}
+
+impl Default for DeadZone {
+    fn default() -> Self {
+        DeadZone {
+            threshold: 288,
+            lambda: 96,
+        }
+    }
+}
+
+impl DeadZone {
+    /// Quantizes `x`, with its neighbours `left` and `right` before quantization.
+    #[inline]
+    pub(crate) fn quantize(&self, x: i16, left: i16, right: i16, q: i32, max_quality: i32) -> i16 {
+        let is_zero = |x: i16| {
+            (i64::from(x).abs() * i64::from(q)) << 8
+                < i64::from(self.threshold) * i64::from(max_quality)
+        };
+        if is_zero(x) {
+            return 0;
+        }
+
+        let magnitude = i64::from(x).abs() * i64::from(q) / i64::from(max_quality);
+        if magnitude == 1 && self.lambda > 0 && is_zero(left) && is_zero(right) {
+            // zeroing adds u^2 - (u - 11/8)^2 = 11u/4 - 121/64 squared steps for x = u steps
+            let steps = ((i64::from(x).abs() * i64::from(q)) << 8) / i64::from(max_quality);
+            let distortion = 11 * steps / 4 - 484;
+            if distortion < i64::from(self.lambda) * ISOLATED_ONE_BITS {
+                return 0;
+            }
+        }
+        let magnitude = magnitude.min(i64::from(i16::MAX)) as i16;
+        if x < 0 {
+            -magnitude
+        } else {
+            magnitude
+        }
+    }
+}
+
+/// Reconstructs a coefficient quantized by `DeadZone::quantize` 3/8 of a step above its value.
+#[inline]
+pub(crate) fn dequantize_value(x: i16, q: i32, max_quality: i32) -> i16 {
+    let magnitude = i64::from(x).abs();
+    if magnitude == 0 {
+        return 0;
+    }
+    let (q, max_quality) = (i64::from(q), i64::from(max_quality));
+    let value = ((8 * magnitude + 3) * max_quality + 4 * q) / (8 * q);
+    if x < 0 {
+        -value as i16
+    } else {
+        value as i16
+    }
+}
@@ -0,0 +1,96 @@
+use alloc::{vec, vec::Vec};
+
+/// Quality scale leaving the quality of a channel as is.
+pub const QUALITY_SCALE_ONE: u16 = 256;
+/// Largest quality scale, 16 times the quality of the channel.
+pub const QUALITY_SCALE_MAX: u16 = 16 * QUALITY_SCALE_ONE;
+
+/// Low-resolution map scaling the quality of all channels per region, e.g. to keep text sharp
+/// while photos are compressed harder. Every cell covers a square of `2^cell_shift` pixels and
+/// multiplies the quality of every wavelet level by `scale / QUALITY_SCALE_ONE`. Lifting keeps
+/// the coefficients in place, so a cell scales the coefficients of all levels that lie in it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct QualityMap {
+    pub cell_shift: u8,
+    /// Number of cells in a row, enough to cover the width of the image.
+    pub width: usize,
+    /// Number of cells in a column, enough to cover the height of the image.
+    pub height: usize,
+    /// Scales of the cells, row by row.
+    pub scales: Vec<u16>,
+}
+
+impl QualityMap {
+    /// Map of `image_width * image_height` images with every cell set to `scale`.
+    pub fn new(image_width: usize, image_height: usize, cell_shift: u8, scale: u16) -> Self {
+        let (width, height) = QualityMap::cells(image_width, image_height, cell_shift);
+        QualityMap {
+            cell_shift,
+            width,
+            height,
+            scales: vec![scale; width * height],
+        }
+    }
+
+    /// Number of cells `(width, height)` covering an `image_width * image_height` image.
+    pub fn cells(image_width: usize, image_height: usize, cell_shift: u8) -> (usize, usize) {
+        let cell_size = 1 << cell_shift;
+        (
+            image_width.div_ceil(cell_size),
+            image_height.div_ceil(cell_size),
+        )
+    }
+
+    /// Sets the scale of every cell overlapping the rectangle of `width * height` pixels at
+    /// `(x, y)`.
+    pub fn set_rect(&mut self, x: usize, y: usize, width: usize, height: usize, scale: u16) {
+        if width == 0 || height == 0 || self.scales.is_empty() {
+            return;
+        }
+        let shift = self.cell_shift;
+        let columns =
+            (x >> shift).min(self.width)..((x + width - 1) >> shift).min(self.width - 1) + 1;
+        for row in
+            (y >> shift).min(self.height)..((y + height - 1) >> shift).min(self.height - 1) + 1
+        {
+            for scale_cell in
+                &mut self.scales[row * self.width..(row + 1) * self.width][columns.clone()]
+            {
+                *scale_cell = scale;
+            }
+        }
+    }
+
+    /// Scale of the cell holding the pixel `(x, y)`.
+    pub fn get(&self, x: usize, y: usize) -> u16 {
+        let column = (x >> self.cell_shift).min(self.width - 1);
+        let row = (y >> self.cell_shift).min(self.height - 1);
+        self.scales[row * self.width + column]
+    }
+
+    /// Qualities of every cell for a wavelet level quantized with `quality`, for the three
+    /// orientations weighted by `weights`, from 1 to `max_quality`.
+    pub(crate) fn fill_qualities(
+        &self,
+        quality: i32,
+        max_quality: i32,
+        weights: [u16; 3],
+        qualities: &mut Vec<[i32; 3]>,
+    ) {
+        qualities.clear();
+        qualities.extend(self.scales.iter().map(|&scale| {
+            let quality = scale_quality(quality, scale, max_quality);
+            weigh(quality, max_quality, weights)
+        }));
+    }
+}
+
+/// `quality` scaled by `scale / QUALITY_SCALE_ONE`, from 1 to `max_quality`.
+fn scale_quality(quality: i32, scale: u16, max_quality: i32) -> i32 {
+    (quality * i32::from(scale) / i32::from(QUALITY_SCALE_ONE)).clamp(1, max_quality)
+}
+
+/// Qualities of the three orientations of a level quantized with `quality`.
+pub(crate) fn weigh(quality: i32, max_quality: i32, weights: [u16; 3]) -> [i32; 3] {
+    weights.map(|weight| scale_quality(quality, weight, max_quality))
+}
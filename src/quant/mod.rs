@@ -1,13 +1,35 @@
-use crate::config::Config;
+use alloc::vec::Vec;
+
+use crate::config::{Config, MultithreadingFactors};
 use crate::processing::process_maybe_parallel_for_each;
 
+mod dead_zone;
+mod map;
 #[cfg(test)]
 mod test;
 
-pub fn quantize(image: &mut [&mut [i16]], mut quality: i32, min_quality: i32, max_quality: i32) {
+pub use self::dead_zone::DeadZone;
+pub use self::map::{QualityMap, QUALITY_SCALE_MAX, QUALITY_SCALE_ONE};
+
+pub fn quantize(image: &mut [&mut [i16]], quality: i32, min_quality: i32, max_quality: i32) {
+    quantize_scalar(
+        image,
+        quality,
+        min_quality,
+        max_quality,
+        Config::multithreading_factors(),
+    );
+}
+
+fn quantize_scalar(
+    image: &mut [&mut [i16]],
+    mut quality: i32,
+    min_quality: i32,
+    max_quality: i32,
+    factors: &MultithreadingFactors,
+) {
     let mut skip = 1;
-    let hint_do_parallel =
-        image.len() * image[0].len() > Config::multithreading_factors().quantization;
+    let hint_do_parallel = image.len() * image[0].len() > factors.quantization;
 
     while skip < image.len() && skip < image[0].len() {
         let q = min_quality.max(1).max(quality);
@@ -21,7 +43,7 @@ pub fn quantize(image: &mut [&mut [i16]], mut quality: i32, min_quality: i32, ma
             |(y, column)| {
                 let x_step = if (y & skip) != 0 { skip } else { 2 * skip };
                 for x in column.iter_mut().skip(x_step - skip).step_by(x_step) {
-                    *x = (i32::from(*x) * q).wrapping_div(max_quality) as i16;
+                    *x = quantize_value(*x, q, max_quality);
                 }
             },
             hint_do_parallel,
@@ -32,10 +54,25 @@ pub fn quantize(image: &mut [&mut [i16]], mut quality: i32, min_quality: i32, ma
     }
 }
 
-pub fn dequantize(image: &mut [&mut [i16]], mut quality: i32, min_quality: i32, max_quality: i32) {
+pub fn dequantize(image: &mut [&mut [i16]], quality: i32, min_quality: i32, max_quality: i32) {
+    dequantize_scalar(
+        image,
+        quality,
+        min_quality,
+        max_quality,
+        Config::multithreading_factors(),
+    );
+}
+
+fn dequantize_scalar(
+    image: &mut [&mut [i16]],
+    mut quality: i32,
+    min_quality: i32,
+    max_quality: i32,
+    factors: &MultithreadingFactors,
+) {
     let mut skip = 1;
-    let hint_do_parallel =
-        image.len() * image[0].len() > Config::multithreading_factors().quantization;
+    let hint_do_parallel = image.len() * image[0].len() > factors.quantization;
 
     while skip < image.len() && skip < image[0].len() {
         let q = min_quality.max(1).max(quality);
@@ -49,19 +86,215 @@ pub fn dequantize(image: &mut [&mut [i16]], mut quality: i32, min_quality: i32,
             |(y, column)| {
                 let x_step = if (y & skip) != 0 { skip } else { 2 * skip };
                 for x in column.iter_mut().skip(x_step - skip).step_by(x_step) {
-                    *x = if *x < 0 {
-                        (i32::from(*x) * max_quality - (max_quality / 2)).wrapping_div(q) as i16
-                    } else if *x > 0 {
-                        (i32::from(*x) * max_quality + (max_quality / 2)).wrapping_div(q) as i16
-                    } else {
-                        (i32::from(*x) * max_quality).wrapping_div(q) as i16
+                    *x = dequantize_value(*x, q, max_quality);
+                }
+            },
+            hint_do_parallel,
+        );
+
+        skip *= 2;
+        quality = max_quality.min(2 * quality);
+    }
+}
+
+/// Rounding of the coefficients to quantized values and back, see `header::Quantization`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Quantizer {
+    /// Truncation towards zero, reconstruction in the middle of the interval.
+    Scalar,
+    DeadZone(DeadZone),
+}
+
+/// Quality weights of the levels, finest first, for `Weighting::Csf`, in units of
+/// `QUALITY_SCALE_ONE`. Every level has weights for the coefficients at odd columns, at odd rows
+/// and at both, the last being the diagonal details. Coarser levels use the last weights.
+pub const CSF_WEIGHTS: [[u16; 3]; 4] = [
+    [192, 192, 128],
+    [240, 240, 192],
+    [272, 272, 240],
+    [288, 288, 272],
+];
+
+/// Scales of the quality of the coefficients on top of doubling it per level.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Scales<'a> {
+    /// Scales of the regions of the image.
+    pub map: Option<&'a QualityMap>,
+    /// Weights of the levels and orientations, see `CSF_WEIGHTS`.
+    pub subbands: Option<&'a [[u16; 3]]>,
+}
+
+impl Scales<'_> {
+    pub fn is_none(&self) -> bool {
+        self.map.is_none() && self.subbands.is_none()
+    }
+}
+
+/// Same as `quantize` with a minimal quality of 0, rounding with `quantizer`, scaling the
+/// quality of every coefficient by `scales` and running in parallel according to `factors`.
+pub fn quantize_with(
+    image: &mut [&mut [i16]],
+    quality: i32,
+    max_quality: i32,
+    quantizer: Quantizer,
+    scales: Scales<'_>,
+    factors: &MultithreadingFactors,
+) {
+    match quantizer {
+        Quantizer::Scalar if scales.is_none() => {
+            quantize_scalar(image, quality, 0, max_quality, factors)
+        }
+        Quantizer::Scalar => for_each_level(
+            image,
+            quality,
+            max_quality,
+            scales,
+            0,
+            factors,
+            |x, _, _, q| quantize_value(x, q, max_quality),
+        ),
+        Quantizer::DeadZone(dead_zone) => for_each_level(
+            image,
+            quality,
+            max_quality,
+            scales,
+            0,
+            factors,
+            |x, left, right, q| dead_zone.quantize(x, left, right, q, max_quality),
+        ),
+    }
+}
+
+/// Same as `dequantize` with a minimal quality of 0, undoing `quantize_with` with the same
+/// quantizer and `scales`. The image covers the pixels of the image downsampled by
+/// `downsampling`.
+pub fn dequantize_with(
+    image: &mut [&mut [i16]],
+    quality: i32,
+    max_quality: i32,
+    quantizer: Quantizer,
+    scales: Scales<'_>,
+    downsampling: usize,
+    factors: &MultithreadingFactors,
+) {
+    match quantizer {
+        Quantizer::Scalar if scales.is_none() => {
+            dequantize_scalar(image, quality, 0, max_quality, factors)
+        }
+        Quantizer::Scalar => for_each_level(
+            image,
+            quality,
+            max_quality,
+            scales,
+            downsampling,
+            factors,
+            |x, _, _, q| dequantize_value(x, q, max_quality),
+        ),
+        Quantizer::DeadZone(_) => for_each_level(
+            image,
+            quality,
+            max_quality,
+            scales,
+            downsampling,
+            factors,
+            |x, _, _, q| dead_zone::dequantize_value(x, q, max_quality),
+        ),
+    }
+}
+
+/// Calls `func` with every coefficient of every level, its left and right neighbours in the
+/// level before quantization and the quality of the coefficient, and stores the result.
+fn for_each_level<F>(
+    image: &mut [&mut [i16]],
+    mut quality: i32,
+    max_quality: i32,
+    scales: Scales<'_>,
+    downsampling: usize,
+    factors: &MultithreadingFactors,
+    func: F,
+) where
+    F: Fn(i16, i16, i16, i32) -> i16 + Sync,
+{
+    if image.is_empty() {
+        return;
+    }
+    let mut skip = 1;
+    let mut level = downsampling;
+    let hint_do_parallel = image.len() * image[0].len() > factors.quantization;
+    let map = scales.map;
+    let mut map_qualities = Vec::with_capacity(map.map_or(0, |map| map.scales.len()));
+    let (width, height, shift) = map.map_or((1, 1, 0), |map| {
+        (map.width, map.height, usize::from(map.cell_shift))
+    });
+    // cell of the coefficient at `position` of the downsampled image
+    let cell = |position: usize, cells: usize| {
+        let pixel = position << downsampling;
+        (pixel >> shift).min(cells - 1)
+    };
+
+    while skip < image.len() && skip < image[0].len() {
+        let q = quality.max(1).min(max_quality);
+        let weights = scales.subbands.map_or([QUALITY_SCALE_ONE; 3], |subbands| {
+            subbands[level.min(subbands.len() - 1)]
+        });
+        let single;
+        let qualities: &[[i32; 3]] = match map {
+            Some(map) => {
+                map.fill_qualities(q, max_quality, weights, &mut map_qualities);
+                &map_qualities
+            }
+            None => {
+                single = [map::weigh(q, max_quality, weights)];
+                &single
+            }
+        };
+        if qualities.iter().flatten().all(|&q| q >= max_quality) {
+            break;
+        }
+
+        process_maybe_parallel_for_each(
+            image.iter_mut().enumerate().step_by(skip),
+            |(y, column)| {
+                let row = &qualities[cell(y, height) * width..][..width];
+                let odd_row = (y & skip) != 0;
+                let x_step = if odd_row { skip } else { 2 * skip };
+                let mut left = 0;
+                for x in (x_step - skip..column.len()).step_by(x_step) {
+                    let value = column[x];
+                    let orientation = match (x & skip != 0, odd_row) {
+                        (_, false) => 0,
+                        (false, true) => 1,
+                        (true, true) => 2,
+                    };
+                    let q = row[cell(x, width)][orientation];
+                    if q < max_quality {
+                        let right = column.get(x + x_step).copied().unwrap_or(0);
+                        column[x] = func(value, left, right, q);
                     }
+                    left = value;
                 }
             },
             hint_do_parallel,
         );
 
         skip *= 2;
+        level += 1;
         quality = max_quality.min(2 * quality);
     }
 }
+
+#[inline]
+fn quantize_value(x: i16, q: i32, max_quality: i32) -> i16 {
+    (i32::from(x) * q).wrapping_div(max_quality) as i16
+}
+
+#[inline]
+fn dequantize_value(x: i16, q: i32, max_quality: i32) -> i16 {
+    if x < 0 {
+        (i32::from(x) * max_quality - (max_quality / 2)).wrapping_div(q) as i16
+    } else if x > 0 {
+        (i32::from(x) * max_quality + (max_quality / 2)).wrapping_div(q) as i16
+    } else {
+        (i32::from(x) * max_quality).wrapping_div(q) as i16
+    }
+}
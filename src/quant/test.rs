@@ -45,3 +45,205 @@ fn test_dequantize() {
     }
     assert_eq!(image, expected);
 }
+
+fn test_image(width: usize, height: usize) -> Vec<i16> {
+    (0..width * height)
+        .map(|i| ((i * 7919) % 4001) as i16 - 2000)
+        .collect()
+}
+
+fn map_scales(map: &QualityMap) -> Scales<'_> {
+    Scales {
+        map: Some(map),
+        ..Scales::default()
+    }
+}
+
+#[test]
+fn test_quantize_with_neutral_map() {
+    let map = QualityMap::new(16, 12, 2, QUALITY_SCALE_ONE);
+    let mut expected = test_image(16, 12);
+    let mut image = expected.clone();
+    {
+        let mut expected: Vec<_> = expected.chunks_mut(16).collect();
+        quantize(&mut expected, 300, 0, 8192);
+        let mut image: Vec<_> = image.chunks_mut(16).collect();
+        quantize_with(
+            &mut image,
+            300,
+            8192,
+            Quantizer::Scalar,
+            map_scales(&map),
+            Config::multithreading_factors(),
+        );
+    }
+    assert_eq!(image, expected);
+
+    {
+        let mut expected: Vec<_> = expected.chunks_mut(16).collect();
+        dequantize(&mut expected, 300, 0, 8192);
+        let mut image: Vec<_> = image.chunks_mut(16).collect();
+        dequantize_with(
+            &mut image,
+            300,
+            8192,
+            Quantizer::Scalar,
+            map_scales(&map),
+            0,
+            Config::multithreading_factors(),
+        );
+    }
+    assert_eq!(image, expected);
+}
+
+#[test]
+fn test_quantize_with_map() {
+    // the left half is kept at the maximum quality, the right half is quantized 4 times harder
+    let mut map = QualityMap::new(16, 12, 3, QUALITY_SCALE_MAX);
+    map.set_rect(8, 0, 8, 12, QUALITY_SCALE_ONE / 4);
+    assert_eq!(map.get(7, 11), QUALITY_SCALE_MAX);
+    assert_eq!(map.get(8, 0), QUALITY_SCALE_ONE / 4);
+
+    let original = test_image(16, 12);
+    let mut image = original.clone();
+    {
+        let mut image: Vec<_> = image.chunks_mut(16).collect();
+        quantize_with(
+            &mut image,
+            512,
+            8192,
+            Quantizer::Scalar,
+            map_scales(&map),
+            Config::multithreading_factors(),
+        );
+        dequantize_with(
+            &mut image,
+            512,
+            8192,
+            Quantizer::Scalar,
+            map_scales(&map),
+            0,
+            Config::multithreading_factors(),
+        );
+    }
+
+    let error = |half: core::ops::Range<usize>| -> i32 {
+        original
+            .chunks(16)
+            .zip(image.chunks(16))
+            .flat_map(|(a, b)| a[half.clone()].iter().zip(&b[half.clone()]))
+            .map(|(&a, &b)| (i32::from(a) - i32::from(b)).abs())
+            .sum()
+    };
+    assert!(error(0..8) < error(8..16));
+}
+
+#[test]
+fn test_dead_zone() {
+    // steps of 16
+    let dead_zone = DeadZone::default();
+    let quantize = |x, left, right| dead_zone.quantize(x, left, right, 512, 8192);
+    assert_eq!(quantize(16, 0, 0), 0);
+    assert_eq!(quantize(44, 0, 0), 2);
+    assert_eq!(quantize(-60, 0, 0), -3);
+    // isolated ones are zeroed while the added distortion is small enough
+    assert_eq!(quantize(19, 0, 0), 0);
+    assert_eq!(quantize(19, 100, 0), 1);
+    assert_eq!(quantize(20, 0, 0), 1);
+    let without_rdo = DeadZone {
+        lambda: 0,
+        ..dead_zone
+    };
+    assert_eq!(without_rdo.quantize(19, 0, 0, 512, 8192), 1);
+
+    assert_eq!(dead_zone::dequantize_value(0, 512, 8192), 0);
+    assert_eq!(dead_zone::dequantize_value(1, 512, 8192), 22);
+    assert_eq!(dead_zone::dequantize_value(-3, 512, 8192), -54);
+}
+
+#[test]
+fn test_quantize_with_dead_zone() {
+    let original = test_image(16, 12);
+    let quantized = |quantizer| {
+        let mut image = original.clone();
+        {
+            let mut image: Vec<_> = image.chunks_mut(16).collect();
+            quantize_with(
+                &mut image,
+                64,
+                8192,
+                quantizer,
+                Scales::default(),
+                Config::multithreading_factors(),
+            );
+        }
+        image
+    };
+    let zeros = |image: &[i16]| image.iter().filter(|&&x| x == 0).count();
+
+    let scalar = quantized(Quantizer::Scalar);
+    let mut dead_zone = quantized(Quantizer::DeadZone(DeadZone::default()));
+    assert!(zeros(&dead_zone) > zeros(&scalar));
+
+    {
+        let mut image: Vec<_> = dead_zone.chunks_mut(16).collect();
+        dequantize_with(
+            &mut image,
+            64,
+            8192,
+            Quantizer::DeadZone(DeadZone::default()),
+            Scales::default(),
+            0,
+            Config::multithreading_factors(),
+        );
+    }
+    // the coarsest level isn't quantized
+    assert_eq!(dead_zone[0], original[0]);
+}
+
+#[test]
+fn test_quantize_with_csf_weights() {
+    let original = test_image(32, 32);
+    let mut image = original.clone();
+    let scales = Scales {
+        subbands: Some(&CSF_WEIGHTS),
+        ..Scales::default()
+    };
+    {
+        let mut image: Vec<_> = image.chunks_mut(32).collect();
+        quantize_with(
+            &mut image,
+            2048,
+            8192,
+            Quantizer::Scalar,
+            scales,
+            Config::multithreading_factors(),
+        );
+    }
+    // finest level, quality 2048 weighted by 3/4 for odd columns and 1/2 for diagonals
+    assert_eq!(image[1], quantize_value(original[1], 1536, 8192));
+    assert_eq!(image[32], quantize_value(original[32], 1536, 8192));
+    assert_eq!(image[33], quantize_value(original[33], 1024, 8192));
+    // next level, quality 4096 weighted by 15/16 and 3/4
+    assert_eq!(image[2], quantize_value(original[2], 3840, 8192));
+    assert_eq!(image[66], quantize_value(original[66], 3072, 8192));
+    // the coarser levels reach the maximum quality and aren't quantized
+    assert_eq!(image[4], original[4]);
+
+    {
+        let mut image: Vec<_> = image.chunks_mut(32).collect();
+        dequantize_with(
+            &mut image,
+            2048,
+            8192,
+            Quantizer::Scalar,
+            scales,
+            0,
+            Config::multithreading_factors(),
+        );
+    }
+    assert_eq!(
+        image[33],
+        dequantize_value(quantize_value(original[33], 1024, 8192), 1024, 8192)
+    );
+}
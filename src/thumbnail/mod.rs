@@ -0,0 +1,122 @@
+use alloc::{vec, vec::Vec};
+
+use byteorder::{ByteOrder, LittleEndian};
+
+use crate::checksum::{skip_color_transform, split_metadata};
+use crate::compress::step_levels;
+use crate::context::Decompressor;
+use crate::errors::Error;
+use crate::header::{Encoder, Header, HEADER_SIZE};
+use crate::io::IOError;
+use crate::palette;
+
+#[cfg(test)]
+mod test;
+
+/// Image decoded by `decode_thumbnail`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Thumbnail {
+    pub header: Header,
+    pub downsampling: usize,
+    pub width: u32,
+    pub height: u32,
+    /// Interleaved samples, laid out like `decompress_simple` writes them.
+    pub image: Vec<u8>,
+    /// Bytes of the stream, starting with the header, the image was decoded from.
+    pub size: usize,
+}
+
+/// Decodes the stream in `data`, starting with the header, downsampled just enough to fit
+/// into `max_width` x `max_height`, see `thumbnail_downsampling`. Only the levels that
+/// downsampling needs are read, so `data` may end after them, see `thumbnail_size`.
+pub fn decode_thumbnail(data: &[u8], max_width: u32, max_height: u32) -> Result<Thumbnail, Error> {
+    let header = Header::decode(&mut &data[..])?;
+    let downsampling = thumbnail_downsampling(&header, max_width, max_height)?;
+    let size = prefix_size(data, &header, downsampling)?;
+
+    let mut image = vec![0; header.get_decompress_buffer_size(downsampling)];
+    Decompressor::new().decompress(
+        &data[HEADER_SIZE..size],
+        &header,
+        downsampling,
+        false,
+        &mut image,
+    )?;
+
+    Ok(Thumbnail {
+        downsampling,
+        width: header.get_downsampled_width(downsampling) as u32,
+        height: header.get_downsampled_height(downsampling) as u32,
+        image,
+        size,
+        header,
+    })
+}
+
+/// Smallest downsampling that fits images described by `header` into `max_width` x
+/// `max_height`, or the largest one the stream has if none does.
+pub fn thumbnail_downsampling(
+    header: &Header,
+    max_width: u32,
+    max_height: u32,
+) -> Result<usize, Error> {
+    let top_step = step_levels(header)?.first().map_or(1, |&(step, _)| step);
+    let max_downsampling = top_step.trailing_zeros() as usize;
+    Ok((0..=max_downsampling)
+        .find(|&downsampling| {
+            header.get_downsampled_width(downsampling) <= max_width as usize
+                && header.get_downsampled_height(downsampling) <= max_height as usize
+        })
+        .unwrap_or(max_downsampling))
+}
+
+/// Size of the prefix of the stream in `data`, starting with the header, that decoding with
+/// `downsampling` reads. The block tables of the levels are read to find it, so if `data`
+/// ends in a level this fails with `Error::Truncated` giving the size known so far.
+pub fn thumbnail_size(data: &[u8], downsampling: usize) -> Result<usize, Error> {
+    let header = Header::decode(&mut &data[..])?;
+    prefix_size(data, &header, downsampling)
+}
+
+/// Same as `thumbnail_size`, with `header` decoded from the start of `data`.
+fn prefix_size(data: &[u8], header: &Header, downsampling: usize) -> Result<usize, Error> {
+    let (_, payload) = split_metadata(&data[HEADER_SIZE..], header)?;
+    let mut payload = skip_color_transform(payload, header)?;
+    if header.encoder == Encoder::Screen {
+        payload = palette::split_section(payload)
+            .map_err(|_| IOError::UnexpectedEof)?
+            .1;
+    }
+
+    for (step, block_count) in step_levels(header)? {
+        if step >> downsampling == 0 {
+            break;
+        }
+        let offset = data.len() - payload.len();
+        let check_size = |actual: usize, expected: usize| {
+            if actual < expected {
+                Err(Error::Truncated {
+                    step,
+                    expected: offset.saturating_add(expected),
+                    actual: offset + actual,
+                })
+            } else {
+                Ok(())
+            }
+        };
+
+        let block_table_size = block_count.saturating_mul(4);
+        check_size(payload.len(), block_table_size)?;
+        let level_size =
+            payload[..block_table_size]
+                .chunks(4)
+                .fold(block_table_size, |size, block_size| {
+                    size.saturating_add(
+                        (LittleEndian::read_u32(block_size) as usize).saturating_mul(4),
+                    )
+                });
+        check_size(payload.len(), level_size)?;
+        payload = &payload[level_size..];
+    }
+    Ok(data.len() - payload.len())
+}
@@ -0,0 +1,102 @@
+use alloc::{vec, vec::Vec};
+
+use super::*;
+use crate::color_transform::ColorTransformProgram;
+use crate::header::{Filter, HeaderBuilder};
+
+fn compress(header: &Header) -> Vec<u8> {
+    let image = (0..header.get_image_size())
+        .map(|i| ((i * 7) ^ (i >> 5)) as u8)
+        .collect::<Vec<_>>();
+    let mut buffer = vec![0; image.len() * 4];
+    let size = crate::compress_simple(
+        &image,
+        header,
+        &ColorTransformProgram::rgb_to_yuv(),
+        &mut buffer,
+    )
+    .unwrap();
+    buffer.truncate(size);
+    buffer
+}
+
+#[test]
+fn test_thumbnail_downsampling() {
+    let header = HeaderBuilder {
+        width: 100,
+        height: 60,
+        filter: Filter::Linear,
+        ..HeaderBuilder::test_default()
+    }
+    .build()
+    .unwrap();
+    assert_eq!(thumbnail_downsampling(&header, 100, 60).unwrap(), 0);
+    assert_eq!(thumbnail_downsampling(&header, 1000, 1000).unwrap(), 0);
+    assert_eq!(thumbnail_downsampling(&header, 99, 60).unwrap(), 1);
+    assert_eq!(thumbnail_downsampling(&header, 30, 30).unwrap(), 2);
+    assert_eq!(thumbnail_downsampling(&header, 2, 1).unwrap(), 6);
+    // the largest step of the stream is 64
+    assert_eq!(thumbnail_downsampling(&header, 0, 0).unwrap(), 6);
+}
+
+#[test]
+fn test_decode_thumbnail() {
+    for &encoder in &[Encoder::Contextual, Encoder::Screen] {
+        let header = HeaderBuilder {
+            width: 100,
+            height: 60,
+            filter: Filter::Linear,
+            encoder,
+            ..HeaderBuilder::test_default()
+        }
+        .build()
+        .unwrap();
+        let stream = compress(&header);
+
+        let thumbnail = decode_thumbnail(&stream, 30, 30).unwrap();
+        assert_eq!(thumbnail.downsampling, 2);
+        assert_eq!((thumbnail.width, thumbnail.height), (25, 15));
+        assert_eq!(thumbnail.header, header);
+        assert!(thumbnail.size < stream.len());
+        assert_eq!(thumbnail.size, thumbnail_size(&stream, 2).unwrap());
+
+        let mut expected = vec![0; header.get_decompress_buffer_size(2)];
+        crate::decompress_simple(&stream[HEADER_SIZE..], &header, 2, false, &mut expected).unwrap();
+        assert_eq!(thumbnail.image, expected);
+
+        // nothing after the prefix is read
+        let prefix = &stream[..thumbnail.size];
+        assert_eq!(decode_thumbnail(prefix, 30, 30).unwrap(), thumbnail);
+
+        let full = decode_thumbnail(&stream, 100, 60).unwrap();
+        assert_eq!(full.size, stream.len());
+        assert_eq!(full.image.len(), header.get_decompress_buffer_size(0));
+    }
+}
+
+#[test]
+fn test_truncated_thumbnail() {
+    let header = HeaderBuilder {
+        width: 100,
+        height: 60,
+        filter: Filter::Linear,
+        ..HeaderBuilder::test_default()
+    }
+    .build()
+    .unwrap();
+    let stream = compress(&header);
+    let size = thumbnail_size(&stream, 2).unwrap();
+
+    match decode_thumbnail(&stream[..size - 1], 30, 30) {
+        Err(Error::Truncated {
+            step: 4,
+            expected,
+            actual,
+        }) if expected == size && actual == size - 1 => (),
+        other => panic!("unexpected result: {:?}", other),
+    }
+    match thumbnail_size(&stream[..HEADER_SIZE + 2], 2) {
+        Err(Error::IOErr(IOError::UnexpectedEof)) => (),
+        other => panic!("unexpected result: {:?}", other),
+    }
+}
@@ -0,0 +1,92 @@
+// rayon may allocate internally when distributing work, so only the single threaded build is
+// expected to be allocation free
+#![cfg(not(feature = "rayon"))]
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+
+use gfwx::*;
+
+struct CountingAllocator;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = Cell::new(0);
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+fn count_allocations(action: impl FnOnce()) -> usize {
+    let before = ALLOCATIONS.with(Cell::get);
+    action();
+    ALLOCATIONS.with(Cell::get) - before
+}
+
+#[test]
+fn test_steady_state_does_not_allocate() {
+    let header = HeaderBuilder {
+        width: 67,
+        height: 45,
+        layers: 1,
+        channels: 3,
+        quality: 512,
+        chroma_scale: 8,
+        block_size: BLOCK_DEFAULT,
+        filter: Filter::Linear,
+        encoder: Encoder::Contextual,
+        intent: Intent::RGB,
+        metadata_size: 0,
+        quantization: Quantization::Scalar,
+        weighting: Weighting::Flat,
+    }
+    .build()
+    .unwrap();
+    let frames = (0..3)
+        .map(|seed| {
+            (0..header.get_image_size())
+                .map(|i| ((i * (seed + 5)) ^ (i >> 4)) as u8)
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+    let mut compressed = vec![0; header.get_image_size() * 2];
+    let mut decompressed = vec![0; header.get_decompress_buffer_size(0)];
+
+    let mut compressor = Compressor::new(&header, &ColorTransformProgram::rgb_to_yuv()).unwrap();
+    let mut decompressor = Decompressor::new();
+    let mut round_trip = |frame: &[u8]| {
+        let size = compressor.compress(frame, &mut compressed).unwrap();
+        decompressor
+            .decompress(
+                &compressed[gfwx::header::HEADER_SIZE..size],
+                &header,
+                0,
+                false,
+                &mut decompressed,
+            )
+            .unwrap();
+    };
+
+    // encoded block buffers grow to the largest block seen
+    for frame in &frames {
+        round_trip(frame);
+    }
+    for frame in &frames {
+        assert_eq!(count_allocations(|| round_trip(frame)), 0);
+    }
+}
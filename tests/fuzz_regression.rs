@@ -0,0 +1,60 @@
+use std::{fs, path::PathBuf};
+
+use gfwx::{decompress_simple, ColorTransformProgram, Header};
+
+const MAX_IMAGE_SIZE: usize = 1 << 20;
+
+fn corpus(target: &str) -> Vec<(PathBuf, Vec<u8>)> {
+    let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("fuzz_corpus")
+        .join(target);
+    let mut inputs: Vec<_> = fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| {
+            let path = entry.unwrap().path();
+            let data = fs::read(&path).unwrap();
+            (path, data)
+        })
+        .collect();
+    inputs.sort();
+    assert!(!inputs.is_empty(), "empty corpus: {}", dir.display());
+    inputs
+}
+
+#[test]
+fn test_header_decode_corpus() {
+    for (_, data) in corpus("header_decode") {
+        let _ = Header::decode(&mut data.as_slice());
+    }
+}
+
+#[test]
+fn test_color_transform_decode_corpus() {
+    for (_, data) in corpus("color_transform_decode") {
+        let mut is_chroma = [false; 4];
+        let _ = ColorTransformProgram::decode(&mut data.as_slice(), &mut is_chroma);
+    }
+}
+
+#[test]
+fn test_decompress_simple_corpus() {
+    for (path, data) in corpus("decompress_simple") {
+        let mut data = data.as_slice();
+        let header = match Header::decode(&mut data) {
+            Ok(header) => header,
+            Err(_) => continue,
+        };
+        assert!(
+            header.get_image_size() <= MAX_IMAGE_SIZE,
+            "{} is too large for the regression corpus",
+            path.display()
+        );
+
+        for downsampling in 0..3 {
+            let mut buffer = vec![0; header.get_decompress_buffer_size(downsampling)];
+            let _ = decompress_simple(data, &header, downsampling, false, &mut buffer);
+            let _ = decompress_simple(data, &header, downsampling, true, &mut buffer);
+        }
+    }
+}
@@ -14,6 +14,8 @@ fn test_compress_simple() {
         encoder: Encoder::Contextual,
         intent: Intent::Generic,
         metadata_size: 0,
+        quantization: Quantization::Scalar,
+        weighting: Weighting::Flat,
     };
     let header = builder.build().unwrap();
 
@@ -76,6 +78,8 @@ fn test_decompress_simple() {
         encoder: Encoder::Contextual,
         intent: Intent::Generic,
         metadata_size: 0,
+        quantization: Quantization::Scalar,
+        weighting: Weighting::Flat,
     };
     let header = builder.build().unwrap();
 
@@ -114,3 +118,291 @@ fn test_decompress_simple() {
     assert_eq!(expected.len(), actual.len());
     assert_eq!(expected, actual);
 }
+
+fn decompress_stream(mut data: &[u8], buffer: &mut [u8]) -> Result<Header, Error> {
+    let header = Header::decode(&mut data)?;
+    decompress_simple(data, &header, 0, false, buffer)?;
+    Ok(header)
+}
+
+#[test]
+fn test_error_propagation() {
+    let builder = HeaderBuilder {
+        width: 12,
+        height: 8,
+        layers: 1,
+        channels: 3,
+        quality: 124,
+        chroma_scale: 8,
+        block_size: BLOCK_DEFAULT,
+        filter: Filter::Linear,
+        encoder: Encoder::Contextual,
+        intent: Intent::Generic,
+        metadata_size: 0,
+        quantization: Quantization::Scalar,
+        weighting: Weighting::Flat,
+    };
+    let header = builder.build().unwrap();
+    let image = vec![0u8; header.get_image_size()];
+    let mut compressed = vec![0u8; image.len() * 4];
+
+    match compress_simple(
+        &image[1..],
+        &header,
+        &ColorTransformProgram::new(),
+        &mut compressed,
+    ) {
+        Err(Error::BufferSize { expected, actual }) => {
+            assert_eq!(expected, image.len());
+            assert_eq!(actual, image.len() - 1);
+        }
+        other => panic!("unexpected result: {:?}", other),
+    }
+
+    let gfwx_size = compress_simple(
+        &image,
+        &header,
+        &ColorTransformProgram::new(),
+        &mut compressed,
+    )
+    .unwrap();
+    compressed.truncate(gfwx_size);
+
+    let mut decompressed = vec![0u8; image.len() - 1];
+    match decompress_stream(&compressed, &mut decompressed) {
+        Err(Error::BufferSize { expected, .. }) => assert_eq!(expected, image.len()),
+        other => panic!("unexpected result: {:?}", other),
+    }
+
+    compressed[0] = 0;
+    match decompress_stream(&compressed, &mut decompressed) {
+        Err(Error::Header(HeaderErr::WrongMagic)) => (),
+        other => panic!("unexpected result: {:?}", other),
+    }
+}
+
+#[test]
+fn test_compress_lossless() {
+    // large factors make the transformed values wrap around
+    let mut wrapping = ColorTransformProgram::new();
+    wrapping
+        .add_channel_transform(
+            ChannelTransformBuilder::with_dest_channel(0)
+                .add_channel_factor(1, 300)
+                .add_channel_factor(2, -77)
+                .set_denominator(3)
+                .build(),
+        )
+        .add_channel_transform(
+            ChannelTransformBuilder::with_dest_channel(2)
+                .add_channel_factor(0, 1000)
+                .set_chroma()
+                .build(),
+        );
+    let programs = [
+        ColorTransformProgram::new(),
+        ColorTransformProgram::rgb_to_yuv(),
+        ColorTransformProgram::bgr_to_a710(),
+        wrapping,
+    ];
+
+    let mut state = 7u32;
+    for &filter in &[Filter::Linear, Filter::Cubic] {
+        for &encoder in &[
+            Encoder::Turbo,
+            Encoder::Fast,
+            Encoder::Contextual,
+            Encoder::Screen,
+        ] {
+            let header = HeaderBuilder {
+                width: 37,
+                height: 23,
+                layers: 1,
+                channels: 3,
+                quality: QUALITY_MAX,
+                chroma_scale: 1,
+                block_size: 3,
+                filter,
+                encoder,
+                intent: Intent::RGB,
+                metadata_size: 0,
+                quantization: Quantization::Scalar,
+                weighting: Weighting::Flat,
+            }
+            .build()
+            .unwrap();
+            let image = (0..header.get_image_size())
+                .map(|_| {
+                    state ^= state << 13;
+                    state ^= state >> 17;
+                    state ^= state << 5;
+                    state as u8
+                })
+                .collect::<Vec<_>>();
+
+            for program in programs.iter() {
+                // noise with wrapped around values may take more than 4 bytes per sample
+                let mut buffer = vec![0u8; image.len() * 8];
+                compress_lossless(&image, &header, program, true, &mut buffer).unwrap();
+            }
+        }
+    }
+}
+
+#[test]
+fn test_compress_lossless_rejects_lossy() {
+    let header = HeaderBuilder {
+        width: 12,
+        height: 8,
+        layers: 1,
+        channels: 3,
+        quality: QUALITY_MAX,
+        chroma_scale: 8,
+        block_size: BLOCK_DEFAULT,
+        filter: Filter::Linear,
+        encoder: Encoder::Contextual,
+        intent: Intent::Generic,
+        metadata_size: 0,
+        quantization: Quantization::Scalar,
+        weighting: Weighting::Flat,
+    }
+    .build()
+    .unwrap();
+    let image = vec![0u8; header.get_image_size()];
+    let mut buffer = vec![0u8; image.len() * 4];
+
+    match compress_lossless(
+        &image,
+        &header,
+        &ColorTransformProgram::rgb_to_yuv(),
+        false,
+        &mut buffer,
+    ) {
+        Err(Error::Lossy(LossyReason::ChromaScale)) => (),
+        other => panic!("unexpected result: {:?}", other),
+    }
+}
+
+/// 256x192 crop of `ci/test_images/screenshot4.png`.
+fn screenshot_crop() -> image::RgbaImage {
+    let mut screenshot = image::open("ci/test_images/screenshot4.png")
+        .unwrap()
+        .to_rgba();
+    image::imageops::crop(&mut screenshot, 400, 300, 256, 192).to_image()
+}
+
+/// Lossless size of `crop` with the channels in `channel_order`.
+fn compress_lossless_size(
+    crop: &image::RgbaImage,
+    channel_order: &[usize],
+    color_transform: &ColorTransformProgram,
+    encoder: Encoder,
+) -> usize {
+    let (width, height) = crop.dimensions();
+    let image = crop
+        .pixels()
+        .flat_map(|pixel| channel_order.iter().map(move |&c| pixel.data[c]))
+        .collect::<Vec<_>>();
+
+    let header = HeaderBuilder {
+        width,
+        height,
+        layers: 1,
+        channels: channel_order.len() as u16,
+        quality: QUALITY_MAX,
+        chroma_scale: 1,
+        block_size: BLOCK_DEFAULT,
+        filter: Filter::Linear,
+        encoder,
+        intent: Intent::Generic,
+        metadata_size: 0,
+        quantization: Quantization::Scalar,
+        weighting: Weighting::Flat,
+    }
+    .build()
+    .unwrap();
+    let mut buffer = vec![0u8; image.len() * 4];
+    compress_lossless(&image, &header, color_transform, true, &mut buffer).unwrap()
+}
+
+#[test]
+fn test_ycocg_r_compression_ratio() {
+    let crop = screenshot_crop();
+    let size = |channel_order: &[usize], color_transform| {
+        compress_lossless_size(&crop, channel_order, &color_transform, Encoder::Contextual)
+    };
+    let rgb = [0, 1, 2];
+    let none = size(&rgb, ColorTransformProgram::new());
+    let yuv = size(&rgb, ColorTransformProgram::rgb_to_yuv());
+    let ycocg_r = size(&rgb, ColorTransformProgram::rgb_to_ycocg_r());
+
+    assert!(
+        ycocg_r * 10 < none * 9,
+        "{} vs {} without transform",
+        ycocg_r,
+        none
+    );
+    assert!(
+        ycocg_r * 100 < yuv * 106,
+        "{} vs {} with rgb_to_yuv",
+        ycocg_r,
+        yuv
+    );
+
+    assert_eq!(
+        size(&[2, 1, 0], ColorTransformProgram::bgr_to_ycocg_r()),
+        ycocg_r
+    );
+    let rgba = size(&[0, 1, 2, 3], ColorTransformProgram::rgba_to_ycocg_r());
+    let bgra = size(&[2, 1, 0, 3], ColorTransformProgram::bgra_to_ycocg_r());
+    assert_eq!(rgba, bgra);
+    assert!(rgba > ycocg_r);
+}
+
+#[test]
+fn test_screen_encoder() {
+    let crop = screenshot_crop();
+    let rgb = [0, 1, 2];
+    let program = ColorTransformProgram::rgb_to_ycocg_r();
+    let contextual = compress_lossless_size(&crop, &rgb, &program, Encoder::Contextual);
+    let screen = compress_lossless_size(&crop, &rgb, &program, Encoder::Screen);
+    assert!(screen < contextual, "{} vs {}", screen, contextual);
+
+    let (width, height) = crop.dimensions();
+    let image = crop
+        .pixels()
+        .flat_map(|pixel| rgb.iter().map(move |&c| pixel.data[c]))
+        .collect::<Vec<_>>();
+    let header = HeaderBuilder {
+        width,
+        height,
+        layers: 1,
+        channels: 3,
+        quality: 128,
+        chroma_scale: 1,
+        block_size: BLOCK_DEFAULT,
+        filter: Filter::Linear,
+        encoder: Encoder::Screen,
+        intent: Intent::RGB,
+        metadata_size: 0,
+        quantization: Quantization::Scalar,
+        weighting: Weighting::Flat,
+    }
+    .build()
+    .unwrap();
+    let mut buffer = vec![0u8; image.len() * 4];
+    let size = compress_simple(&image, &header, &program, &mut buffer).unwrap();
+    let data = &buffer[header::HEADER_SIZE..size];
+
+    let mut decoded = vec![0u8; image.len()];
+    decompress_simple(data, &header, 0, false, &mut decoded).unwrap();
+    let squared_error = decoded
+        .iter()
+        .zip(&image)
+        .map(|(&a, &b)| (i32::from(a) - i32::from(b)).pow(2) as u64)
+        .sum::<u64>();
+    assert!(squared_error < image.len() as u64 * 4, "{}", squared_error);
+
+    let mut downsampled = vec![0u8; header.get_downsampled_image_size(1)];
+    decompress_simple(data, &header, 1, false, &mut downsampled).unwrap();
+}